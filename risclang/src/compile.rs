@@ -1,39 +1,323 @@
-use std::{collections::HashMap};
+use std::collections::HashMap;
 
-use crate::{Instruction, parse, def};
+use crate::diag::{self, Diagnostic, Span};
+use crate::def::{self, Operand, Xlen};
+use crate::{parse, EncodeError, Instruction, InstructionFormat};
 
+/// Where the sections of a program are placed in memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Layout {
+	pub text_base: u32,
+	/// Where the data section starts. `None` puts it right after the text, aligned to 16 bytes.
+	pub data_base: Option<u32>,
+}
+
+/// An assembled program, ready to be loaded into memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+	pub text_base: u32,
+	pub text: Vec<Instruction>,
+	pub data_base: u32,
+	pub data: Vec<u8>,
+	/// The address of every label.
+	pub symbols: HashMap<String, u32>,
+}
 
-pub fn compile(mut input: Vec<parse::Inst>, labels: &HashMap<String, u32>) -> Vec<Instruction> {
-	let mut output = Vec::new();
-	process_labels(&mut input, labels);
-	for inst in &input {
-		output.push(gen_code(inst));
+impl Image {
+	pub fn text_bytes(&self) -> Vec<u8> {
+		self.text.iter().flat_map(|inst| inst.0.to_le_bytes()).collect()
 	}
-	output
 }
 
-pub fn process_labels(input: &mut Vec<parse::Inst>, labels: &HashMap<String, u32>) {
-	for i in 0..input.len() {
-		if let Some(ref mut imm) = input[i].imm {
-			if let parse::Imm::Label(ref label) = imm.clone() {
-				let target = *labels.get(label).unwrap();
-				let offset = target as i32 - i as i32;
-				*imm = parse::Imm::Value(offset * 4);
-			}
+pub fn compile(program: parse::Program, layout: &Layout) -> Result<Image, Vec<Diagnostic>> {
+	let parse::Program {
+		mut insts,
+		mut data,
+		fixups,
+		labels,
+		xlen,
+		..
+	} = program;
+	let text_base = layout.text_base;
+	// both sections have to end by the top of the 32 bit address space
+	let top = 1u64 << 32;
+	let text_end = text_base as u64 + insts.len() as u64 * 4;
+	if text_end > top {
+		let first = ((top - text_base as u64) / 4) as usize;
+		return Err(vec![Diagnostic::error(insts[first].span, "instruction is placed past the end of the address space")]);
+	}
+	let data_base = layout.data_base.map_or((text_end + 15) & !15, u64::from);
+	if data_base + data.len() as u64 > top {
+		let message = format!("the data section at {data_base:#x} runs past the end of the address space");
+		return Err(vec![Diagnostic::error(Span::default(), message)]);
+	}
+	// an empty data section right at the top wraps around to zero, where nothing can be in it
+	let data_base = data_base as u32;
+	let mut symbols = HashMap::new();
+	let mut equates = Vec::new();
+	for (name, symbol) in labels {
+		let addr = match symbol {
+			// a label at the very end of a section that ends at the top of memory wraps around
+			parse::Symbol::Text(index) => text_base.wrapping_add(index * 4),
+			parse::Symbol::Data(offset) => data_base.wrapping_add(offset),
+			parse::Symbol::Equ(imm, span) => {
+				equates.push((name, imm, span));
+				continue;
+			},
+		};
+		symbols.insert(name, Value::addr(addr, xlen));
+	}
+	let addresses = symbols.iter().map(|(name, value)| (name.clone(), value.val as u32)).collect();
+
+	let mut diags = Vec::new();
+	resolve_equates(equates, &mut symbols, xlen, &mut diags);
+	if let Err(mut errors) = process_labels(&mut insts, &symbols, text_base, xlen) {
+		diags.append(&mut errors);
+	}
+	for fixup in &fixups {
+		let here = data_base + fixup.offset;
+		match resolve_fixup(fixup, &symbols, here, xlen) {
+			Ok(val) => {
+				let offset = fixup.offset as usize;
+				let width = fixup.width as usize;
+				data[offset..offset + width].copy_from_slice(&val.to_le_bytes()[..width]);
+			},
+			Err(diag) => diags.push(diag),
+		}
+	}
+	if !diags.is_empty() {
+		return Err(diags);
+	}
+	let mut text = Vec::new();
+	for inst in &insts {
+		match gen_code(inst, xlen) {
+			Ok(inst) => text.push(inst),
+			Err(diag) => diags.push(diag),
+		}
+	}
+	if diags.is_empty() {
+		Ok(Image {
+			text_base,
+			text,
+			data_base,
+			data,
+			symbols: addresses,
+		})
+	} else {
+		Err(diags)
+	}
+}
+
+/// The value of an expression, and whether it's the address of something rather than a plain number.
+#[derive(Debug, Clone, Copy)]
+struct Value {
+	val: i64,
+	addr: bool,
+}
+
+impl Value {
+	/// An address, which on RV32 wraps around to a negative number in the upper half of memory.
+	fn addr(addr: u32, xlen: Xlen) -> Value {
+		Value {
+			val: xlen.wrap(addr as i64),
+			addr: true,
+		}
+	}
+}
+
+enum EvalError {
+	Undefined(String),
+	Invalid(&'static str),
+}
+
+impl EvalError {
+	fn diagnostic(self, span: Span, symbols: &HashMap<String, Value>) -> Diagnostic {
+		match self {
+			EvalError::Undefined(label) => undefined_label(&label, span, symbols),
+			EvalError::Invalid(message) => Diagnostic::error(span, message),
+		}
+	}
+}
+
+struct Eval<'a> {
+	symbols: &'a HashMap<String, Value>,
+	/// The target of the `%pcrel_hi` operand of each `auipc`, by address.
+	pcrel_hi: HashMap<u32, &'a parse::Imm>,
+	xlen: Xlen,
+}
+
+impl Eval<'_> {
+	/// Evaluate an expression belonging to the instruction or data item at `here`, or to a `.equ`
+	/// if that's `None`.
+	fn eval(&self, imm: &parse::Imm, here: Option<u32>) -> Result<Value, EvalError> {
+		use parse::{BinaryOp, Imm, Reloc};
+		let number = |val| Value { val, addr: false };
+		Ok(match imm {
+			Imm::Value(val) => number(*val),
+			Imm::Label(label) if label == "." => match here {
+				Some(here) => Value::addr(here, self.xlen),
+				None => return Err(EvalError::Invalid("`.` can't be used in `.equ`")),
+			},
+			Imm::Label(label) => *self.symbols.get(label).ok_or_else(|| EvalError::Undefined(label.clone()))?,
+			Imm::Unary(op, operand) => number(op.apply(self.eval(operand, here)?.val, self.xlen)),
+			Imm::Binary(op, lhs, rhs) => {
+				let (lhs, rhs) = (self.eval(lhs, here)?, self.eval(rhs, here)?);
+				let val = op.apply(lhs.val, rhs.val, self.xlen).ok_or(EvalError::Invalid("division by zero"))?;
+				// an address plus or minus a number is still an address, the difference of two is not
+				let addr = match op {
+					BinaryOp::Add => lhs.addr || rhs.addr,
+					BinaryOp::Sub => lhs.addr && !rhs.addr,
+					_ => false,
+				};
+				Value { val, addr }
+			},
+			Imm::Reloc(Reloc::Hi, operand) => number(split_large_imm(self.eval(operand, here)?.val).0),
+			Imm::Reloc(Reloc::Lo, operand) => number(split_large_imm(self.eval(operand, here)?.val).1),
+			Imm::Reloc(Reloc::PcrelHi, target) => {
+				let pc = here.ok_or(EvalError::Invalid("`%pcrel_hi` can't be used in `.equ`"))?;
+				number(split_large_imm(self.eval(target, here)?.val.wrapping_sub(pc as i64)).0)
+			},
+			Imm::Reloc(Reloc::PcrelLo, auipc) => {
+				let pc = self.eval(auipc, here)?.val as u32;
+				let target = self
+					.pcrel_hi
+					.get(&pc)
+					.ok_or(EvalError::Invalid("`%pcrel_lo` must refer to an `auipc` with a `%pcrel_hi` operand"))?;
+				number(split_large_imm(self.eval(target, Some(pc))?.val.wrapping_sub(pc as i64)).1)
+			},
+		})
+	}
+}
+
+/// Evaluate the `.equ` symbols, in whatever order their dependencies allow.
+fn resolve_equates(
+	mut pending: Vec<(String, parse::Imm, Span)>,
+	symbols: &mut HashMap<String, Value>,
+	xlen: Xlen,
+	diags: &mut Vec<Diagnostic>,
+) {
+	loop {
+		let eval = Eval {
+			symbols,
+			pcrel_hi: HashMap::new(),
+			xlen,
+		};
+		let resolved = pending
+			.iter()
+			.enumerate()
+			.filter_map(|(i, (_, imm, _))| Some((i, eval.eval(imm, None).ok()?)))
+			.collect::<Vec<_>>();
+		if resolved.is_empty() {
+			break;
+		}
+		for (i, value) in resolved.into_iter().rev() {
+			let (name, ..) = pending.remove(i);
+			symbols.insert(name, value);
 		}
 	}
+	pending.sort_by_key(|(_, _, span)| (span.line, span.start));
+	let eval = Eval {
+		symbols,
+		pcrel_hi: HashMap::new(),
+		xlen,
+	};
+	for (name, imm, span) in &pending {
+		let Err(error) = eval.eval(imm, None) else {
+			continue;
+		};
+		diags.push(match error {
+			EvalError::Undefined(label) if pending.iter().any(|(name, ..)| *name == label) => {
+				Diagnostic::error(*span, format!("`{name}` can't be evaluated because its definition is circular"))
+			},
+			error => error.diagnostic(*span, symbols),
+		});
+	}
+}
+
+/// Evaluate the operand expressions now that every label has an address. A branch or jump target
+/// that's an address becomes the offset to it from the instruction. Every error is reported.
+fn process_labels(
+	input: &mut [parse::Inst],
+	symbols: &HashMap<String, Value>,
+	text_base: u32,
+	xlen: Xlen,
+) -> Result<(), Vec<Diagnostic>> {
+	let pc = |i: usize| text_base.wrapping_add(i as u32 * 4);
+	let pcrel_hi = input
+		.iter()
+		.enumerate()
+		.filter_map(|(i, inst)| match &inst.imm {
+			Some(parse::Imm::Reloc(parse::Reloc::PcrelHi, target)) if inst.name == "auipc" => Some((pc(i), &**target)),
+			_ => None,
+		})
+		.collect();
+	let eval = Eval { symbols, pcrel_hi, xlen };
+	let mut diags = Vec::new();
+	let mut values = Vec::new();
+	for (i, inst) in input.iter().enumerate() {
+		let Some(imm) = &inst.imm else {
+			continue;
+		};
+		if let parse::Imm::Value(_) = imm {
+			continue;
+		}
+		match eval.eval(imm, Some(pc(i))) {
+			Ok(value) => {
+				let target = def::lookup(&inst.name).is_some_and(|e| e.operands().contains(&Operand::Label));
+				let val = if target && value.addr { xlen.wrap(value.val.wrapping_sub(pc(i) as i64)) } else { value.val };
+				values.push((i, val));
+			},
+			Err(error) => diags.push(error.diagnostic(inst.span, symbols)),
+		}
+	}
+	for (i, val) in values {
+		input[i].imm = Some(parse::Imm::Value(val));
+	}
+	if diags.is_empty() {
+		Ok(())
+	} else {
+		Err(diags)
+	}
+}
+
+fn resolve_fixup(fixup: &parse::DataFixup, symbols: &HashMap<String, Value>, here: u32, xlen: Xlen) -> Result<i64, Diagnostic> {
+	let eval = Eval {
+		symbols,
+		pcrel_hi: HashMap::new(),
+		xlen,
+	};
+	let val = eval.eval(&fixup.imm, Some(here)).map_err(|error| error.diagnostic(fixup.span, symbols))?.val;
+	parse::check_data_range(val, fixup.width, fixup.span)?;
+	Ok(val)
+}
+
+fn undefined_label(label: &str, span: Span, symbols: &HashMap<String, Value>) -> Diagnostic {
+	let mut message = format!("undefined label `{label}` referenced on line {}", span.line);
+	let similar = diag::similar_names(label, symbols.keys().map(|l| &**l));
+	if !similar.is_empty() {
+		let similar = similar.iter().map(|l| format!("`{l}`")).collect::<Vec<_>>();
+		message += &format!("; did you mean {}?", similar.join(" or "));
+	}
+	Diagnostic::error(span, message)
 }
 
-fn gen_code(input: &parse::Inst) -> Instruction {
-	let isetelem = def::ISET_DEFINITION.iter().find(|t| t.3 == input.name).unwrap();
+fn gen_code(input: &parse::Inst, xlen: Xlen) -> Result<Instruction, Diagnostic> {
+	let isetelem = def::lookup(&input.name)
+		.ok_or_else(|| Diagnostic::error(input.span, format!("unknown instruction `{}`", input.name)))?;
 	let mut inst = Instruction(0);
-	inst.set_opcode(isetelem.0);
-	if let Some(funct3) = isetelem.1 {
-		inst.set_funct3(funct3);
+	inst.set_opcode(isetelem.opcode());
+	match isetelem.funct3() {
+		Some(funct3) => inst.set_funct3(funct3),
+		// the rounding mode takes the place of funct3, and is dynamic unless one was given
+		None if isetelem.operands().contains(&Operand::Rm) => inst.set_funct3(input.rm.unwrap_or(def::RM_DYN)),
+		None => {},
 	}
-	if let Some(funct7) = isetelem.2 {
+	if let Some(funct7) = isetelem.funct7() {
 		inst.set_funct7(funct7);
 	}
+	if let Some(funct12) = isetelem.funct12() {
+		inst.0 |= funct12 << 20;
+	}
 	if let Some(rd) = input.rd {
 		inst.set_rd(rd);
 	}
@@ -43,158 +327,368 @@ fn gen_code(input: &parse::Inst) -> Instruction {
 	if let Some(rs2) = input.rs2 {
 		inst.set_rs2(rs2);
 	}
+	if let Some(rs3) = input.rs3 {
+		inst.set_rs3(rs3);
+	}
 	if let Some(ref imm) = input.imm {
-		match imm {
-			parse::Imm::Value(val) => inst.set_imm_by_format(inst.format(), *val),
-			parse::Imm::Label(label) => panic!("unresolved label {label}"),
-		}
+		let val = match imm {
+			parse::Imm::Value(val) => *val,
+			parse::Imm::Label(label) => return Err(Diagnostic::error(input.span, format!("unresolved label `{label}`"))),
+			_ => return Err(Diagnostic::error(input.span, "unresolved expression")),
+		};
+		let format = isetelem.format();
+		let csr = isetelem.operands().contains(&Operand::Csr);
+		// shifts keep their funct7 in the upper bits of the immediate, leaving 5 bits for the amount, or
+		// 6 for the full width shifts of RV64
+		let shift = format == InstructionFormat::I && isetelem.funct7().is_some();
+		let max_shamt = (1 << isetelem.shamt_bits(xlen)) - 1;
+		let result = if shift && !(0..=max_shamt).contains(&val) {
+			Err(EncodeError::OutOfRange { imm: val, min: 0, max: max_shamt as i32 })
+		} else if csr && !(0..4096).contains(&val) {
+			Err(EncodeError::OutOfRange { imm: val, min: 0, max: 4095 })
+		} else {
+			match i32::try_from(val) {
+				// CSR numbers are unsigned, so the upper half of them looks negative to the I format
+				Ok(imm) if csr => inst.set_imm_by_format(format, (imm << 20) >> 20),
+				Ok(imm) => inst.set_imm_by_format(format, imm),
+				// no format has room for more than 32 bits
+				Err(_) => match format.imm_range() {
+					Some((min, max, _)) => Err(EncodeError::OutOfRange { imm: val, min, max }),
+					None => Err(EncodeError::NoImmediate(format)),
+				},
+			}
+		};
+		result.map_err(|e| Diagnostic::error(input.span, format!("cannot encode `{}`: {e}", input.name)))?;
 	}
-	inst
-}
-
-pub fn expand_pseudo(inst: &parse::Inst) -> Vec<parse::Inst> {
-	match &*inst.name {
-		"beqz" => vec![parse::Inst {
-			name: "beq".to_owned(),
-			rs1: inst.rs1,
-			rs2: Some(0),
-			rd: None,
-			imm: inst.imm.clone(),
-		}],
-		"bnez" => vec![parse::Inst {
-			name: "bne".to_owned(),
-			rs1: inst.rs1,
-			rs2: Some(0),
-			rd: None,
-			imm: inst.imm.clone(),
-		}],
-		"j" => vec![parse::Inst {
-			name: "jal".to_owned(),
-			rs1: None,
-			rs2: None,
-			rd: inst.rd,
-			imm: inst.imm.clone(),
-		}],
-		"jr" => vec![parse::Inst {
-			name: "jalr".to_owned(),
-			rs1: inst.rs1,
-			rs2: None,
-			rd: Some(0),
-			imm: Some(parse::Imm::Value(0)),
-		}],
-		"la" => {
-			let val = if let Some(ref imm) = inst.imm {
-				if let parse::Imm::Value(val) = *imm {
-					val
-				} else {
-					panic!("unresolved label");
-				}
-			} else {
-				panic!("la label not specified");
+	Ok(inst)
+}
+
+/// Rewrite conditional branches whose label is too far away to encode into an inverted branch over
+/// an unconditional jump, which has a much larger range:
+///
+/// ```text
+/// beq a0, a1, far    =>    bne a0, a1, 8
+///                          jal x0, far
+/// ```
+///
+/// Inserting a jump moves every later label, which can push other branches out of range, so this
+/// repeats until nothing changes. The padding of every `.align` after the jump is redone too.
+pub fn relax_branches(program: &mut parse::Program) {
+	let (min, max, _) = InstructionFormat::B.imm_range().unwrap();
+	let mut symbols = text_symbols(program);
+	let mut changed = true;
+	while changed {
+		changed = false;
+		let mut i = 0;
+		while i < program.insts.len() {
+			// the target is found the way the encoder finds it, so expressions and `.equ` aliases count
+			let here = i as u32 * 4;
+			let eval = Eval {
+				symbols: &symbols,
+				pcrel_hi: HashMap::new(),
+				xlen: program.xlen,
 			};
-			let (h, l) = split_large_imm(val);
-			vec![
-				parse::Inst {
-					name: "auipc".to_owned(),
-					rs1: None,
-					rs2: None,
-					rd: inst.rd,
-					imm: Some(parse::Imm::Value(h)),
-				},
-				parse::Inst {
-					name: "addi".to_owned(),
-					rs1: inst.rd,
-					rs2: None,
-					rd: inst.rd,
-					imm: Some(parse::Imm::Value(l)),
+			let target = program.insts[i].imm.as_ref().and_then(|imm| eval.eval(imm, Some(here)).ok()).filter(|value| value.addr);
+			let inverse = inverse_branch(&program.insts[i].name);
+			if let (Some(target), Some(inverse)) = (target, inverse) {
+				let offset = program.xlen.wrap(target.val.wrapping_sub(here as i64));
+				if offset < min as i64 || offset > max as i64 {
+					let jump = parse::Inst {
+						name: "jal".to_owned(),
+						rd: Some(0),
+						rs1: None,
+						rs2: None,
+						rs3: None,
+						rm: None,
+						imm: program.insts[i].imm.take().map(moved_forward),
+						span: program.insts[i].span,
+					};
+					program.insts[i].name = inverse.to_owned();
+					program.insts[i].imm = Some(parse::Imm::Value(8));
+					let text = program.texts[i].clone();
+					insert_insts(program, i + 1, vec![(jump, text)], |_| false, 0);
+					repad(program);
+					symbols = text_symbols(program);
+					changed = true;
 				}
-			]
+			}
+			i += 1;
+		}
+	}
+}
+
+/// The text labels, with the text at address 0, and the `.equ` symbols that can be evaluated from
+/// them. That's enough to measure a branch before the data section is placed.
+fn text_symbols(program: &parse::Program) -> HashMap<String, Value> {
+	let mut symbols = HashMap::new();
+	let mut equates = Vec::new();
+	for (name, symbol) in &program.labels {
+		match symbol {
+			parse::Symbol::Text(index) => {
+				symbols.insert(name.clone(), Value::addr(index * 4, program.xlen));
+			},
+			parse::Symbol::Equ(imm, span) => equates.push((name.clone(), imm.clone(), *span)),
+			parse::Symbol::Data(_) => {},
+		}
+	}
+	// equates that need a data address stay undefined here, and the encoder reports any errors
+	resolve_equates(equates, &mut symbols, program.xlen, &mut Vec::new());
+	symbols
+}
+
+/// `imm` for the instruction after the one it was written for, so that `.` still means the same.
+fn moved_forward(imm: parse::Imm) -> parse::Imm {
+	use parse::Imm;
+	match imm {
+		Imm::Label(label) if label == "." => {
+			Imm::Binary(parse::BinaryOp::Sub, Box::new(Imm::Label(label)), Box::new(Imm::Value(4)))
 		},
-		"li" => {
-			let val = if let Some(ref imm) = inst.imm {
-				if let parse::Imm::Value(val) = *imm {
-					val
-				} else {
-					panic!("unresolved label");
+		Imm::Unary(op, operand) => Imm::Unary(op, Box::new(moved_forward(*operand))),
+		Imm::Binary(op, lhs, rhs) => Imm::Binary(op, Box::new(moved_forward(*lhs)), Box::new(moved_forward(*rhs))),
+		Imm::Reloc(reloc, operand) => Imm::Reloc(reloc, Box::new(moved_forward(*operand))),
+		imm => imm,
+	}
+}
+
+/// Insert `insts` with their source lines at `index`, moving the labels after them and the `.align`
+/// padding from `first_align` on. `moves` says whether a label pointing right at `index` moves too.
+fn insert_insts(
+	program: &mut parse::Program,
+	index: usize,
+	insts: Vec<(parse::Inst, String)>,
+	moves: impl Fn(&str) -> bool,
+	first_align: usize,
+) {
+	let count = insts.len() as u32;
+	let (insts, texts): (Vec<_>, Vec<_>) = insts.into_iter().unzip();
+	program.insts.splice(index..index, insts);
+	program.texts.splice(index..index, texts);
+	let index = index as u32;
+	for (name, symbol) in &mut program.labels {
+		match symbol {
+			parse::Symbol::Text(i) if *i > index || (*i == index && moves(name)) => *i += count,
+			_ => {},
+		}
+	}
+	for align in &mut program.aligns[first_align..] {
+		if align.index >= index {
+			align.index += count;
+		}
+	}
+}
+
+/// Change the number of nops at each `.align` to what its alignment needs now. Labels after the
+/// padding move with it, the ones before stay where they are.
+fn repad(program: &mut parse::Program) {
+	for i in 0..program.aligns.len() {
+		let parse::Align { index, nops, align, .. } = program.aligns[i];
+		let needed = (align - index * 4 % align) % align / 4;
+		let start = index as usize;
+		if needed > nops {
+			let align = &program.aligns[i];
+			let padding = vec![(parse::Inst::nop(align.span), align.text.clone()); (needed - nops) as usize];
+			let labels = align.labels.clone();
+			insert_insts(program, start, padding, |name| labels.iter().any(|label| label == name), i + 1);
+		} else if needed < nops {
+			let removed = nops - needed;
+			program.insts.drain(start..start + removed as usize);
+			program.texts.drain(start..start + removed as usize);
+			for symbol in program.labels.values_mut() {
+				match symbol {
+					parse::Symbol::Text(i) if *i > index => *i -= removed,
+					_ => {},
 				}
-			} else {
-				panic!("li label not specified");
-			};
-			if val >= -2048 && val < 2048 {
-				vec![parse::Inst {
-					name: "addi".to_owned(),
-					rs1: Some(0),
-					rs2: None,
-					rd: inst.rd,
-					imm: inst.imm.clone(),
-				}]
-			} else {
-				let (h, l) = split_large_imm(val);
-				vec![
-					parse::Inst {
-						name: "lui".to_owned(),
-						rs1: None,
-						rs2: None,
-						rd: inst.rd,
-						imm: Some(parse::Imm::Value(h)),
-					},
-					parse::Inst {
-						name: "addi".to_owned(),
-						rs1: inst.rd,
-						rs2: None,
-						rd: inst.rd,
-						imm: Some(parse::Imm::Value(l)),
-					},
-				]
 			}
+			for align in &mut program.aligns[i + 1..] {
+				align.index -= removed;
+			}
+		}
+		program.aligns[i].nops = needed;
+	}
+}
+
+fn inverse_branch(name: &str) -> Option<&'static str> {
+	Some(match name {
+		"beq" => "bne",
+		"bne" => "beq",
+		"blt" => "bge",
+		"bge" => "blt",
+		"bltu" => "bgeu",
+		"bgeu" => "bltu",
+		_ => return None,
+	})
+}
+
+/// Expand a pseudo instruction into the real instructions that implement it. Real instructions are
+/// returned unchanged.
+pub fn expand_pseudo(inst: &parse::Inst, xlen: Xlen) -> Result<Vec<parse::Inst>, Diagnostic> {
+	// a pseudo instruction sharing its name with a real one is told apart by which operands it has
+	let pseudo = def::PSEUDO_INSTS.iter().find(|p| {
+		let takes = |kinds: &[Operand]| p.operands().iter().any(|o| kinds.contains(o));
+		p.name() == inst.name
+			&& takes(&[Operand::Rd, Operand::Frd]) == inst.rd.is_some()
+			&& takes(&[Operand::Rs1, Operand::Frs1, Operand::Uimm]) == inst.rs1.is_some()
+			&& takes(&[Operand::Rs2, Operand::Frs2]) == inst.rs2.is_some()
+			&& takes(&[Operand::Imm, Operand::Label, Operand::Csr]) == inst.imm.is_some()
+	});
+	let Some(pseudo) = pseudo else {
+		return Ok(vec![inst.clone()]);
+	};
+	let insts = match pseudo.name() {
+		"la" => match inst.imm {
+			// an address that's already known is loaded the same way as any other number
+			Some(parse::Imm::Value(_)) => load_immediate(inst, xlen),
+			Some(ref target) => pcrel_pair(inst.rd, "addi", inst.rd, target, inst.span),
+			None => return Err(Diagnostic::error(inst.span, "invalid operand to `la`")),
 		},
-		"mv" => vec![parse::Inst {
-			name: "addi".to_owned(),
-			rs1: inst.rs1,
+		"li" => load_immediate(inst, xlen),
+		// go through a register so that the target can be anywhere, `tail` uses t1 to keep ra intact
+		"call" => pcrel_pair(Some(1), "jalr", Some(1), inst.imm.as_ref().unwrap(), inst.span),
+		"tail" => pcrel_pair(Some(6), "jalr", Some(0), inst.imm.as_ref().unwrap(), inst.span),
+		_ => pseudo
+			.expansion()
+			.iter()
+			.map(|&(name, args)| {
+				let reg = |arg| match arg {
+					def::Arg::Operand(i) => match pseudo.operands()[i] {
+						Operand::Rd | Operand::Frd => inst.rd,
+						Operand::Rs2 | Operand::Frs2 => inst.rs2,
+						Operand::Frs3 => inst.rs3,
+						_ => inst.rs1,
+					},
+					def::Arg::Reg(reg) => Some(reg),
+					def::Arg::Value(_) | def::Arg::XlenMinus(_) => None,
+				};
+				let imm = |arg| match arg {
+					def::Arg::Value(val) => Some(parse::Imm::Value(val.into())),
+					def::Arg::XlenMinus(bits) => Some(parse::Imm::Value((xlen.bits() - bits).into())),
+					_ => inst.imm.clone(),
+				};
+				let mut expanded = parse::Inst {
+					name: name.to_owned(),
+					rd: None,
+					rs1: None,
+					rs2: None,
+					rs3: None,
+					rm: None,
+					imm: None,
+					span: inst.span,
+				};
+				let operands = def::lookup(name).map_or(&[][..], |e| e.operands());
+				for (&operand, &arg) in operands.iter().zip(args) {
+					match operand {
+						Operand::Rd | Operand::Frd => expanded.rd = reg(arg),
+						Operand::Rs1 | Operand::Frs1 | Operand::Uimm => expanded.rs1 = reg(arg),
+						Operand::Rs2 | Operand::Frs2 => expanded.rs2 = reg(arg),
+						Operand::Frs3 => expanded.rs3 = reg(arg),
+						Operand::Rm => expanded.rm = inst.rm,
+						Operand::Imm | Operand::Shamt | Operand::Label | Operand::Mem | Operand::Csr => expanded.imm = imm(arg),
+					}
+				}
+				expanded
+			})
+			.collect(),
+	};
+	Ok(insts)
+}
+
+/// An `auipc` of the upper part of the offset to `target` into `base`, then `name rd, base, lo` with
+/// the lower part.
+fn pcrel_pair(base: Option<u32>, name: &str, rd: Option<u32>, target: &parse::Imm, span: Span) -> Vec<parse::Inst> {
+	// the low half is relative to the auipc, one instruction back
+	let auipc = parse::Imm::Binary(
+		parse::BinaryOp::Sub,
+		Box::new(parse::Imm::Label(".".to_owned())),
+		Box::new(parse::Imm::Value(4)),
+	);
+	vec![
+		parse::Inst {
+			name: "auipc".to_owned(),
+			rs1: None,
 			rs2: None,
-			rd: inst.rd,
-			imm: Some(parse::Imm::Value(0)),
-		}],
-		"neg" => vec![parse::Inst {
-			name: "sub".to_owned(),
-			rs1: Some(0),
-			rs2: inst.rs1,
-			rd: inst.rd,
-			imm: None,
-		}],
-		"nop" => vec![parse::Inst {
-			name: "addi".to_owned(),
-			rs1: Some(0),
+			rs3: None,
+			rm: None,
+			rd: base,
+			imm: Some(parse::Imm::Reloc(parse::Reloc::PcrelHi, Box::new(target.clone()))),
+			span,
+		},
+		parse::Inst {
+			name: name.to_owned(),
+			rs1: base,
 			rs2: None,
-			rd: Some(0),
-			imm: Some(parse::Imm::Value(0)),
-		}],
-		"not" => vec![parse::Inst {
-			name: "xori".to_owned(),
-			rs1: inst.rs1,
+			rs3: None,
+			rm: None,
+			rd,
+			imm: Some(parse::Imm::Reloc(parse::Reloc::PcrelLo, Box::new(auipc))),
+			span,
+		},
+	]
+}
+
+/// The shortest sequence that loads the immediate of `li rd, imm` into `rd`, see [`li_sequence`]. The
+/// value of an expression that depends on a label isn't known yet, so that always takes a `lui` and
+/// an `addi`.
+fn load_immediate(inst: &parse::Inst, xlen: Xlen) -> Vec<parse::Inst> {
+	let steps = match inst.imm {
+		Some(parse::Imm::Value(val)) => li_sequence(val, xlen).into_iter().map(|(name, val)| (name, parse::Imm::Value(val))).collect(),
+		ref imm => {
+			let imm = Box::new(imm.clone().unwrap_or(parse::Imm::Value(0)));
+			vec![("lui", parse::Imm::Reloc(parse::Reloc::Hi, imm.clone())), ("addi", parse::Imm::Reloc(parse::Reloc::Lo, imm))]
+		},
+	};
+	steps
+		.into_iter()
+		.enumerate()
+		.map(|(i, (name, imm))| parse::Inst {
+			name: name.to_owned(),
+			// the first instruction starts from zero and the rest build on rd
+			rs1: match name {
+				"lui" => None,
+				_ if i == 0 => Some(0),
+				_ => inst.rd,
+			},
 			rs2: None,
+			rs3: None,
+			rm: None,
 			rd: inst.rd,
-			imm: Some(parse::Imm::Value(-1)),
-		}],
-		"ret" => vec![parse::Inst {
-			name: "jalr".to_owned(),
-			rs1: Some(1),
-			rs2: None,
-			rd: Some(0),
-			imm: Some(parse::Imm::Value(0)),
-		}],
-		_ => vec![inst.clone()],
-	}
+			imm: Some(imm),
+			span: inst.span,
+		})
+		.collect()
 }
 
-fn split_large_imm(val: i32) -> (i32, i32) {
-	let l = val & 0b111111111111;
-	let h = (val & !0b111111111111) >> 12;
-	if l & 0b100000000000 == 0 {
-		(h, l)
-	} else {
-		(h - 0b1111_11111111_11111111, l)
+/// The instructions and immediates that load `val`: an `addi` for 12 bit numbers, a `lui` when the
+/// lower 12 bits are zero and both otherwise. On RV64 the `addi` becomes an `addiw` so that the sum
+/// wraps at 32 bits like it does on RV32, and a number wider than 32 bits is built the way LLVM does
+/// it: its upper bits are loaded on their own, shifted into place with `slli`, and the lower 12 bits
+/// are added with `addi`.
+fn li_sequence(val: i64, xlen: Xlen) -> Vec<(&'static str, i64)> {
+	if i32::try_from(val).is_ok() {
+		let add = if xlen == Xlen::Rv64 { "addiw" } else { "addi" };
+		return match split_large_imm(val) {
+			(0, l) => vec![("addi", l)],
+			(h, 0) => vec![("lui", h)],
+			(h, l) => vec![("lui", h), (add, l)],
+		};
+	}
+	let l = (val << 52) >> 52;
+	let upper = val.wrapping_sub(l);
+	let shamt = upper.trailing_zeros();
+	let mut seq = li_sequence(upper >> shamt, xlen);
+	seq.push(("slli", shamt as i64));
+	if l != 0 {
+		seq.push(("addi", l));
 	}
+	seq
+}
+
+/// Split the lower 32 bits of a value into the upper 20 bits for `lui`/`auipc` and the lower 12 bits
+/// for `addi`, accounting for `addi` sign extending its immediate.
+pub(crate) fn split_large_imm(val: i64) -> (i64, i64) {
+	let val = val as i32;
+	let l = (val << 20) >> 20;
+	let h = val.wrapping_sub(l) >> 12;
+	(h as i64, l as i64)
 }
 
 #[test]
@@ -212,15 +706,428 @@ fn test_imm_split() {
 		0b1111111111111,
 		-1,
 		-2,
-		unsafe { std::mem::transmute::<u32, i32>(0b11111111_11111111_11110000_00000000) },
-		unsafe { std::mem::transmute::<u32, i32>(0b11111111_11111111_11110000_00000001) },
-		unsafe { std::mem::transmute::<u32, i32>(0b11111111_11111111_11111000_00000000) },
-		unsafe { std::mem::transmute::<u32, i32>(0b11111111_11111111_11111000_00000001) },
+		0b11111111_11111111_11110000_00000000u32 as i32,
+		0b11111111_11111111_11110000_00000001u32 as i32,
+		0b11111111_11111111_11111000_00000000u32 as i32,
+		0b11111111_11111111_11111000_00000001u32 as i32,
 	];
 	for &case in cases {
-		let (h, l) = split_large_imm(case);
+		let (h, l) = split_large_imm(case.into());
+		let (h, l) = (h as i32, l as i32);
 		println!("{:0b}, {:0b}", h, l);
 		let r = (h << 12).checked_add((l << 20) >> 20).unwrap();
 		assert_eq!(case, r);
 	}
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_undefined_label() {
+	let source = "
+loop:
+	addi t0 t0 1
+	blt t0 t1 lop
+	j end
+	";
+	let program = parse::parse(source).unwrap();
+	let diags = compile(program, &Layout::default()).unwrap_err();
+	assert_eq!(diags.len(), 2);
+	assert_eq!(diags[0].span.line, 4);
+	assert_eq!(diags[0].message, "undefined label `lop` referenced on line 4; did you mean `loop`?");
+	assert_eq!(diags[1].message, "undefined label `end` referenced on line 5");
+}
+
+#[test]
+fn test_imm_out_of_range() {
+	let source = "
+	addi x1 x0 5000
+	beq x0 x0 3
+	slli x1 x1 32
+	";
+	let program = parse::parse(source).unwrap();
+	let diags = program
+		.insts
+		.into_iter()
+		.map(|inst| {
+			let program = parse::Program {
+				insts: vec![inst],
+				..Default::default()
+			};
+			compile(program, &Layout::default()).unwrap_err().remove(0)
+		})
+		.collect::<Vec<_>>();
+	assert_eq!(diags[0].message, "cannot encode `addi`: immediate 5000 is out of range, it must be between -2048 and 2047");
+	assert_eq!(diags[1].message, "cannot encode `beq`: immediate 3 is not a multiple of 2");
+	assert_eq!(diags[2].message, "cannot encode `slli`: immediate 32 is out of range, it must be between 0 and 31");
+}
+
+#[test]
+fn test_relax_branches() {
+	let mut source = String::from("start:\nbeq a0 a1 end\nblt a0 a1 start\n");
+	for _ in 0..1024 {
+		source += "nop\n";
+	}
+	source += "end:\nbltu a0 a1 start\n";
+	let program = parse::parse(&source).unwrap();
+	assert_eq!(program.insts.len(), program.texts.len());
+	assert_eq!(program.labels["end"], parse::Symbol::Text(1027));
+	let code = compile(program, &Layout::default()).unwrap().text;
+	// the forward branch is 4104 bytes away and gets relaxed
+	assert_eq!(code[0].opcode(), 0b1100011);
+	assert_eq!(code[0].funct3(), 0b001);
+	assert_eq!(code[0].imm(), Some(8));
+	assert_eq!(code[1].opcode(), 0b1101111);
+	assert_eq!(code[1].imm(), Some(4 * 1026));
+	// the short backward branch stays as it is
+	assert_eq!(code[2].funct3(), 0b100);
+	assert_eq!(code[2].imm(), Some(-8));
+	// the last branch ends up 4108 bytes back once the first jump is inserted
+	assert_eq!(code[1027].funct3(), 0b111);
+	assert_eq!(code[1027].imm(), Some(8));
+	assert_eq!(code[1028].imm(), Some(-4 * 1028));
+}
+
+#[test]
+fn test_relax_branches_expressions() {
+	// targets written as expressions or through `.equ` are measured like plain labels
+	let mut source = String::from(".equ alias, end\nbeq a0, a1, end + 4\nbne a0, a1, alias\nbeq a0, a1, . + 4200\n");
+	for _ in 0..1024 {
+		source += "nop\n";
+	}
+	source += "end: nop\nnop\n";
+	let code = compile(parse::parse(&source).unwrap(), &Layout::default()).unwrap().text;
+	let jumps = [1, 3, 5].map(|i| (code[i].opcode(), code[i].imm()));
+	let end = 4 * 1030;
+	assert_eq!(jumps, [(0b1101111, Some(end + 4 - 4)), (0b1101111, Some(end - 12)), (0b1101111, Some(4200 - 4))]);
+	assert_eq!([0, 2, 4].map(|i| (code[i].funct3(), code[i].imm())), [(0b001, Some(8)), (0b000, Some(8)), (0b001, Some(8))]);
+}
+
+#[test]
+fn test_relax_branches_align() {
+	// the jump inserted for the far branch has to come out of the padding before `vectors`
+	let mut source = String::from("beq a0, a1, end
+nop
+before:
+.align 6
+vectors:
+j end
+");
+	for _ in 0..1024 {
+		source += "nop
+";
+	}
+	source += "end:
+ret
+.align 4
+last: nop
+";
+	let program = parse::parse(&source).unwrap();
+	assert_eq!(program.insts.len(), program.texts.len());
+	let image = compile(program, &Layout::default()).unwrap();
+	assert_eq!(image.text[1].opcode(), 0b1101111);
+	assert_eq!(image.symbols["before"], 12);
+	assert_eq!(image.symbols["vectors"], 64);
+	assert_eq!(image.symbols["last"] % 16, 0);
+	let end = image.symbols["end"];
+	assert_eq!(image.text[1].imm(), Some(end as i32 - 4));
+	assert_eq!(image.text[16].imm(), Some(end as i32 - 64));
+
+	// and a jump that fills the padding exactly leaves none
+	let mut source = String::from("beq a0, a1, end
+nop
+nop
+.align 4
+vectors: nop
+");
+	for _ in 0..1024 {
+		source += "nop
+";
+	}
+	source += "end:
+ret
+";
+	let image = compile(parse::parse(&source).unwrap(), &Layout::default()).unwrap();
+	assert_eq!(image.symbols["vectors"], 16);
+	assert_eq!(image.text.len(), 4 + 1025 + 1);
+}
+
+#[test]
+fn test_data_labels() {
+	let source = "
+.data
+msg: .asciiz \"hi\"
+.align 2
+table: .word msg, end
+.text
+	la a0, table
+	lw a1, 4(a0)
+end:
+	ecall
+	";
+	let program = parse::parse(source).unwrap();
+	let image = compile(program, &Layout::default()).unwrap();
+	assert_eq!(image.data_base, 16);
+	assert_eq!(image.symbols["msg"], 16);
+	assert_eq!(image.symbols["table"], 20);
+	assert_eq!(image.symbols["end"], 12);
+	assert_eq!(&image.data[4..], &[16, 0, 0, 0, 12, 0, 0, 0]);
+	// `la` becomes auipc + addi relative to the auipc
+	assert_eq!(image.text[0].imm(), Some(0));
+	assert_eq!(image.text[1].imm(), Some(20));
+
+	let layout = Layout {
+		text_base: 0x1000,
+		data_base: Some(0x10000),
+	};
+	let image = compile(parse::parse(source).unwrap(), &layout).unwrap();
+	assert_eq!(&image.data[4..], &[0x00, 0, 1, 0, 0x0c, 0x10, 0, 0]);
+	assert_eq!(image.text[0].imm(), Some(0xf000));
+	assert_eq!(image.text[1].imm(), Some(4));
+}
+
+#[test]
+fn test_expressions() {
+	let source = "
+	.equ SIZE, 8
+	.equ BUF_END, buf + SIZE * 4
+start:
+	lui a0, %hi(buf)
+	addi a0, a0, %lo(buf)
+	lw a1, %lo(buf + 4)(a0)
+here:
+	auipc a2, %pcrel_hi(BUF_END)
+	addi a2, a2, %pcrel_lo(here)
+	beq a0, a1, start + 4
+	bne a0, a1, 8
+	addi a3, zero, here - start
+.data
+	.word 1, 2
+buf:
+	.word BUF_END - buf, ., start
+	.half %lo(0x1800)
+	";
+	let layout = Layout {
+		text_base: 0x1000,
+		data_base: Some(0x123400),
+	};
+	let image = compile(parse::parse(source).unwrap(), &layout).unwrap();
+	let buf = 0x123408;
+	assert_eq!(image.symbols["buf"], buf);
+	assert!(!image.symbols.contains_key("SIZE"));
+	assert_eq!(image.text[0].imm(), Some(0x123000));
+	assert_eq!(image.text[1].imm(), Some(0x408));
+	assert_eq!(image.text[2].imm(), Some(0x40c));
+	// BUF_END is 0x123428, 0x122418 bytes after the auipc at 0x100c
+	assert_eq!(image.text[3].imm(), Some(0x122000));
+	assert_eq!(image.text[4].imm(), Some(0x41c));
+	// a label makes a branch target an address, a plain number is an offset
+	assert_eq!(image.text[5].imm(), Some(-16));
+	assert_eq!(image.text[6].imm(), Some(8));
+	assert_eq!(image.text[7].imm(), Some(12));
+	let word = |i: usize| u32::from_le_bytes(image.data[i..i + 4].try_into().unwrap());
+	assert_eq!(word(8), 32);
+	assert_eq!(word(12), buf + 4);
+	assert_eq!(word(16), 0x1000);
+	assert_eq!(&image.data[20..], &[0x00, 0xf8]);
+}
+
+#[test]
+fn test_expression_errors() {
+	let source = "
+	.equ A, B + 1
+	.equ B, A * 2
+	.equ C, .
+	addi a0, a0, %pcrel_lo(start)
+start:
+	addi a0, a0, undefined + 1
+	addi a0, a0, 1 / (start - start)
+	";
+	let diags = compile(parse::parse(source).unwrap(), &Layout::default()).unwrap_err();
+	let messages = diags.iter().map(|d| (d.span.line, &*d.message)).collect::<Vec<_>>();
+	assert_eq!(
+		messages,
+		&[
+			(2, "`A` can't be evaluated because its definition is circular"),
+			(3, "`B` can't be evaluated because its definition is circular"),
+			(4, "`.` can't be used in `.equ`"),
+			(5, "`%pcrel_lo` must refer to an `auipc` with a `%pcrel_hi` operand"),
+			(7, "undefined label `undefined` referenced on line 7"),
+			(8, "division by zero"),
+		]
+	);
+}
+
+#[test]
+fn test_load_address() {
+	let source = "
+	li a0, 100
+	li a1, 0x12345000
+	li a2, 0x12345678
+	li a3, buf + 4
+	la a4, 0x10000
+	la a5, buf
+.data
+	.space 0x800
+buf:
+	";
+	let program = parse::parse(source).unwrap();
+	let names = program.insts.iter().map(|inst| &*inst.name).collect::<Vec<_>>();
+	assert_eq!(names, &["addi", "lui", "lui", "addi", "lui", "addi", "lui", "auipc", "addi"]);
+	let layout = Layout {
+		text_base: 0x400,
+		data_base: Some(0x2000),
+	};
+	let image = compile(program, &layout).unwrap();
+	let imms = image.text.iter().map(|inst| inst.imm()).collect::<Vec<_>>();
+	assert_eq!(
+		imms,
+		[100, 0x12345000, 0x12345000, 0x678, 0x3000, -0x7fc, 0x10000, 0x2000, 0x3e4].map(Some)
+	);
+}
+
+#[test]
+fn test_pseudo_insts() {
+	let source = "
+	bgt a0, a1, end
+	bleu a0, a1, end
+	blez a0, end
+	seqz t0, t1
+	sgtz t0, t1
+	sext.b t0, t1
+	jalr t2
+	jalr t2, t3, 4
+	call end
+	tail end
+end:
+	";
+	let program = parse::parse(source).unwrap();
+	let text = program
+		.insts
+		.iter()
+		.map(|inst| (&*inst.name, inst.rd, inst.rs1, inst.rs2))
+		.collect::<Vec<_>>();
+	assert_eq!(
+		text,
+		&[
+			("blt", None, Some(11), Some(10)),
+			("bgeu", None, Some(11), Some(10)),
+			("bge", None, Some(0), Some(10)),
+			("sltiu", Some(5), Some(6), None),
+			("slt", Some(5), Some(0), Some(6)),
+			("slli", Some(5), Some(6), None),
+			("srai", Some(5), Some(5), None),
+			("jalr", Some(1), Some(7), None),
+			("jalr", Some(7), Some(28), None),
+			("auipc", Some(1), None, None),
+			("jalr", Some(1), Some(1), None),
+			("auipc", Some(6), None, None),
+			("jalr", Some(0), Some(6), None),
+		]
+	);
+	let image = compile(program, &Layout::default()).unwrap();
+	assert_eq!(image.text[0].imm(), Some(52));
+	assert_eq!(image.text[9].imm(), Some(0));
+	assert_eq!(image.text[10].imm(), Some(16));
+	assert_eq!(image.text[12].imm(), Some(8));
+}
+
+#[test]
+fn test_extend_pseudo_insts() {
+	// the value is shifted to the top of the register and back, however wide that is
+	for (xlen, shifts) in [(Xlen::Rv32, [24, 24, 16, 16, 16, 16]), (Xlen::Rv64, [56, 56, 48, 48, 48, 48])] {
+		let program = parse::parse_xlen("sext.b t0, t1\nsext.h t0, t1\nzext.h t0, t1", xlen).unwrap();
+		let imms = program.insts.iter().map(|inst| inst.imm.clone()).collect::<Vec<_>>();
+		assert_eq!(imms, shifts.map(|shift| Some(parse::Imm::Value(shift))));
+		assert!(compile(program, &Layout::default()).is_ok());
+	}
+}
+
+#[test]
+fn test_rv64() {
+	assert_eq!(li_sequence(0x7ffff800, Xlen::Rv64), &[("lui", -0x80000), ("addiw", -0x800)]);
+	assert_eq!(li_sequence(0x7ffff800, Xlen::Rv32), &[("lui", -0x80000), ("addi", -0x800)]);
+	assert_eq!(li_sequence(1 << 40, Xlen::Rv64), &[("addi", 1), ("slli", 40)]);
+	assert_eq!(
+		li_sequence(0x1234_5678_9abc_def0, Xlen::Rv64),
+		&[
+			("lui", 0x247),
+			("addiw", -0x753),
+			("slli", 14),
+			("addi", -0x3b3),
+			("slli", 12),
+			("addi", 0x5e7),
+			("slli", 13),
+			("addi", -0x110),
+		]
+	);
+
+	let source = "
+	.data
+	big: .dword 0x123456789abcdef0
+	.text
+	li a0, 0x123456789abcdef0
+	ld a1, 0(a0)
+	sext.w a0, a1
+	slli a0, a0, 63
+	";
+	let program = parse::parse_xlen(source, Xlen::Rv64).unwrap();
+	assert_eq!(program.insts.len(), 11);
+	let image = compile(program, &Layout::default()).unwrap();
+	assert_eq!(image.data, 0x123456789abcdef0u64.to_le_bytes());
+	assert_eq!(image.text[10].imm(), Some(63));
+
+	let diags = parse::parse("ld a1, 0(a0)\nsext.w a0, a1\nli a0, 0x100000000").unwrap_err();
+	assert_eq!(diags.len(), 3);
+	assert_eq!(diags[0].message, "`ld` is only available on RV64");
+	assert_eq!(diags[1].message, "`sext.w` is only available on RV64");
+	assert_eq!(diags[2].message, "literal `0x100000000` does not fit in 32 bits");
+}
+
+#[test]
+fn test_float() {
+	let source = "
+	.data
+	.float 1.5
+	.double -2.0
+	.text
+	flw fa0, 4(sp)
+	fsw fa0, 8(sp)
+	fadd.s fa0, fa1, fa2
+	fmadd.d fa0, fa1, fa2, fa3, rne
+	fcvt.w.s a0, fa0, rtz
+	fmv.s fa1, fa0
+	fgt.s a0, fa0, fa1
+	frrm a0
+	";
+	let image = compile(parse::parse(source).unwrap(), &Layout::default()).unwrap();
+	let mut data = 1.5f32.to_le_bytes().to_vec();
+	data.extend_from_slice(&(-2.0f64).to_le_bytes());
+	assert_eq!(image.data, data);
+	let words = image.text.iter().map(|inst| inst.0).collect::<Vec<_>>();
+	assert_eq!(
+		words,
+		&[0x00412507, 0x00a12427, 0x00c5f553, 0x6ac58543, 0xc0051553, 0x20a505d3, 0xa0a59553, 0x00202573]
+	);
+
+	let diags = parse::parse("fadd.s fa0, a1, fa2\nfadd.s fa0, fa1, fa2, up\nfcvt.l.s a0, fa0\n.data\n.float x").unwrap_err();
+	assert_eq!(diags.len(), 4);
+	assert_eq!(diags[0].message, "invalid floating-point register `a1`");
+	assert_eq!(diags[1].message, "invalid rounding mode `up`, expected rne, rtz, rdn, rup, rmm or dyn");
+	assert_eq!(diags[2].message, "`fcvt.l.s` is only available on RV64");
+	assert_eq!(diags[3].message, "invalid floating-point number `x`");
+}
+
+#[test]
+fn test_layout_at_top() {
+	let layout = |text_base, data_base| Layout { text_base, data_base };
+	let image = compile(parse::parse("nop\nend: nop").unwrap(), &layout(0xffff_fff8, None)).unwrap();
+	assert_eq!(image.symbols["end"], 0xffff_fffc);
+	assert_eq!(image.data_base, 0);
+
+	let diags = compile(parse::parse("nop\nnop\nnop").unwrap(), &layout(0xffff_fff8, None)).unwrap_err();
+	assert_eq!(diags[0].span.line, 3);
+	assert_eq!(diags[0].message, "instruction is placed past the end of the address space");
+	let diags = compile(parse::parse("nop\nnop\n.data\n.word 1").unwrap(), &layout(0xffff_fff0, None)).unwrap_err();
+	assert_eq!(diags[0].message, "the data section at 0x100000000 runs past the end of the address space");
+	let diags = compile(parse::parse(".data\n.word 1, 2").unwrap(), &layout(0, Some(0xffff_fffc))).unwrap_err();
+	assert_eq!(diags[0].message, "the data section at 0xfffffffc runs past the end of the address space");
+}
@@ -0,0 +1,438 @@
+use std::fmt;
+
+use crate::def::{self, AluOp, Class, Cond, CsrOp, CvtType, Fmt, FpCond, FpOp, FusedOp, ISetElem, Operand};
+use crate::Instruction;
+
+/// An instruction word taken apart: the table entry it's an encoding of, the operands that entry
+/// lists, and the instruction again with its own operands for code that runs it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+	pub elem: &'static ISetElem,
+	pub fields: Fields,
+	pub inst: DecodedInst,
+}
+
+impl Decoded {
+	pub fn name(&self) -> &'static str {
+		self.elem.name()
+	}
+}
+
+/// An instruction with its operands pulled out of the encoding. Immediates are sign extended and,
+/// for branches and jumps, are the byte offset from the instruction. The immediate of `lui` and
+/// `auipc` is the value they add, with the low 12 bits clear. CSR numbers are unsigned.
+///
+/// Floating-point instructions come in a single and a double precision version, which share a
+/// variant and are told apart by their `fmt`. `rm` is the rounding mode field, which is
+/// [`RM_DYN`](def::RM_DYN) for the one in `frm`. Reserved rounding modes don't decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodedInst {
+	Add { rd: u32, rs1: u32, rs2: u32 },
+	Sub { rd: u32, rs1: u32, rs2: u32 },
+	And { rd: u32, rs1: u32, rs2: u32 },
+	Or { rd: u32, rs1: u32, rs2: u32 },
+	Xor { rd: u32, rs1: u32, rs2: u32 },
+	Sll { rd: u32, rs1: u32, rs2: u32 },
+	Srl { rd: u32, rs1: u32, rs2: u32 },
+	Sra { rd: u32, rs1: u32, rs2: u32 },
+	Slt { rd: u32, rs1: u32, rs2: u32 },
+	Sltu { rd: u32, rs1: u32, rs2: u32 },
+	Mul { rd: u32, rs1: u32, rs2: u32 },
+	Mulh { rd: u32, rs1: u32, rs2: u32 },
+	Mulhsu { rd: u32, rs1: u32, rs2: u32 },
+	Mulhu { rd: u32, rs1: u32, rs2: u32 },
+	Div { rd: u32, rs1: u32, rs2: u32 },
+	Divu { rd: u32, rs1: u32, rs2: u32 },
+	Rem { rd: u32, rs1: u32, rs2: u32 },
+	Remu { rd: u32, rs1: u32, rs2: u32 },
+	Addi { rd: u32, rs1: u32, imm: i32 },
+	Andi { rd: u32, rs1: u32, imm: i32 },
+	Ori { rd: u32, rs1: u32, imm: i32 },
+	Xori { rd: u32, rs1: u32, imm: i32 },
+	Slti { rd: u32, rs1: u32, imm: i32 },
+	Sltiu { rd: u32, rs1: u32, imm: i32 },
+	Slli { rd: u32, rs1: u32, shamt: u32 },
+	Srli { rd: u32, rs1: u32, shamt: u32 },
+	Srai { rd: u32, rs1: u32, shamt: u32 },
+	Lb { rd: u32, rs1: u32, offset: i32 },
+	Lbu { rd: u32, rs1: u32, offset: i32 },
+	Lh { rd: u32, rs1: u32, offset: i32 },
+	Lhu { rd: u32, rs1: u32, offset: i32 },
+	Lw { rd: u32, rs1: u32, offset: i32 },
+	Sb { rs1: u32, rs2: u32, offset: i32 },
+	Sh { rs1: u32, rs2: u32, offset: i32 },
+	Sw { rs1: u32, rs2: u32, offset: i32 },
+	Beq { rs1: u32, rs2: u32, offset: i32 },
+	Bne { rs1: u32, rs2: u32, offset: i32 },
+	Blt { rs1: u32, rs2: u32, offset: i32 },
+	Bge { rs1: u32, rs2: u32, offset: i32 },
+	Bltu { rs1: u32, rs2: u32, offset: i32 },
+	Bgeu { rs1: u32, rs2: u32, offset: i32 },
+	Jal { rd: u32, offset: i32 },
+	Jalr { rd: u32, rs1: u32, offset: i32 },
+	Lui { rd: u32, imm: i32 },
+	Auipc { rd: u32, imm: i32 },
+	Ecall,
+	Ebreak,
+	Mret,
+	Sret,
+	Wfi,
+	SfenceVma { rs1: u32, rs2: u32 },
+	Csrrw { rd: u32, rs1: u32, csr: u32 },
+	Csrrs { rd: u32, rs1: u32, csr: u32 },
+	Csrrc { rd: u32, rs1: u32, csr: u32 },
+	Csrrwi { rd: u32, uimm: u32, csr: u32 },
+	Csrrsi { rd: u32, uimm: u32, csr: u32 },
+	Csrrci { rd: u32, uimm: u32, csr: u32 },
+	Addw { rd: u32, rs1: u32, rs2: u32 },
+	Subw { rd: u32, rs1: u32, rs2: u32 },
+	Sllw { rd: u32, rs1: u32, rs2: u32 },
+	Srlw { rd: u32, rs1: u32, rs2: u32 },
+	Sraw { rd: u32, rs1: u32, rs2: u32 },
+	Mulw { rd: u32, rs1: u32, rs2: u32 },
+	Divw { rd: u32, rs1: u32, rs2: u32 },
+	Divuw { rd: u32, rs1: u32, rs2: u32 },
+	Remw { rd: u32, rs1: u32, rs2: u32 },
+	Remuw { rd: u32, rs1: u32, rs2: u32 },
+	Addiw { rd: u32, rs1: u32, imm: i32 },
+	Slliw { rd: u32, rs1: u32, shamt: u32 },
+	Srliw { rd: u32, rs1: u32, shamt: u32 },
+	Sraiw { rd: u32, rs1: u32, shamt: u32 },
+	Lwu { rd: u32, rs1: u32, offset: i32 },
+	Ld { rd: u32, rs1: u32, offset: i32 },
+	Sd { rs1: u32, rs2: u32, offset: i32 },
+	Flw { rd: u32, rs1: u32, offset: i32 },
+	Fld { rd: u32, rs1: u32, offset: i32 },
+	Fsw { rs1: u32, rs2: u32, offset: i32 },
+	Fsd { rs1: u32, rs2: u32, offset: i32 },
+	Fmadd { fmt: Fmt, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+	Fmsub { fmt: Fmt, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+	Fnmsub { fmt: Fmt, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+	Fnmadd { fmt: Fmt, rd: u32, rs1: u32, rs2: u32, rs3: u32, rm: u32 },
+	Fadd { fmt: Fmt, rd: u32, rs1: u32, rs2: u32, rm: u32 },
+	Fsub { fmt: Fmt, rd: u32, rs1: u32, rs2: u32, rm: u32 },
+	Fmul { fmt: Fmt, rd: u32, rs1: u32, rs2: u32, rm: u32 },
+	Fdiv { fmt: Fmt, rd: u32, rs1: u32, rs2: u32, rm: u32 },
+	Fsqrt { fmt: Fmt, rd: u32, rs1: u32, rm: u32 },
+	Fsgnj { fmt: Fmt, rd: u32, rs1: u32, rs2: u32 },
+	Fsgnjn { fmt: Fmt, rd: u32, rs1: u32, rs2: u32 },
+	Fsgnjx { fmt: Fmt, rd: u32, rs1: u32, rs2: u32 },
+	Fmin { fmt: Fmt, rd: u32, rs1: u32, rs2: u32 },
+	Fmax { fmt: Fmt, rd: u32, rs1: u32, rs2: u32 },
+	Feq { fmt: Fmt, rd: u32, rs1: u32, rs2: u32 },
+	Flt { fmt: Fmt, rd: u32, rs1: u32, rs2: u32 },
+	Fle { fmt: Fmt, rd: u32, rs1: u32, rs2: u32 },
+	Fclass { fmt: Fmt, rd: u32, rs1: u32 },
+	/// Any of the `fcvt` instructions, with `rd` and `rs1` in the register files of `to` and `from`.
+	Fcvt { to: CvtType, from: CvtType, rd: u32, rs1: u32, rm: u32 },
+	/// `fmv.x.w` and `fmv.x.d`
+	FmvX { fmt: Fmt, rd: u32, rs1: u32 },
+	/// `fmv.w.x` and `fmv.d.x`
+	FmvF { fmt: Fmt, rd: u32, rs1: u32 },
+}
+
+/// The register and immediate operands of an instruction, for code that handles every instruction
+/// the same way. Shift amounts and CSR numbers are in `imm`, unsigned.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fields {
+	pub rd: Option<u32>,
+	pub rs1: Option<u32>,
+	pub rs2: Option<u32>,
+	pub rs3: Option<u32>,
+	pub rm: Option<u32>,
+	pub imm: Option<i32>,
+}
+
+/// The word wasn't the encoding of any instruction in the ISA table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError(pub u32);
+
+impl fmt::Display for DecodeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "illegal instruction {:#010x}", self.0)
+	}
+}
+
+impl std::error::Error for DecodeError {}
+
+pub fn decode(word: u32) -> Result<Decoded, DecodeError> {
+	use DecodedInst::*;
+	let inst = Instruction(word);
+	let elem = def::decode(inst).ok_or(DecodeError(word))?;
+	let (rd, rs1, rs2) = (inst.rd(), inst.rs1(), inst.rs2());
+	let imm = inst.imm_by_format(elem.format());
+	// 6 bits for the RV64 shifts, the word shifts only match when the sixth is clear
+	let shamt = inst.0 >> 20 & 0x3f;
+	let (rs3, rm) = (inst.rs3(), inst.funct3());
+	let csr = inst.0 >> 20;
+	let has = |kinds: &[Operand]| elem.operands().iter().any(|operand| kinds.contains(operand));
+	if has(&[Operand::Rm]) && (rm == 0b101 || rm == 0b110) {
+		return Err(DecodeError(word));
+	}
+	let fields = Fields {
+		rd: has(&[Operand::Rd, Operand::Frd]).then_some(rd),
+		rs1: has(&[Operand::Rs1, Operand::Frs1, Operand::Uimm, Operand::Mem]).then_some(rs1),
+		rs2: has(&[Operand::Rs2, Operand::Frs2]).then_some(rs2),
+		rs3: has(&[Operand::Frs3]).then_some(rs3),
+		rm: has(&[Operand::Rm]).then_some(rm),
+		imm: if has(&[Operand::Shamt]) {
+			Some(shamt as i32)
+		} else if has(&[Operand::Csr]) {
+			Some(csr as i32)
+		} else {
+			has(&[Operand::Imm, Operand::Label, Operand::Mem]).then_some(imm)
+		},
+	};
+	let inst = match elem.class() {
+		Class::Op(op) => match op {
+			AluOp::Add => Add { rd, rs1, rs2 },
+			AluOp::Sub => Sub { rd, rs1, rs2 },
+			AluOp::And => And { rd, rs1, rs2 },
+			AluOp::Or => Or { rd, rs1, rs2 },
+			AluOp::Xor => Xor { rd, rs1, rs2 },
+			AluOp::Sll => Sll { rd, rs1, rs2 },
+			AluOp::Srl => Srl { rd, rs1, rs2 },
+			AluOp::Sra => Sra { rd, rs1, rs2 },
+			AluOp::Slt => Slt { rd, rs1, rs2 },
+			AluOp::Sltu => Sltu { rd, rs1, rs2 },
+			AluOp::Mul => Mul { rd, rs1, rs2 },
+			AluOp::Mulh => Mulh { rd, rs1, rs2 },
+			AluOp::Mulhsu => Mulhsu { rd, rs1, rs2 },
+			AluOp::Mulhu => Mulhu { rd, rs1, rs2 },
+			AluOp::Div => Div { rd, rs1, rs2 },
+			AluOp::Divu => Divu { rd, rs1, rs2 },
+			AluOp::Rem => Rem { rd, rs1, rs2 },
+			AluOp::Remu => Remu { rd, rs1, rs2 },
+		},
+		Class::OpImm(op) => match op {
+			AluOp::Add => Addi { rd, rs1, imm },
+			AluOp::And => Andi { rd, rs1, imm },
+			AluOp::Or => Ori { rd, rs1, imm },
+			AluOp::Xor => Xori { rd, rs1, imm },
+			AluOp::Slt => Slti { rd, rs1, imm },
+			AluOp::Sltu => Sltiu { rd, rs1, imm },
+			AluOp::Sll => Slli { rd, rs1, shamt },
+			AluOp::Srl => Srli { rd, rs1, shamt },
+			AluOp::Sra => Srai { rd, rs1, shamt },
+			AluOp::Sub
+			| AluOp::Mul
+			| AluOp::Mulh
+			| AluOp::Mulhsu
+			| AluOp::Mulhu
+			| AluOp::Div
+			| AluOp::Divu
+			| AluOp::Rem
+			| AluOp::Remu => return Err(DecodeError(word)),
+		},
+		Class::Op32(op) => match op {
+			AluOp::Add => Addw { rd, rs1, rs2 },
+			AluOp::Sub => Subw { rd, rs1, rs2 },
+			AluOp::Sll => Sllw { rd, rs1, rs2 },
+			AluOp::Srl => Srlw { rd, rs1, rs2 },
+			AluOp::Sra => Sraw { rd, rs1, rs2 },
+			AluOp::Mul => Mulw { rd, rs1, rs2 },
+			AluOp::Div => Divw { rd, rs1, rs2 },
+			AluOp::Divu => Divuw { rd, rs1, rs2 },
+			AluOp::Rem => Remw { rd, rs1, rs2 },
+			AluOp::Remu => Remuw { rd, rs1, rs2 },
+			AluOp::And | AluOp::Or | AluOp::Xor | AluOp::Slt | AluOp::Sltu | AluOp::Mulh | AluOp::Mulhsu | AluOp::Mulhu => {
+				return Err(DecodeError(word))
+			},
+		},
+		Class::OpImm32(op) => match op {
+			AluOp::Add => Addiw { rd, rs1, imm },
+			AluOp::Sll => Slliw { rd, rs1, shamt },
+			AluOp::Srl => Srliw { rd, rs1, shamt },
+			AluOp::Sra => Sraiw { rd, rs1, shamt },
+			_ => return Err(DecodeError(word)),
+		},
+		Class::Load { width, signed } => match (width, signed) {
+			(1, true) => Lb { rd, rs1, offset: imm },
+			(1, false) => Lbu { rd, rs1, offset: imm },
+			(2, true) => Lh { rd, rs1, offset: imm },
+			(2, false) => Lhu { rd, rs1, offset: imm },
+			(4, true) => Lw { rd, rs1, offset: imm },
+			(4, false) => Lwu { rd, rs1, offset: imm },
+			(8, _) => Ld { rd, rs1, offset: imm },
+			_ => return Err(DecodeError(word)),
+		},
+		Class::Store { width } => match width {
+			1 => Sb { rs1, rs2, offset: imm },
+			2 => Sh { rs1, rs2, offset: imm },
+			4 => Sw { rs1, rs2, offset: imm },
+			8 => Sd { rs1, rs2, offset: imm },
+			_ => return Err(DecodeError(word)),
+		},
+		Class::Branch(cond) => match cond {
+			Cond::Eq => Beq { rs1, rs2, offset: imm },
+			Cond::Ne => Bne { rs1, rs2, offset: imm },
+			Cond::Lt => Blt { rs1, rs2, offset: imm },
+			Cond::Ge => Bge { rs1, rs2, offset: imm },
+			Cond::Ltu => Bltu { rs1, rs2, offset: imm },
+			Cond::Geu => Bgeu { rs1, rs2, offset: imm },
+		},
+		Class::Jal => Jal { rd, offset: imm },
+		Class::Jalr => Jalr { rd, rs1, offset: imm },
+		Class::Lui => Lui { rd, imm },
+		Class::Auipc => Auipc { rd, imm },
+		Class::Ecall => Ecall,
+		Class::Ebreak => Ebreak,
+		Class::Mret => Mret,
+		Class::Sret => Sret,
+		Class::Wfi => Wfi,
+		Class::SfenceVma => SfenceVma { rs1, rs2 },
+		Class::Csr { op, imm: false } => match op {
+			CsrOp::Write => Csrrw { rd, rs1, csr },
+			CsrOp::Set => Csrrs { rd, rs1, csr },
+			CsrOp::Clear => Csrrc { rd, rs1, csr },
+		},
+		Class::Csr { op, imm: true } => {
+			let uimm = rs1;
+			match op {
+				CsrOp::Write => Csrrwi { rd, uimm, csr },
+				CsrOp::Set => Csrrsi { rd, uimm, csr },
+				CsrOp::Clear => Csrrci { rd, uimm, csr },
+			}
+		},
+		Class::FpLoad(Fmt::S) => Flw { rd, rs1, offset: imm },
+		Class::FpLoad(Fmt::D) => Fld { rd, rs1, offset: imm },
+		Class::FpStore(Fmt::S) => Fsw { rs1, rs2, offset: imm },
+		Class::FpStore(Fmt::D) => Fsd { rs1, rs2, offset: imm },
+		Class::FpFused(op, fmt) => match op {
+			FusedOp::Madd => Fmadd { fmt, rd, rs1, rs2, rs3, rm },
+			FusedOp::Msub => Fmsub { fmt, rd, rs1, rs2, rs3, rm },
+			FusedOp::Nmsub => Fnmsub { fmt, rd, rs1, rs2, rs3, rm },
+			FusedOp::Nmadd => Fnmadd { fmt, rd, rs1, rs2, rs3, rm },
+		},
+		Class::FpArith(op, fmt) => match op {
+			FpOp::Add => Fadd { fmt, rd, rs1, rs2, rm },
+			FpOp::Sub => Fsub { fmt, rd, rs1, rs2, rm },
+			FpOp::Mul => Fmul { fmt, rd, rs1, rs2, rm },
+			FpOp::Div => Fdiv { fmt, rd, rs1, rs2, rm },
+			FpOp::Sqrt => Fsqrt { fmt, rd, rs1, rm },
+			FpOp::SgnJ => Fsgnj { fmt, rd, rs1, rs2 },
+			FpOp::SgnJn => Fsgnjn { fmt, rd, rs1, rs2 },
+			FpOp::SgnJx => Fsgnjx { fmt, rd, rs1, rs2 },
+			FpOp::Min => Fmin { fmt, rd, rs1, rs2 },
+			FpOp::Max => Fmax { fmt, rd, rs1, rs2 },
+		},
+		Class::FpCompare(cond, fmt) => match cond {
+			FpCond::Eq => Feq { fmt, rd, rs1, rs2 },
+			FpCond::Lt => Flt { fmt, rd, rs1, rs2 },
+			FpCond::Le => Fle { fmt, rd, rs1, rs2 },
+		},
+		Class::FpClass(fmt) => Fclass { fmt, rd, rs1 },
+		Class::FpConvert { to, from } => Fcvt { to, from, rd, rs1, rm },
+		Class::FpMoveToInt(fmt) => FmvX { fmt, rd, rs1 },
+		Class::FpMoveFromInt(fmt) => FmvF { fmt, rd, rs1 },
+	};
+	Ok(Decoded { elem, fields, inst })
+}
+
+#[test]
+fn test_decode() {
+	use DecodedInst::*;
+	let decode = |word| decode(word).map(|decoded| decoded.inst);
+	// addi a0, zero, -5
+	assert_eq!(decode(0xffb00513), Ok(Addi { rd: 10, rs1: 0, imm: -5 }));
+	// srai a2, a1, 3
+	assert_eq!(decode(0x4035d613), Ok(Srai { rd: 12, rs1: 11, shamt: 3 }));
+	// lui a1, 0x12345
+	assert_eq!(decode(0x123455b7), Ok(Lui { rd: 11, imm: 0x12345000 }));
+	// beq a0, zero, -20
+	assert_eq!(decode(0xfe0506e3), Ok(Beq { rs1: 10, rs2: 0, offset: -20 }));
+	assert_eq!(decode(0x00000073), Ok(Ecall));
+	assert_eq!(decode(0x00100073), Ok(Ebreak));
+	assert_eq!(decode(0x30200073), Ok(Mret));
+	assert_eq!(decode(0x10500073), Ok(Wfi));
+	assert_eq!(decode(0x10200073), Ok(Sret));
+	assert_eq!(decode(0x12050073), Ok(SfenceVma { rs1: 10, rs2: 0 }));
+	// csrrw a0, mscratch, a1
+	assert_eq!(decode(0x34059573), Ok(Csrrw { rd: 10, rs1: 11, csr: 0x340 }));
+	// csrrsi zero, mhartid, 31
+	assert_eq!(decode(0xf14fe073), Ok(Csrrsi { rd: 0, uimm: 31, csr: 0xf14 }));
+	assert_eq!(decode(0xffffffff), Err(DecodeError(0xffffffff)));
+	// srai with a funct7 that isn't 0100000
+	assert_eq!(decode(0x6035d613), Err(DecodeError(0x6035d613)));
+	// slli a0, a0, 40 and the RV64 only instructions
+	assert_eq!(decode(0x02851513), Ok(Slli { rd: 10, rs1: 10, shamt: 40 }));
+	assert_eq!(decode(0xffb5051b), Ok(Addiw { rd: 10, rs1: 10, imm: -5 }));
+	assert_eq!(decode(0x40b5053b), Ok(Subw { rd: 10, rs1: 10, rs2: 11 }));
+	assert_eq!(decode(0x0085b503), Ok(Ld { rd: 10, rs1: 11, offset: 8 }));
+	assert_eq!(decode(0x00a5b423), Ok(Sd { rs1: 11, rs2: 10, offset: 8 }));
+	// slliw with the sixth shift amount bit set
+	assert_eq!(decode(0x0205151b), Err(DecodeError(0x0205151b)));
+	// fadd.s fa0, fa1, fa2 with the dynamic rounding mode
+	assert_eq!(
+		decode(0x00c5f553),
+		Ok(Fadd {
+			fmt: Fmt::S,
+			rd: 10,
+			rs1: 11,
+			rs2: 12,
+			rm: def::RM_DYN
+		})
+	);
+	// fmadd.d fa0, fa1, fa2, fa3, rne
+	assert_eq!(
+		decode(0x6ac58543),
+		Ok(Fmadd {
+			fmt: Fmt::D,
+			rd: 10,
+			rs1: 11,
+			rs2: 12,
+			rs3: 13,
+			rm: 0
+		})
+	);
+	// fcvt.w.s a0, fa0, rtz
+	assert_eq!(
+		decode(0xc0051553),
+		Ok(Fcvt {
+			to: CvtType::W,
+			from: CvtType::S,
+			rd: 10,
+			rs1: 10,
+			rm: 1
+		})
+	);
+	// fld fa0, 8(sp) and fmv.x.w a0, fa0
+	assert_eq!(decode(0x00813507), Ok(Fld { rd: 10, rs1: 2, offset: 8 }));
+	assert_eq!(decode(0xe0050553), Ok(FmvX { fmt: Fmt::S, rd: 10, rs1: 10 }));
+	// fadd.s with the reserved rounding mode 5
+	assert_eq!(decode(0x00c5d553), Err(DecodeError(0x00c5d553)));
+}
+
+#[test]
+fn test_decode_every_instruction() {
+	for elem in def::ISET_DEFINITION {
+		let mut inst = Instruction(0);
+		inst.set_opcode(elem.opcode());
+		inst.set_funct3(elem.funct3().unwrap_or(0));
+		inst.set_funct7(elem.funct7().unwrap_or(0));
+		inst.0 |= elem.funct12().unwrap_or(0) << 20;
+		assert_eq!(decode(inst.0).unwrap().name(), elem.name());
+	}
+}
+
+#[test]
+fn test_decode_fields() {
+	// csrrsi zero, mhartid, 31
+	let decoded = decode(0xf14fe073).unwrap();
+	assert_eq!(decoded.name(), "csrrsi");
+	assert_eq!(
+		decoded.fields,
+		Fields {
+			rd: Some(0),
+			rs1: Some(31),
+			imm: Some(0xf14),
+			..Fields::default()
+		}
+	);
+	// slli a0, a0, 40, sw a0, -4(sp) and fmadd.d fa0, fa1, fa2, fa3, rne
+	assert_eq!(decode(0x02851513).unwrap().fields.imm, Some(40));
+	let fields = decode(0xfea12e23).unwrap().fields;
+	assert_eq!((fields.rd, fields.rs1, fields.rs2, fields.imm), (None, Some(2), Some(10), Some(-4)));
+	let fields = decode(0x6ac58543).unwrap().fields;
+	assert_eq!((fields.rs3, fields.rm, fields.imm), (Some(13), Some(0), None));
+}
@@ -1,50 +1,651 @@
-/// opcode, funct3, funct7, instruction name, instruction type
-pub struct ISetElem(pub u32, pub Option<u32>, pub Option<u32>, pub &'static str, pub &'static str);
+use std::fmt;
 
+use crate::{Instruction, InstructionFormat};
+
+/// opcode, funct3, funct7, funct12, instruction name, instruction format, operand syntax, semantics
+///
+/// funct7 doubles as the upper bits of the immediate for the immediate shifts, and funct12 is the
+/// whole immediate of the environment instructions. The floating-point instructions that pick their
+/// variant with rs2, like the conversions, give it in funct12 too. Everything about an instruction
+/// lives in its entry here: the parser, encoder, decoder and VM are all driven by this table.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ISetElem(
+	pub u32,
+	pub Option<u32>,
+	pub Option<u32>,
+	pub Option<u32>,
+	pub &'static str,
+	pub InstructionFormat,
+	pub &'static [Operand],
+	pub Class,
+);
+
+impl ISetElem {
+	pub fn opcode(&self) -> u32 {
+		self.0
+	}
+
+	pub fn funct3(&self) -> Option<u32> {
+		self.1
+	}
+
+	pub fn funct7(&self) -> Option<u32> {
+		self.2
+	}
+
+	pub fn funct12(&self) -> Option<u32> {
+		self.3
+	}
+
+	pub fn name(&self) -> &'static str {
+		self.4
+	}
+
+	pub fn format(&self) -> InstructionFormat {
+		self.5
+	}
+
+	pub fn operands(&self) -> &'static [Operand] {
+		self.6
+	}
+
+	pub fn class(&self) -> Class {
+		self.7
+	}
+
+	pub fn extension(&self) -> Extension {
+		match self.class() {
+			Op(Mul | Mulh | Mulhsu | Mulhu | Div | Divu | Rem | Remu) | Op32(Mul | Div | Divu | Rem | Remu) => Extension::M,
+			Class::Csr { .. } => Extension::Zicsr,
+			FpLoad(fmt)
+			| FpStore(fmt)
+			| FpArith(_, fmt)
+			| FpFused(_, fmt)
+			| FpCompare(_, fmt)
+			| FpClass(fmt)
+			| FpMoveToInt(fmt)
+			| FpMoveFromInt(fmt) => fmt.extension(),
+			FpConvert { to, from } if to == CvtType::D || from == CvtType::D => Extension::D,
+			FpConvert { .. } => Extension::F,
+			_ => Extension::I,
+		}
+	}
+
+	/// Whether this instruction only exists on RV64.
+	pub fn rv64_only(&self) -> bool {
+		matches!(
+			self.class(),
+			Op32(_)
+				| OpImm32(_)
+				| Load { width: 8, .. }
+				| Load { width: 4, signed: false }
+				| Store { width: 8 }
+				| FpConvert { to: CvtType::L | CvtType::Lu, .. }
+				| FpConvert { from: CvtType::L | CvtType::Lu, .. }
+				| FpMoveToInt(Fmt::D)
+				| FpMoveFromInt(Fmt::D)
+		)
+	}
+
+	/// How many bits the shift amount of an immediate shift has. The full width shifts of RV64 take
+	/// the lowest bit of funct7 for a sixth.
+	pub fn shamt_bits(&self, xlen: Xlen) -> u32 {
+		if xlen == Xlen::Rv64 && self.opcode() == OP_IMM {
+			6
+		} else {
+			5
+		}
+	}
+
+	/// Whether `inst` is an encoding of this instruction, on either RV32 or RV64.
+	pub fn matches(&self, inst: Instruction) -> bool {
+		// the lowest funct7 bit of the immediate shifts is part of the shift amount on RV64, and the fused
+		// multiply-adds only have the format there, below rs3
+		let funct7_mask = match self.format() {
+			_ if self.opcode() == OP_IMM => 0b1111110,
+			R4 => 0b11,
+			_ => 0b1111111,
+		};
+		inst.opcode() == self.opcode()
+			&& self.funct3().is_none_or(|f| f == inst.funct3())
+			&& self.funct7().is_none_or(|f| f == inst.funct7() & funct7_mask)
+			&& self.funct12().is_none_or(|f| f == inst.0 >> 20)
+	}
+}
+
+/// One operand in the assembly syntax of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+	Rd,
+	Rs1,
+	Rs2,
+	Imm,
+	Shamt,
+	Label,
+	/// `offset(rs1)`
+	Mem,
+	/// A CSR, by name or number, in the immediate.
+	Csr,
+	/// A 5 bit unsigned immediate in the rs1 field.
+	Uimm,
+	/// Floating-point registers.
+	Frd,
+	Frs1,
+	Frs2,
+	Frs3,
+	/// A rounding mode in place of funct3. It may be left out, which makes it dynamic.
+	Rm,
+}
+
+impl fmt::Display for Operand {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			Operand::Rd => "rd",
+			Operand::Rs1 => "rs1",
+			Operand::Rs2 => "rs2",
+			Operand::Imm => "imm",
+			Operand::Shamt => "shamt",
+			Operand::Label => "label",
+			Operand::Mem => "offset(rs1)",
+			Operand::Csr => "csr",
+			Operand::Uimm => "uimm",
+			Operand::Frd => "frd",
+			Operand::Frs1 => "frs1",
+			Operand::Frs2 => "frs2",
+			Operand::Frs3 => "frs3",
+			Operand::Rm => "rm",
+		};
+		write!(f, "{s}")
+	}
+}
+
+/// What an instruction does, which is what the VM dispatches on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+	/// `rd = rs1 op rs2`
+	Op(AluOp),
+	/// `rd = rs1 op imm`
+	OpImm(AluOp),
+	/// `rd = rs1 op rs2` on the lower 32 bits, sign extended. RV64 only.
+	Op32(AluOp),
+	/// `rd = rs1 op imm` on the lower 32 bits, sign extended. RV64 only.
+	OpImm32(AluOp),
+	/// Load `width` bytes into `rd`, sign extending if `signed`.
+	Load { width: u32, signed: bool },
+	/// Store the low `width` bytes of `rs2`.
+	Store { width: u32 },
+	Branch(Cond),
+	Jal,
+	Jalr,
+	Lui,
+	Auipc,
+	Ecall,
+	Ebreak,
+	/// Return from a machine mode trap handler.
+	Mret,
+	/// Return from a supervisor mode trap handler.
+	Sret,
+	/// Flush cached address translations for the address in `rs1`, or all of them if it's `zero`.
+	SfenceVma,
+	/// Wait for an interrupt. Doing nothing is a valid way to wait.
+	Wfi,
+	/// Swap `rd` and a CSR, with the new value from `rs1`, or from the uimm if `imm`.
+	Csr { op: CsrOp, imm: bool },
+	/// Load a float into `rd`.
+	FpLoad(Fmt),
+	/// Store the float in `rs2`.
+	FpStore(Fmt),
+	/// `rd = rs1 op rs2`, or `rd = op rs1` for the square root.
+	FpArith(FpOp, Fmt),
+	/// `rd = ±(rs1 * rs2) ± rs3` with a single rounding.
+	FpFused(FusedOp, Fmt),
+	/// `rd = rs1 cond rs2`, with `rd` an integer register.
+	FpCompare(FpCond, Fmt),
+	/// Set the bit of integer register `rd` for the kind of number in `rs1`.
+	FpClass(Fmt),
+	/// Convert between the floating-point formats and to or from the integer registers.
+	FpConvert { to: CvtType, from: CvtType },
+	/// Copy the bits of float `rs1` to integer register `rd`.
+	FpMoveToInt(Fmt),
+	/// Copy the bits of integer register `rs1` to float `rd`.
+	FpMoveFromInt(Fmt),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsrOp {
+	/// Write the value.
+	Write,
+	/// Set the bits that are set in the value.
+	Set,
+	/// Clear the bits that are set in the value.
+	Clear,
+}
+
+impl CsrOp {
+	/// The new value of a CSR that was `old`.
+	pub fn apply(self, old: u64, val: u64) -> u64 {
+		match self {
+			CsrOp::Write => val,
+			CsrOp::Set => old | val,
+			CsrOp::Clear => old & !val,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AluOp {
+	Add,
+	Sub,
+	And,
+	Or,
+	Xor,
+	Sll,
+	Srl,
+	Sra,
+	Slt,
+	Sltu,
+	Mul,
+	/// The upper XLEN bits of the signed product.
+	Mulh,
+	/// The upper XLEN bits of the product of a signed `rs1` and an unsigned `rs2`.
+	Mulhsu,
+	Mulhu,
+	Div,
+	Divu,
+	Rem,
+	Remu,
+}
+
+/// The floating-point format an instruction works on, from its `fmt` field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fmt {
+	/// IEEE 754 single precision.
+	S,
+	/// IEEE 754 double precision.
+	D,
+}
+
+impl Fmt {
+	pub fn extension(self) -> Extension {
+		match self {
+			Fmt::S => Extension::F,
+			Fmt::D => Extension::D,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FpOp {
+	Add,
+	Sub,
+	Mul,
+	Div,
+	Sqrt,
+	/// The sign of `rs2` with the rest of `rs1`.
+	SgnJ,
+	/// The opposite of the sign of `rs2` with the rest of `rs1`.
+	SgnJn,
+	/// The sign of `rs1` xor that of `rs2`, with the rest of `rs1`.
+	SgnJx,
+	Min,
+	Max,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FusedOp {
+	/// `rs1 * rs2 + rs3`
+	Madd,
+	/// `rs1 * rs2 - rs3`
+	Msub,
+	/// `-(rs1 * rs2) + rs3`
+	Nmsub,
+	/// `-(rs1 * rs2) - rs3`
+	Nmadd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FpCond {
+	Eq,
+	Lt,
+	Le,
+}
+
+/// What a conversion converts to or from: a float of either format, or a signed or unsigned word or
+/// (on RV64) long in an integer register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CvtType {
+	W,
+	Wu,
+	L,
+	Lu,
+	S,
+	D,
+}
+
+impl CvtType {
+	/// The floating-point format, if this is a float.
+	pub fn fmt(self) -> Option<Fmt> {
+		match self {
+			CvtType::S => Some(Fmt::S),
+			CvtType::D => Some(Fmt::D),
+			_ => None,
+		}
+	}
+}
+
+/// The `rm` field value that takes the rounding mode from `frm`.
+pub const RM_DYN: u32 = 0b111;
+
+/// The rounding modes the assembler knows by name.
+pub static ROUNDING_MODES: &[(&str, u32)] = &[("rne", 0), ("rtz", 1), ("rdn", 2), ("rup", 3), ("rmm", 4), ("dyn", RM_DYN)];
+
+pub fn rounding_mode_name(rm: u32) -> Option<&'static str> {
+	ROUNDING_MODES.iter().find(|&&(_, num)| num == rm).map(|&(name, _)| name)
+}
+
+/// A part of the ISA that a machine may leave out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Extension {
+	/// The base integer instruction set, which is always there.
+	I,
+	/// Integer multiplication and division.
+	M,
+	/// Control and status register instructions.
+	Zicsr,
+	/// Single precision floating point.
+	F,
+	/// Double precision floating point, which needs F too.
+	D,
+}
+
+/// The width of the integer registers and of addresses, which picks between RV32 and RV64.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Xlen {
+	#[default]
+	Rv32,
+	Rv64,
+}
+
+impl Xlen {
+	pub const fn bits(self) -> u32 {
+		match self {
+			Xlen::Rv32 => 32,
+			Xlen::Rv64 => 64,
+		}
+	}
+
+	/// `val` wrapped around to the register width, sign extended to 64 bits.
+	pub const fn wrap(self, val: i64) -> i64 {
+		match self {
+			Xlen::Rv32 => val as i32 as i64,
+			Xlen::Rv64 => val,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+	Eq,
+	Ne,
+	Lt,
+	Ge,
+	Ltu,
+	Geu,
+}
+
+use AluOp::*;
+use Class::*;
+use InstructionFormat::{B, I, J, R, R4, S, U};
+use Operand::*;
+
+const OP: u32 = 0b0110011;
+const OP_IMM: u32 = 0b0010011;
+const OP_32: u32 = 0b0111011;
+const OP_IMM_32: u32 = 0b0011011;
+const LOAD: u32 = 0b0000011;
+const STORE: u32 = 0b0100011;
+const BRANCH: u32 = 0b1100011;
+const JAL: u32 = 0b1101111;
+const JALR: u32 = 0b1100111;
+const AUIPC: u32 = 0b0010111;
+const LUI: u32 = 0b0110111;
+const SYSTEM: u32 = 0b1110011;
+const LOAD_FP: u32 = 0b0000111;
+const STORE_FP: u32 = 0b0100111;
+const OP_FP: u32 = 0b1010011;
+const MADD: u32 = 0b1000011;
+const MSUB: u32 = 0b1000111;
+const NMSUB: u32 = 0b1001011;
+const NMADD: u32 = 0b1001111;
+
+const RD_RS1_RS2: &[Operand] = &[Rd, Rs1, Rs2];
+const RD_RS1_IMM: &[Operand] = &[Rd, Rs1, Imm];
+const RD_RS1_SHAMT: &[Operand] = &[Rd, Rs1, Shamt];
+const RD_MEM: &[Operand] = &[Rd, Mem];
+const RS2_MEM: &[Operand] = &[Rs2, Mem];
+const RS1_RS2: &[Operand] = &[Rs1, Rs2];
+const RS1_RS2_LABEL: &[Operand] = &[Rs1, Rs2, Label];
+const RD_LABEL: &[Operand] = &[Rd, Label];
+const RD_IMM: &[Operand] = &[Rd, Imm];
+const RD_CSR_RS1: &[Operand] = &[Rd, Operand::Csr, Rs1];
+const RD_CSR_UIMM: &[Operand] = &[Rd, Operand::Csr, Uimm];
+const FRD_MEM: &[Operand] = &[Frd, Mem];
+const FRS2_MEM: &[Operand] = &[Frs2, Mem];
+const FRD_FRS1_FRS2_FRS3_RM: &[Operand] = &[Frd, Frs1, Frs2, Frs3, Rm];
+const FRD_FRS1_FRS2_RM: &[Operand] = &[Frd, Frs1, Frs2, Rm];
+const FRD_FRS1_FRS2: &[Operand] = &[Frd, Frs1, Frs2];
+const FRD_FRS1_RM: &[Operand] = &[Frd, Frs1, Rm];
+const RD_FRS1_FRS2: &[Operand] = &[Rd, Frs1, Frs2];
+const RD_FRS1_RM: &[Operand] = &[Rd, Frs1, Rm];
+const RD_FRS1: &[Operand] = &[Rd, Frs1];
+const FRD_RS1_RM: &[Operand] = &[Frd, Rs1, Rm];
+const FRD_RS1: &[Operand] = &[Frd, Rs1];
+
+/// funct12 of a conversion or other OP-FP instruction that uses rs2 to pick its variant.
+const fn fp_funct12(funct7: u32, rs2: u32) -> Option<u32> {
+	Some(funct7 << 5 | rs2)
+}
+
+#[rustfmt::skip]
 pub static ISET_DEFINITION: &[ISetElem] = &[
-	ISetElem(0b0110011, Some(0b000), Some(0b0000000), "add", "R"),
-	ISetElem(0b0110011, Some(0b000), Some(0b0100000), "sub", "R"),
-	ISetElem(0b0110011, Some(0b111), Some(0b0000000), "and", "R"),
-	ISetElem(0b0110011, Some(0b110), Some(0b0000000), "or", "R"),
-	ISetElem(0b0110011, Some(0b100), Some(0b0000000), "xor", "R"),
-	ISetElem(0b0110011, Some(0b001), Some(0b0000000), "sll", "R"),
-	ISetElem(0b0110011, Some(0b101), Some(0b0000000), "srl", "R"),
-	ISetElem(0b0110011, Some(0b101), Some(0b0100000), "sra", "R"),
-	ISetElem(0b0110011, Some(0b010), Some(0b0000000), "slt", "R"),
-	ISetElem(0b0110011, Some(0b011), Some(0b0000000), "sltu", "R"),
-	ISetElem(0b0010011, Some(0b000), None, "addi", "I"),
-	ISetElem(0b0010011, Some(0b000), None, "adni", "I"),
-	ISetElem(0b0010011, Some(0b000), None, "ori", "I"),
-	ISetElem(0b0010011, Some(0b000), None, "xori", "I"),
-	ISetElem(0b0010011, Some(0b000), Some(0b0000000), "slli", "I"),
-	ISetElem(0b0010011, Some(0b000), Some(0b0000000), "srli", "I"),
-	ISetElem(0b0010011, Some(0b000), Some(0b0100000), "srai", "I"),
-	ISetElem(0b0010011, Some(0b000), None, "slti", "I"),
-	ISetElem(0b0010011, Some(0b000), None, "sltiu", "I"),
-	ISetElem(0b0000011, Some(0b000), None, "lb", "I"),
-	ISetElem(0b0000011, Some(0b000), None, "lbu", "I"),
-	ISetElem(0b0000011, Some(0b000), None, "lh", "I"),
-	ISetElem(0b0000011, Some(0b000), None, "lhu", "I"),
-	ISetElem(0b0000011, Some(0b000), None, "lw", "I"),
-	ISetElem(0b0100011, Some(0b000), None, "sb", "S"),
-	ISetElem(0b0100011, Some(0b001), None, "sh", "S"),
-	ISetElem(0b0100011, Some(0b010), None, "sw", "S"),
-	ISetElem(0b1100011, Some(0b000), None, "beq", "B"),
-	ISetElem(0b1100011, Some(0b101), None, "bge", "B"),
-	ISetElem(0b1100011, Some(0b111), None, "bgeu", "B"),
-	ISetElem(0b1100011, Some(0b100), None, "blt", "B"),
-	ISetElem(0b1100011, Some(0b110), None, "bltu", "B"),
-	ISetElem(0b1100011, Some(0b001), None, "bne", "B"),
-	ISetElem(0b1101111, None, None, "jal", "J"),
-	ISetElem(0b1100111, Some(0b000), None, "jalr", "I"),
-	ISetElem(0b0010111, None, None, "auipc", "I"),
-	ISetElem(0b0110111, None, None, "lui", "U"),
-	ISetElem(0b1110011, Some(0b000), None, "ebreak", "I"),
-	ISetElem(0b1110011, Some(0b000), None, "ecall", "I"),
-	ISetElem(0b0110011, Some(0b000), Some(0b0000001), "mul", "R"),
+	ISetElem(OP, Some(0b000), Some(0b0000000), None, "add", R, RD_RS1_RS2, Op(Add)),
+	ISetElem(OP, Some(0b000), Some(0b0100000), None, "sub", R, RD_RS1_RS2, Op(Sub)),
+	ISetElem(OP, Some(0b111), Some(0b0000000), None, "and", R, RD_RS1_RS2, Op(And)),
+	ISetElem(OP, Some(0b110), Some(0b0000000), None, "or", R, RD_RS1_RS2, Op(Or)),
+	ISetElem(OP, Some(0b100), Some(0b0000000), None, "xor", R, RD_RS1_RS2, Op(Xor)),
+	ISetElem(OP, Some(0b001), Some(0b0000000), None, "sll", R, RD_RS1_RS2, Op(Sll)),
+	ISetElem(OP, Some(0b101), Some(0b0000000), None, "srl", R, RD_RS1_RS2, Op(Srl)),
+	ISetElem(OP, Some(0b101), Some(0b0100000), None, "sra", R, RD_RS1_RS2, Op(Sra)),
+	ISetElem(OP, Some(0b010), Some(0b0000000), None, "slt", R, RD_RS1_RS2, Op(Slt)),
+	ISetElem(OP, Some(0b011), Some(0b0000000), None, "sltu", R, RD_RS1_RS2, Op(Sltu)),
+	ISetElem(OP_IMM, Some(0b000), None, None, "addi", I, RD_RS1_IMM, OpImm(Add)),
+	ISetElem(OP_IMM, Some(0b111), None, None, "andi", I, RD_RS1_IMM, OpImm(And)),
+	ISetElem(OP_IMM, Some(0b110), None, None, "ori", I, RD_RS1_IMM, OpImm(Or)),
+	ISetElem(OP_IMM, Some(0b100), None, None, "xori", I, RD_RS1_IMM, OpImm(Xor)),
+	ISetElem(OP_IMM, Some(0b001), Some(0b0000000), None, "slli", I, RD_RS1_SHAMT, OpImm(Sll)),
+	ISetElem(OP_IMM, Some(0b101), Some(0b0000000), None, "srli", I, RD_RS1_SHAMT, OpImm(Srl)),
+	ISetElem(OP_IMM, Some(0b101), Some(0b0100000), None, "srai", I, RD_RS1_SHAMT, OpImm(Sra)),
+	ISetElem(OP_IMM, Some(0b010), None, None, "slti", I, RD_RS1_IMM, OpImm(Slt)),
+	ISetElem(OP_IMM, Some(0b011), None, None, "sltiu", I, RD_RS1_IMM, OpImm(Sltu)),
+	ISetElem(LOAD, Some(0b000), None, None, "lb", I, RD_MEM, Load { width: 1, signed: true }),
+	ISetElem(LOAD, Some(0b100), None, None, "lbu", I, RD_MEM, Load { width: 1, signed: false }),
+	ISetElem(LOAD, Some(0b001), None, None, "lh", I, RD_MEM, Load { width: 2, signed: true }),
+	ISetElem(LOAD, Some(0b101), None, None, "lhu", I, RD_MEM, Load { width: 2, signed: false }),
+	ISetElem(LOAD, Some(0b010), None, None, "lw", I, RD_MEM, Load { width: 4, signed: true }),
+	ISetElem(STORE, Some(0b000), None, None, "sb", S, RS2_MEM, Store { width: 1 }),
+	ISetElem(STORE, Some(0b001), None, None, "sh", S, RS2_MEM, Store { width: 2 }),
+	ISetElem(STORE, Some(0b010), None, None, "sw", S, RS2_MEM, Store { width: 4 }),
+	ISetElem(BRANCH, Some(0b000), None, None, "beq", B, RS1_RS2_LABEL, Branch(Cond::Eq)),
+	ISetElem(BRANCH, Some(0b101), None, None, "bge", B, RS1_RS2_LABEL, Branch(Cond::Ge)),
+	ISetElem(BRANCH, Some(0b111), None, None, "bgeu", B, RS1_RS2_LABEL, Branch(Cond::Geu)),
+	ISetElem(BRANCH, Some(0b100), None, None, "blt", B, RS1_RS2_LABEL, Branch(Cond::Lt)),
+	ISetElem(BRANCH, Some(0b110), None, None, "bltu", B, RS1_RS2_LABEL, Branch(Cond::Ltu)),
+	ISetElem(BRANCH, Some(0b001), None, None, "bne", B, RS1_RS2_LABEL, Branch(Cond::Ne)),
+	ISetElem(JAL, None, None, None, "jal", J, RD_LABEL, Jal),
+	ISetElem(JALR, Some(0b000), None, None, "jalr", I, RD_RS1_IMM, Jalr),
+	ISetElem(AUIPC, None, None, None, "auipc", U, RD_IMM, Auipc),
+	ISetElem(LUI, None, None, None, "lui", U, RD_IMM, Lui),
+	ISetElem(SYSTEM, Some(0b000), None, Some(0b000000000000), "ecall", I, &[], Ecall),
+	ISetElem(SYSTEM, Some(0b000), None, Some(0b000000000001), "ebreak", I, &[], Ebreak),
+	ISetElem(SYSTEM, Some(0b000), None, Some(0b001100000010), "mret", I, &[], Mret),
+	ISetElem(SYSTEM, Some(0b000), None, Some(0b000100000010), "sret", I, &[], Sret),
+	ISetElem(SYSTEM, Some(0b000), None, Some(0b000100000101), "wfi", I, &[], Wfi),
+	ISetElem(SYSTEM, Some(0b000), Some(0b0001001), None, "sfence.vma", R, RS1_RS2, SfenceVma),
+	ISetElem(OP, Some(0b000), Some(0b0000001), None, "mul", R, RD_RS1_RS2, Op(Mul)),
+	ISetElem(OP, Some(0b001), Some(0b0000001), None, "mulh", R, RD_RS1_RS2, Op(Mulh)),
+	ISetElem(OP, Some(0b010), Some(0b0000001), None, "mulhsu", R, RD_RS1_RS2, Op(Mulhsu)),
+	ISetElem(OP, Some(0b011), Some(0b0000001), None, "mulhu", R, RD_RS1_RS2, Op(Mulhu)),
+	ISetElem(OP, Some(0b100), Some(0b0000001), None, "div", R, RD_RS1_RS2, Op(Div)),
+	ISetElem(OP, Some(0b101), Some(0b0000001), None, "divu", R, RD_RS1_RS2, Op(Divu)),
+	ISetElem(OP, Some(0b110), Some(0b0000001), None, "rem", R, RD_RS1_RS2, Op(Rem)),
+	ISetElem(OP, Some(0b111), Some(0b0000001), None, "remu", R, RD_RS1_RS2, Op(Remu)),
+	ISetElem(SYSTEM, Some(0b001), None, None, "csrrw", I, RD_CSR_RS1, Class::Csr { op: CsrOp::Write, imm: false }),
+	ISetElem(SYSTEM, Some(0b010), None, None, "csrrs", I, RD_CSR_RS1, Class::Csr { op: CsrOp::Set, imm: false }),
+	ISetElem(SYSTEM, Some(0b011), None, None, "csrrc", I, RD_CSR_RS1, Class::Csr { op: CsrOp::Clear, imm: false }),
+	ISetElem(SYSTEM, Some(0b101), None, None, "csrrwi", I, RD_CSR_UIMM, Class::Csr { op: CsrOp::Write, imm: true }),
+	ISetElem(SYSTEM, Some(0b110), None, None, "csrrsi", I, RD_CSR_UIMM, Class::Csr { op: CsrOp::Set, imm: true }),
+	ISetElem(SYSTEM, Some(0b111), None, None, "csrrci", I, RD_CSR_UIMM, Class::Csr { op: CsrOp::Clear, imm: true }),
+	ISetElem(OP_IMM_32, Some(0b000), None, None, "addiw", I, RD_RS1_IMM, OpImm32(Add)),
+	ISetElem(OP_IMM_32, Some(0b001), Some(0b0000000), None, "slliw", I, RD_RS1_SHAMT, OpImm32(Sll)),
+	ISetElem(OP_IMM_32, Some(0b101), Some(0b0000000), None, "srliw", I, RD_RS1_SHAMT, OpImm32(Srl)),
+	ISetElem(OP_IMM_32, Some(0b101), Some(0b0100000), None, "sraiw", I, RD_RS1_SHAMT, OpImm32(Sra)),
+	ISetElem(OP_32, Some(0b000), Some(0b0000000), None, "addw", R, RD_RS1_RS2, Op32(Add)),
+	ISetElem(OP_32, Some(0b000), Some(0b0100000), None, "subw", R, RD_RS1_RS2, Op32(Sub)),
+	ISetElem(OP_32, Some(0b001), Some(0b0000000), None, "sllw", R, RD_RS1_RS2, Op32(Sll)),
+	ISetElem(OP_32, Some(0b101), Some(0b0000000), None, "srlw", R, RD_RS1_RS2, Op32(Srl)),
+	ISetElem(OP_32, Some(0b101), Some(0b0100000), None, "sraw", R, RD_RS1_RS2, Op32(Sra)),
+	ISetElem(LOAD, Some(0b110), None, None, "lwu", I, RD_MEM, Load { width: 4, signed: false }),
+	ISetElem(LOAD, Some(0b011), None, None, "ld", I, RD_MEM, Load { width: 8, signed: true }),
+	ISetElem(STORE, Some(0b011), None, None, "sd", S, RS2_MEM, Store { width: 8 }),
+	ISetElem(OP_32, Some(0b000), Some(0b0000001), None, "mulw", R, RD_RS1_RS2, Op32(Mul)),
+	ISetElem(OP_32, Some(0b100), Some(0b0000001), None, "divw", R, RD_RS1_RS2, Op32(Div)),
+	ISetElem(OP_32, Some(0b101), Some(0b0000001), None, "divuw", R, RD_RS1_RS2, Op32(Divu)),
+	ISetElem(OP_32, Some(0b110), Some(0b0000001), None, "remw", R, RD_RS1_RS2, Op32(Rem)),
+	ISetElem(OP_32, Some(0b111), Some(0b0000001), None, "remuw", R, RD_RS1_RS2, Op32(Remu)),
+	ISetElem(LOAD_FP, Some(0b010), None, None, "flw", I, FRD_MEM, FpLoad(Fmt::S)),
+	ISetElem(STORE_FP, Some(0b010), None, None, "fsw", S, FRS2_MEM, FpStore(Fmt::S)),
+	ISetElem(MADD, None, Some(0b00), None, "fmadd.s", R4, FRD_FRS1_FRS2_FRS3_RM, FpFused(FusedOp::Madd, Fmt::S)),
+	ISetElem(MSUB, None, Some(0b00), None, "fmsub.s", R4, FRD_FRS1_FRS2_FRS3_RM, FpFused(FusedOp::Msub, Fmt::S)),
+	ISetElem(NMSUB, None, Some(0b00), None, "fnmsub.s", R4, FRD_FRS1_FRS2_FRS3_RM, FpFused(FusedOp::Nmsub, Fmt::S)),
+	ISetElem(NMADD, None, Some(0b00), None, "fnmadd.s", R4, FRD_FRS1_FRS2_FRS3_RM, FpFused(FusedOp::Nmadd, Fmt::S)),
+	ISetElem(OP_FP, None, Some(0b0000000), None, "fadd.s", R, FRD_FRS1_FRS2_RM, FpArith(FpOp::Add, Fmt::S)),
+	ISetElem(OP_FP, None, Some(0b0000100), None, "fsub.s", R, FRD_FRS1_FRS2_RM, FpArith(FpOp::Sub, Fmt::S)),
+	ISetElem(OP_FP, None, Some(0b0001000), None, "fmul.s", R, FRD_FRS1_FRS2_RM, FpArith(FpOp::Mul, Fmt::S)),
+	ISetElem(OP_FP, None, Some(0b0001100), None, "fdiv.s", R, FRD_FRS1_FRS2_RM, FpArith(FpOp::Div, Fmt::S)),
+	ISetElem(OP_FP, None, None, fp_funct12(0b0101100, 0), "fsqrt.s", R, FRD_FRS1_RM, FpArith(FpOp::Sqrt, Fmt::S)),
+	ISetElem(OP_FP, Some(0b000), Some(0b0010000), None, "fsgnj.s", R, FRD_FRS1_FRS2, FpArith(FpOp::SgnJ, Fmt::S)),
+	ISetElem(OP_FP, Some(0b001), Some(0b0010000), None, "fsgnjn.s", R, FRD_FRS1_FRS2, FpArith(FpOp::SgnJn, Fmt::S)),
+	ISetElem(OP_FP, Some(0b010), Some(0b0010000), None, "fsgnjx.s", R, FRD_FRS1_FRS2, FpArith(FpOp::SgnJx, Fmt::S)),
+	ISetElem(OP_FP, Some(0b000), Some(0b0010100), None, "fmin.s", R, FRD_FRS1_FRS2, FpArith(FpOp::Min, Fmt::S)),
+	ISetElem(OP_FP, Some(0b001), Some(0b0010100), None, "fmax.s", R, FRD_FRS1_FRS2, FpArith(FpOp::Max, Fmt::S)),
+	ISetElem(OP_FP, Some(0b010), Some(0b1010000), None, "feq.s", R, RD_FRS1_FRS2, FpCompare(FpCond::Eq, Fmt::S)),
+	ISetElem(OP_FP, Some(0b001), Some(0b1010000), None, "flt.s", R, RD_FRS1_FRS2, FpCompare(FpCond::Lt, Fmt::S)),
+	ISetElem(OP_FP, Some(0b000), Some(0b1010000), None, "fle.s", R, RD_FRS1_FRS2, FpCompare(FpCond::Le, Fmt::S)),
+	ISetElem(OP_FP, Some(0b001), None, fp_funct12(0b1110000, 0), "fclass.s", R, RD_FRS1, FpClass(Fmt::S)),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1100000, 0), "fcvt.w.s", R, RD_FRS1_RM, FpConvert { to: CvtType::W, from: CvtType::S }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1100000, 1), "fcvt.wu.s", R, RD_FRS1_RM, FpConvert { to: CvtType::Wu, from: CvtType::S }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1100000, 2), "fcvt.l.s", R, RD_FRS1_RM, FpConvert { to: CvtType::L, from: CvtType::S }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1100000, 3), "fcvt.lu.s", R, RD_FRS1_RM, FpConvert { to: CvtType::Lu, from: CvtType::S }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1101000, 0), "fcvt.s.w", R, FRD_RS1_RM, FpConvert { to: CvtType::S, from: CvtType::W }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1101000, 1), "fcvt.s.wu", R, FRD_RS1_RM, FpConvert { to: CvtType::S, from: CvtType::Wu }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1101000, 2), "fcvt.s.l", R, FRD_RS1_RM, FpConvert { to: CvtType::S, from: CvtType::L }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1101000, 3), "fcvt.s.lu", R, FRD_RS1_RM, FpConvert { to: CvtType::S, from: CvtType::Lu }),
+	ISetElem(OP_FP, Some(0b000), None, fp_funct12(0b1110000, 0), "fmv.x.w", R, RD_FRS1, FpMoveToInt(Fmt::S)),
+	ISetElem(OP_FP, Some(0b000), None, fp_funct12(0b1111000, 0), "fmv.w.x", R, FRD_RS1, FpMoveFromInt(Fmt::S)),
+	ISetElem(LOAD_FP, Some(0b011), None, None, "fld", I, FRD_MEM, FpLoad(Fmt::D)),
+	ISetElem(STORE_FP, Some(0b011), None, None, "fsd", S, FRS2_MEM, FpStore(Fmt::D)),
+	ISetElem(MADD, None, Some(0b01), None, "fmadd.d", R4, FRD_FRS1_FRS2_FRS3_RM, FpFused(FusedOp::Madd, Fmt::D)),
+	ISetElem(MSUB, None, Some(0b01), None, "fmsub.d", R4, FRD_FRS1_FRS2_FRS3_RM, FpFused(FusedOp::Msub, Fmt::D)),
+	ISetElem(NMSUB, None, Some(0b01), None, "fnmsub.d", R4, FRD_FRS1_FRS2_FRS3_RM, FpFused(FusedOp::Nmsub, Fmt::D)),
+	ISetElem(NMADD, None, Some(0b01), None, "fnmadd.d", R4, FRD_FRS1_FRS2_FRS3_RM, FpFused(FusedOp::Nmadd, Fmt::D)),
+	ISetElem(OP_FP, None, Some(0b0000001), None, "fadd.d", R, FRD_FRS1_FRS2_RM, FpArith(FpOp::Add, Fmt::D)),
+	ISetElem(OP_FP, None, Some(0b0000101), None, "fsub.d", R, FRD_FRS1_FRS2_RM, FpArith(FpOp::Sub, Fmt::D)),
+	ISetElem(OP_FP, None, Some(0b0001001), None, "fmul.d", R, FRD_FRS1_FRS2_RM, FpArith(FpOp::Mul, Fmt::D)),
+	ISetElem(OP_FP, None, Some(0b0001101), None, "fdiv.d", R, FRD_FRS1_FRS2_RM, FpArith(FpOp::Div, Fmt::D)),
+	ISetElem(OP_FP, None, None, fp_funct12(0b0101101, 0), "fsqrt.d", R, FRD_FRS1_RM, FpArith(FpOp::Sqrt, Fmt::D)),
+	ISetElem(OP_FP, Some(0b000), Some(0b0010001), None, "fsgnj.d", R, FRD_FRS1_FRS2, FpArith(FpOp::SgnJ, Fmt::D)),
+	ISetElem(OP_FP, Some(0b001), Some(0b0010001), None, "fsgnjn.d", R, FRD_FRS1_FRS2, FpArith(FpOp::SgnJn, Fmt::D)),
+	ISetElem(OP_FP, Some(0b010), Some(0b0010001), None, "fsgnjx.d", R, FRD_FRS1_FRS2, FpArith(FpOp::SgnJx, Fmt::D)),
+	ISetElem(OP_FP, Some(0b000), Some(0b0010101), None, "fmin.d", R, FRD_FRS1_FRS2, FpArith(FpOp::Min, Fmt::D)),
+	ISetElem(OP_FP, Some(0b001), Some(0b0010101), None, "fmax.d", R, FRD_FRS1_FRS2, FpArith(FpOp::Max, Fmt::D)),
+	ISetElem(OP_FP, Some(0b010), Some(0b1010001), None, "feq.d", R, RD_FRS1_FRS2, FpCompare(FpCond::Eq, Fmt::D)),
+	ISetElem(OP_FP, Some(0b001), Some(0b1010001), None, "flt.d", R, RD_FRS1_FRS2, FpCompare(FpCond::Lt, Fmt::D)),
+	ISetElem(OP_FP, Some(0b000), Some(0b1010001), None, "fle.d", R, RD_FRS1_FRS2, FpCompare(FpCond::Le, Fmt::D)),
+	ISetElem(OP_FP, Some(0b001), None, fp_funct12(0b1110001, 0), "fclass.d", R, RD_FRS1, FpClass(Fmt::D)),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1100001, 0), "fcvt.w.d", R, RD_FRS1_RM, FpConvert { to: CvtType::W, from: CvtType::D }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1100001, 1), "fcvt.wu.d", R, RD_FRS1_RM, FpConvert { to: CvtType::Wu, from: CvtType::D }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1100001, 2), "fcvt.l.d", R, RD_FRS1_RM, FpConvert { to: CvtType::L, from: CvtType::D }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1100001, 3), "fcvt.lu.d", R, RD_FRS1_RM, FpConvert { to: CvtType::Lu, from: CvtType::D }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1101001, 0), "fcvt.d.w", R, FRD_RS1_RM, FpConvert { to: CvtType::D, from: CvtType::W }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1101001, 1), "fcvt.d.wu", R, FRD_RS1_RM, FpConvert { to: CvtType::D, from: CvtType::Wu }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1101001, 2), "fcvt.d.l", R, FRD_RS1_RM, FpConvert { to: CvtType::D, from: CvtType::L }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b1101001, 3), "fcvt.d.lu", R, FRD_RS1_RM, FpConvert { to: CvtType::D, from: CvtType::Lu }),
+	ISetElem(OP_FP, Some(0b000), None, fp_funct12(0b1110001, 0), "fmv.x.d", R, RD_FRS1, FpMoveToInt(Fmt::D)),
+	ISetElem(OP_FP, Some(0b000), None, fp_funct12(0b1111001, 0), "fmv.d.x", R, FRD_RS1, FpMoveFromInt(Fmt::D)),
+	ISetElem(OP_FP, None, None, fp_funct12(0b0100000, 1), "fcvt.s.d", R, FRD_FRS1_RM, FpConvert { to: CvtType::S, from: CvtType::D }),
+	ISetElem(OP_FP, None, None, fp_funct12(0b0100001, 0), "fcvt.d.s", R, FRD_FRS1_RM, FpConvert { to: CvtType::D, from: CvtType::S }),
+];
+
+/// The CSRs the assembler knows by name.
+pub static CSR_NAMES: &[(&str, u32)] = &[
+	("fflags", 0x001),
+	("frm", 0x002),
+	("fcsr", 0x003),
+	("sstatus", 0x100),
+	("sie", 0x104),
+	("stvec", 0x105),
+	("sscratch", 0x140),
+	("sepc", 0x141),
+	("scause", 0x142),
+	("stval", 0x143),
+	("sip", 0x144),
+	("satp", 0x180),
+	("mstatus", 0x300),
+	("misa", 0x301),
+	("medeleg", 0x302),
+	("mideleg", 0x303),
+	("mie", 0x304),
+	("mtvec", 0x305),
+	("mscratch", 0x340),
+	("mepc", 0x341),
+	("mcause", 0x342),
+	("mtval", 0x343),
+	("mip", 0x344),
+	("mcycle", 0xb00),
+	("minstret", 0xb02),
+	("mcycleh", 0xb80),
+	("minstreth", 0xb82),
+	("cycle", 0xc00),
+	("time", 0xc01),
+	("instret", 0xc02),
+	("cycleh", 0xc80),
+	("timeh", 0xc81),
+	("instreth", 0xc82),
+	("mhartid", 0xf14),
 ];
 
-pub static INST_FORMAT_IMM_PIECES: &[(&'static str, (&[(u32, u32, u32)], bool))] = &[
+pub fn csr_number(name: &str) -> Option<u32> {
+	CSR_NAMES.iter().find(|&&(n, _)| n == name).map(|&(_, num)| num)
+}
+
+pub fn csr_name(num: u32) -> Option<&'static str> {
+	CSR_NAMES.iter().find(|&&(_, n)| n == num).map(|&(name, _)| name)
+}
+
+/// The table entry for the instruction called `name`.
+pub fn lookup(name: &str) -> Option<&'static ISetElem> {
+	ISET_DEFINITION.iter().find(|e| e.name() == name)
+}
+
+/// The table entry that `inst` is an encoding of, if any.
+pub fn decode(inst: Instruction) -> Option<&'static ISetElem> {
+	ISET_DEFINITION.iter().find(|e| e.matches(inst))
+}
+
+/// instruction bit index start, immediate output bit index start, length
+pub type ImmPiece = (u32, u32, u32);
+
+pub static INST_FORMAT_IMM_PIECES: &[(&str, (&[ImmPiece], bool))] = &[
 	("I", (&[(20, 0, 12)], true)),
 	("S", (&[(7, 0, 5), (25, 5, 7)], true)),
 	("B", (&[(7, 11, 1), (8, 1, 4), (25, 5, 6), (31, 12, 1)], true)),
@@ -52,7 +653,7 @@ pub static INST_FORMAT_IMM_PIECES: &[(&'static str, (&[(u32, u32, u32)], bool))]
 	("J", (&[(12, 12, 8), (20, 11, 1), (21, 1, 10), (31, 20, 1)], true)),
 ];
 
-pub static INST_PIECES: &[(&'static str, (u32, u32))] = &[
+pub static INST_PIECES: &[(&str, (u32, u32))] = &[
 	("opcode", (0, 7)),
 	("rd", (7, 5)),
 	("funct3", (12, 3)),
@@ -61,7 +662,7 @@ pub static INST_PIECES: &[(&'static str, (u32, u32))] = &[
 	("funct7", (25, 7)),
 ];
 
-pub static REG_ALIASES: &[&'static str; 32] = &[
+pub static REG_ALIASES: &[&str; 32] = &[
 	"zero",
 	"ra",
 	"sp",
@@ -96,16 +697,256 @@ pub static REG_ALIASES: &[&'static str; 32] = &[
 	"t6",
 ];
 
-pub static PSEUDO_INSTS: &[&'static str] = &[
-	"beqz",
-	"bnez",
-	"j",
-	"jr",
-	"la",
-	"li",
-	"mv",
-	"neg",
-	"nop",
-	"not",
-	"ret",
-];
\ No newline at end of file
+pub static FREG_ALIASES: &[&str; 32] = &[
+	"ft0",
+	"ft1",
+	"ft2",
+	"ft3",
+	"ft4",
+	"ft5",
+	"ft6",
+	"ft7",
+	"fs0",
+	"fs1",
+	"fa0",
+	"fa1",
+	"fa2",
+	"fa3",
+	"fa4",
+	"fa5",
+	"fa6",
+	"fa7",
+	"fs2",
+	"fs3",
+	"fs4",
+	"fs5",
+	"fs6",
+	"fs7",
+	"fs8",
+	"fs9",
+	"fs10",
+	"fs11",
+	"ft8",
+	"ft9",
+	"ft10",
+	"ft11",
+];
+
+/// pseudo instruction name, operand syntax, expansion
+///
+/// Each instruction in the expansion is a real instruction and its operands, in the order of that
+/// instruction's syntax. The expansion is empty for the pseudo instructions whose sequence depends on
+/// their operands, which `compile::expand_pseudo` builds itself. A pseudo instruction may share its
+/// name with a real instruction as long as it takes a different number of operands.
+pub struct PseudoInst(pub &'static str, pub &'static [Operand], pub &'static [(&'static str, &'static [Arg])]);
+
+impl PseudoInst {
+	pub fn name(&self) -> &'static str {
+		self.0
+	}
+
+	pub fn operands(&self) -> &'static [Operand] {
+		self.1
+	}
+
+	pub fn expansion(&self) -> &'static [(&'static str, &'static [Arg])] {
+		self.2
+	}
+}
+
+/// An operand of an instruction in the expansion of a pseudo instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arg {
+	/// The pseudo instruction's operand at this index.
+	Operand(usize),
+	Reg(u32),
+	Value(i32),
+	/// XLEN minus this many bits, the shift that moves a value this wide to the top of a register.
+	XlenMinus(u32),
+}
+
+const ARG0: Arg = Arg::Operand(0);
+const ARG1: Arg = Arg::Operand(1);
+const ARG2: Arg = Arg::Operand(2);
+const ZERO: Arg = Arg::Reg(0);
+const RA: Arg = Arg::Reg(1);
+
+const RD_RS1: &[Operand] = &[Rd, Rs1];
+const RS1_LABEL: &[Operand] = &[Rs1, Label];
+const RD_CSR: &[Operand] = &[Rd, Operand::Csr];
+const CSR_RS1: &[Operand] = &[Operand::Csr, Rs1];
+const CSR_UIMM: &[Operand] = &[Operand::Csr, Uimm];
+const FRD_FRS1: &[Operand] = &[Frd, Frs1];
+const FCSR: Arg = csr(0x003);
+const FRM: Arg = csr(0x002);
+const FFLAGS: Arg = csr(0x001);
+
+const fn csr(num: u32) -> Arg {
+	Arg::Value(num as i32)
+}
+
+#[rustfmt::skip]
+pub static PSEUDO_INSTS: &[PseudoInst] = &[
+	PseudoInst("beqz", RS1_LABEL, &[("beq", &[ARG0, ZERO, ARG1])]),
+	PseudoInst("bnez", RS1_LABEL, &[("bne", &[ARG0, ZERO, ARG1])]),
+	PseudoInst("bgez", RS1_LABEL, &[("bge", &[ARG0, ZERO, ARG1])]),
+	PseudoInst("bltz", RS1_LABEL, &[("blt", &[ARG0, ZERO, ARG1])]),
+	PseudoInst("blez", RS1_LABEL, &[("bge", &[ZERO, ARG0, ARG1])]),
+	PseudoInst("bgtz", RS1_LABEL, &[("blt", &[ZERO, ARG0, ARG1])]),
+	PseudoInst("bgt", RS1_RS2_LABEL, &[("blt", &[ARG1, ARG0, ARG2])]),
+	PseudoInst("ble", RS1_RS2_LABEL, &[("bge", &[ARG1, ARG0, ARG2])]),
+	PseudoInst("bgtu", RS1_RS2_LABEL, &[("bltu", &[ARG1, ARG0, ARG2])]),
+	PseudoInst("bleu", RS1_RS2_LABEL, &[("bgeu", &[ARG1, ARG0, ARG2])]),
+	PseudoInst("j", &[Label], &[("jal", &[ZERO, ARG0])]),
+	PseudoInst("jal", &[Label], &[("jal", &[RA, ARG0])]),
+	PseudoInst("jr", &[Rs1], &[("jalr", &[ZERO, ARG0, Arg::Value(0)])]),
+	PseudoInst("jalr", &[Rs1], &[("jalr", &[RA, ARG0, Arg::Value(0)])]),
+	PseudoInst("ret", &[], &[("jalr", &[ZERO, RA, Arg::Value(0)])]),
+	PseudoInst("call", &[Label], &[]),
+	PseudoInst("tail", &[Label], &[]),
+	PseudoInst("la", RD_LABEL, &[]),
+	PseudoInst("li", RD_IMM, &[]),
+	PseudoInst("mv", RD_RS1, &[("addi", &[ARG0, ARG1, Arg::Value(0)])]),
+	PseudoInst("neg", RD_RS1, &[("sub", &[ARG0, ZERO, ARG1])]),
+	PseudoInst("nop", &[], &[("addi", &[ZERO, ZERO, Arg::Value(0)])]),
+	PseudoInst("not", RD_RS1, &[("xori", &[ARG0, ARG1, Arg::Value(-1)])]),
+	PseudoInst("seqz", RD_RS1, &[("sltiu", &[ARG0, ARG1, Arg::Value(1)])]),
+	PseudoInst("snez", RD_RS1, &[("sltu", &[ARG0, ZERO, ARG1])]),
+	PseudoInst("sltz", RD_RS1, &[("slt", &[ARG0, ARG1, ZERO])]),
+	PseudoInst("sgtz", RD_RS1, &[("slt", &[ARG0, ZERO, ARG1])]),
+	PseudoInst("sext.b", RD_RS1, &[("slli", &[ARG0, ARG1, Arg::XlenMinus(8)]), ("srai", &[ARG0, ARG0, Arg::XlenMinus(8)])]),
+	PseudoInst("sext.h", RD_RS1, &[("slli", &[ARG0, ARG1, Arg::XlenMinus(16)]), ("srai", &[ARG0, ARG0, Arg::XlenMinus(16)])]),
+	PseudoInst("zext.b", RD_RS1, &[("andi", &[ARG0, ARG1, Arg::Value(255)])]),
+	PseudoInst("sext.w", RD_RS1, &[("addiw", &[ARG0, ARG1, Arg::Value(0)])]),
+	PseudoInst("negw", RD_RS1, &[("subw", &[ARG0, ZERO, ARG1])]),
+	PseudoInst("zext.h", RD_RS1, &[("slli", &[ARG0, ARG1, Arg::XlenMinus(16)]), ("srli", &[ARG0, ARG0, Arg::XlenMinus(16)])]),
+	PseudoInst("sfence.vma", &[], &[("sfence.vma", &[ZERO, ZERO])]),
+	PseudoInst("sfence.vma", &[Rs1], &[("sfence.vma", &[ARG0, ZERO])]),
+	PseudoInst("csrr", RD_CSR, &[("csrrs", &[ARG0, ARG1, ZERO])]),
+	PseudoInst("csrw", CSR_RS1, &[("csrrw", &[ZERO, ARG0, ARG1])]),
+	PseudoInst("csrs", CSR_RS1, &[("csrrs", &[ZERO, ARG0, ARG1])]),
+	PseudoInst("csrc", CSR_RS1, &[("csrrc", &[ZERO, ARG0, ARG1])]),
+	PseudoInst("csrwi", CSR_UIMM, &[("csrrwi", &[ZERO, ARG0, ARG1])]),
+	PseudoInst("csrsi", CSR_UIMM, &[("csrrsi", &[ZERO, ARG0, ARG1])]),
+	PseudoInst("csrci", CSR_UIMM, &[("csrrci", &[ZERO, ARG0, ARG1])]),
+	PseudoInst("rdcycle", &[Rd], &[("csrrs", &[ARG0, csr(0xc00), ZERO])]),
+	PseudoInst("rdtime", &[Rd], &[("csrrs", &[ARG0, csr(0xc01), ZERO])]),
+	PseudoInst("rdinstret", &[Rd], &[("csrrs", &[ARG0, csr(0xc02), ZERO])]),
+	PseudoInst("rdcycleh", &[Rd], &[("csrrs", &[ARG0, csr(0xc80), ZERO])]),
+	PseudoInst("rdtimeh", &[Rd], &[("csrrs", &[ARG0, csr(0xc81), ZERO])]),
+	PseudoInst("rdinstreth", &[Rd], &[("csrrs", &[ARG0, csr(0xc82), ZERO])]),
+	PseudoInst("fmv.s", FRD_FRS1, &[("fsgnj.s", &[ARG0, ARG1, ARG1])]),
+	PseudoInst("fabs.s", FRD_FRS1, &[("fsgnjx.s", &[ARG0, ARG1, ARG1])]),
+	PseudoInst("fneg.s", FRD_FRS1, &[("fsgnjn.s", &[ARG0, ARG1, ARG1])]),
+	PseudoInst("fgt.s", RD_FRS1_FRS2, &[("flt.s", &[ARG0, ARG2, ARG1])]),
+	PseudoInst("fge.s", RD_FRS1_FRS2, &[("fle.s", &[ARG0, ARG2, ARG1])]),
+	PseudoInst("fmv.d", FRD_FRS1, &[("fsgnj.d", &[ARG0, ARG1, ARG1])]),
+	PseudoInst("fabs.d", FRD_FRS1, &[("fsgnjx.d", &[ARG0, ARG1, ARG1])]),
+	PseudoInst("fneg.d", FRD_FRS1, &[("fsgnjn.d", &[ARG0, ARG1, ARG1])]),
+	PseudoInst("fgt.d", RD_FRS1_FRS2, &[("flt.d", &[ARG0, ARG2, ARG1])]),
+	PseudoInst("fge.d", RD_FRS1_FRS2, &[("fle.d", &[ARG0, ARG2, ARG1])]),
+	PseudoInst("frcsr", &[Rd], &[("csrrs", &[ARG0, FCSR, ZERO])]),
+	PseudoInst("fscsr", RD_RS1, &[("csrrw", &[ARG0, FCSR, ARG1])]),
+	PseudoInst("fscsr", &[Rs1], &[("csrrw", &[ZERO, FCSR, ARG0])]),
+	PseudoInst("frrm", &[Rd], &[("csrrs", &[ARG0, FRM, ZERO])]),
+	PseudoInst("fsrm", RD_RS1, &[("csrrw", &[ARG0, FRM, ARG1])]),
+	PseudoInst("fsrm", &[Rs1], &[("csrrw", &[ZERO, FRM, ARG0])]),
+	PseudoInst("frflags", &[Rd], &[("csrrs", &[ARG0, FFLAGS, ZERO])]),
+	PseudoInst("fsflags", RD_RS1, &[("csrrw", &[ARG0, FFLAGS, ARG1])]),
+	PseudoInst("fsflags", &[Rs1], &[("csrrw", &[ZERO, FFLAGS, ARG0])]),
+	PseudoInst("fsrmi", &[Rd, Uimm], &[("csrrwi", &[ARG0, FRM, ARG1])]),
+	PseudoInst("fsrmi", &[Uimm], &[("csrrwi", &[ZERO, FRM, ARG0])]),
+	PseudoInst("fsflagsi", &[Rd, Uimm], &[("csrrwi", &[ARG0, FFLAGS, ARG1])]),
+	PseudoInst("fsflagsi", &[Uimm], &[("csrrwi", &[ZERO, FFLAGS, ARG0])]),
+];
+
+#[test]
+fn test_iset_round_trip() {
+	use crate::{compile, parse};
+	for elem in ISET_DEFINITION {
+		assert!(std::ptr::eq(lookup(elem.name()).unwrap(), elem), "duplicate name {}", elem.name());
+		let mut inst = parse::Inst {
+			name: elem.name().to_owned(),
+			rd: None,
+			rs1: None,
+			rs2: None,
+			rs3: None,
+			rm: None,
+			imm: None,
+			span: Default::default(),
+		};
+		let imm = match elem.format() {
+			B => -8,
+			J => 2048,
+			U => 0x12345,
+			_ => -5,
+		};
+		for operand in elem.operands() {
+			match operand {
+				Rd | Frd => inst.rd = Some(5),
+				Rs1 | Frs1 => inst.rs1 = Some(6),
+				Rs2 | Frs2 => inst.rs2 = Some(7),
+				Frs3 => inst.rs3 = Some(8),
+				Rm => inst.rm = Some(1),
+				Imm | Label => inst.imm = Some(parse::Imm::Value(imm)),
+				Shamt => inst.imm = Some(parse::Imm::Value(3)),
+				Mem => {
+					inst.rs1 = Some(6);
+					inst.imm = Some(parse::Imm::Value(imm));
+				},
+				Operand::Csr => inst.imm = Some(parse::Imm::Value(0xf14)),
+				Uimm => inst.rs1 = Some(17),
+			}
+		}
+		let program = parse::Program {
+			insts: vec![inst.clone()],
+			..Default::default()
+		};
+		let code = compile::compile(program, &Default::default()).unwrap().text[0];
+		let decoded = decode(code).unwrap_or_else(|| panic!("{} does not decode", elem.name()));
+		assert_eq!(decoded.name(), elem.name());
+		for operand in elem.operands() {
+			match operand {
+				Rd | Frd => assert_eq!(code.rd(), 5),
+				Rs1 | Frs1 | Mem => assert_eq!(code.rs1(), 6),
+				Uimm => assert_eq!(code.rs1(), 17),
+				Rs2 | Frs2 => assert_eq!(code.rs2(), 7),
+				Frs3 => assert_eq!(code.rs3(), 8),
+				Rm => assert_eq!(code.funct3(), 1),
+				_ => {},
+			}
+		}
+		let code_imm = code.imm_by_format(elem.format());
+		match inst.imm {
+			Some(parse::Imm::Value(imm)) if elem.format() == U => assert_eq!(i64::from(code_imm), imm << 12),
+			Some(parse::Imm::Value(imm)) if elem.funct7().is_some() => assert_eq!(i64::from(code_imm & 0b11111), imm),
+			Some(parse::Imm::Value(imm)) if elem.operands().contains(&Operand::Csr) => assert_eq!(i64::from(code_imm & 0xfff), imm),
+			Some(parse::Imm::Value(imm)) => assert_eq!(i64::from(code_imm), imm, "{}", elem.name()),
+			_ => {},
+		}
+	}
+}
+
+#[test]
+fn test_pseudo_table() {
+	for pseudo in PSEUDO_INSTS {
+		for &(name, args) in pseudo.expansion() {
+			let elem = lookup(name).unwrap_or_else(|| panic!("{} expands to unknown `{name}`", pseudo.name()));
+			assert_eq!(elem.operands().len(), args.len(), "{}: {name}", pseudo.name());
+			for (&operand, &arg) in elem.operands().iter().zip(args) {
+				// which register file an operand names, if any
+				let reg_file = |operand| match operand {
+					Rd | Rs1 | Rs2 => Some(false),
+					Frd | Frs1 | Frs2 | Frs3 => Some(true),
+					_ => None,
+				};
+				let arg_reg_file = match arg {
+					Arg::Operand(i) => reg_file(pseudo.operands()[i]),
+					Arg::Reg(_) => Some(false),
+					Arg::Value(_) | Arg::XlenMinus(_) => None,
+				};
+				assert_eq!(reg_file(operand), arg_reg_file, "{}: {name} {operand}", pseudo.name());
+			}
+		}
+	}
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+	Error,
+	Warning,
+}
+
+/// A region of the source text. `line` is 1-based, `start` and `end` are the byte columns of the
+/// region within that line (`end` is exclusive).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+	pub line: usize,
+	pub start: usize,
+	pub end: usize,
+}
+
+impl Span {
+	pub fn new(line: usize, start: usize, end: usize) -> Self {
+		Self { line, start, end }
+	}
+
+	/// The smallest span covering both `self` and `other`, which must be on the same line.
+	pub fn to(self, other: Span) -> Span {
+		Span {
+			line: self.line,
+			start: std::cmp::min(self.start, other.start),
+			end: std::cmp::max(self.end, other.end),
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+	pub span: Span,
+	pub severity: Severity,
+	pub message: String,
+}
+
+impl Diagnostic {
+	pub fn error(span: Span, message: impl Into<String>) -> Self {
+		Self {
+			span,
+			severity: Severity::Error,
+			message: message.into(),
+		}
+	}
+
+	pub fn warning(span: Span, message: impl Into<String>) -> Self {
+		Self {
+			span,
+			severity: Severity::Warning,
+			message: message.into(),
+		}
+	}
+
+	pub fn is_error(&self) -> bool {
+		self.severity == Severity::Error
+	}
+}
+
+impl fmt::Display for Severity {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Severity::Error => write!(f, "error"),
+			Severity::Warning => write!(f, "warning"),
+		}
+	}
+}
+
+impl fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}:{}: {}: {}", self.span.line, self.span.start + 1, self.severity, self.message)
+	}
+}
+
+/// Names from `candidates` that look like a misspelling of `name`, closest first.
+pub fn similar_names<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+	let max_distance = std::cmp::max(1, name.len() / 3);
+	let mut similar = candidates
+		.map(|c| (edit_distance(&name.to_lowercase(), &c.to_lowercase()), c))
+		.filter(|&(d, _)| d <= max_distance)
+		.collect::<Vec<_>>();
+	similar.sort();
+	similar.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b = b.chars().collect::<Vec<_>>();
+	let mut prev = (0..=b.len()).collect::<Vec<_>>();
+	for (i, ca) in a.chars().enumerate() {
+		let mut cur = vec![i + 1; b.len() + 1];
+		for (j, &cb) in b.iter().enumerate() {
+			let cost = if ca == cb { 0 } else { 1 };
+			cur[j + 1] = std::cmp::min(std::cmp::min(prev[j + 1] + 1, cur[j] + 1), prev[j] + cost);
+		}
+		prev = cur;
+	}
+	prev[b.len()]
+}
+
+#[test]
+fn test_similar_names() {
+	let names = ["loop", "Loop2", "end", "exit_loop"];
+	assert_eq!(similar_names("lop", names.iter().copied()), &["loop"]);
+	assert_eq!(similar_names("loop", names.iter().copied()), &["loop", "Loop2"]);
+	assert!(similar_names("start", names.iter().copied()).is_empty());
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::def::{self, Operand, FREG_ALIASES, REG_ALIASES};
+use crate::{DecodedInst, Instruction};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Options<'a> {
+	/// Show idioms like `addi zero, zero, 0` as the pseudo instructions they implement (`nop`).
+	pub pseudo: bool,
+	/// Label names by address, used to show branch and jump targets as labels instead of offsets.
+	pub symbols: Option<&'a HashMap<u32, String>>,
+}
+
+impl Instruction {
+	/// Turn this instruction back into assembly, using ABI register names. `pc` is the address of the
+	/// instruction, which is needed to resolve branch targets to labels. Words that aren't a valid
+	/// instruction are shown as a `.word` directive.
+	pub fn disassemble(self, pc: u32, options: &Options<'_>) -> String {
+		let Ok(decoded) = crate::decode(self.0) else {
+			return format!(".word {:#010x}", self.0);
+		};
+		let (elem, fields) = (decoded.elem, decoded.fields);
+		let (rd, rs1, rs2) = (fields.rd.unwrap_or(0), fields.rs1.unwrap_or(0), fields.rs2.unwrap_or(0));
+		let imm = match decoded.inst {
+			// show the upper immediate the way it's written in `lui`
+			DecodedInst::Lui { imm, .. } | DecodedInst::Auipc { imm, .. } => imm >> 12,
+			_ => fields.imm.unwrap_or(0),
+		};
+		let target = || {
+			let addr = pc.wrapping_add(imm as u32);
+			match options.symbols.and_then(|symbols| symbols.get(&addr)) {
+				Some(label) => label.clone(),
+				None => imm.to_string(),
+			}
+		};
+		if options.pseudo {
+			if let Some(text) = fold_pseudo(elem.name(), rd, rs1, rs2, imm, target) {
+				return text;
+			}
+		}
+		let mut text = elem.name().to_owned();
+		for (i, operand) in elem.operands().iter().enumerate() {
+			text += if i == 0 { " " } else { ", " };
+			match operand {
+				Operand::Rd => text += reg(rd),
+				Operand::Rs1 => text += reg(rs1),
+				Operand::Rs2 => text += reg(rs2),
+				Operand::Imm | Operand::Shamt => write!(text, "{imm}").unwrap(),
+				Operand::Label => text += &target(),
+				Operand::Mem => write!(text, "{imm}({})", reg(rs1)).unwrap(),
+				Operand::Csr => text += &csr(imm),
+				Operand::Uimm => write!(text, "{rs1}").unwrap(),
+				Operand::Frd => text += freg(rd),
+				Operand::Frs1 => text += freg(rs1),
+				Operand::Frs2 => text += freg(rs2),
+				Operand::Frs3 => text += freg(fields.rs3.unwrap_or(0)),
+				Operand::Rm => match fields.rm {
+					// the dynamic mode is the default, so it's left out like the assembler allows
+					Some(def::RM_DYN) | None => text.truncate(text.len() - 2),
+					Some(rm) => text += def::rounding_mode_name(rm).unwrap(),
+				},
+			}
+		}
+		text
+	}
+}
+
+/// The pseudo instruction that an instruction is the canonical expansion of, if any.
+fn fold_pseudo(name: &str, rd: u32, rs1: u32, rs2: u32, imm: i32, target: impl Fn() -> String) -> Option<String> {
+	Some(match (name, rd, rs1, rs2, imm) {
+		("addi", 0, 0, _, 0) => "nop".to_owned(),
+		("addi", rd, 0, _, imm) => format!("li {}, {imm}", reg(rd)),
+		("addi", rd, rs1, _, 0) => format!("mv {}, {}", reg(rd), reg(rs1)),
+		("xori", rd, rs1, _, -1) => format!("not {}, {}", reg(rd), reg(rs1)),
+		("sub", rd, 0, rs2, _) => format!("neg {}, {}", reg(rd), reg(rs2)),
+		("addiw", rd, rs1, _, 0) => format!("sext.w {}, {}", reg(rd), reg(rs1)),
+		("subw", rd, 0, rs2, _) => format!("negw {}, {}", reg(rd), reg(rs2)),
+		("beq", _, rs1, 0, _) => format!("beqz {}, {}", reg(rs1), target()),
+		("bne", _, rs1, 0, _) => format!("bnez {}, {}", reg(rs1), target()),
+		("jal", 0, _, _, _) => format!("j {}", target()),
+		("jal", 1, _, _, _) => format!("jal {}", target()),
+		("jalr", 0, 1, _, 0) => "ret".to_owned(),
+		("jalr", 0, rs1, _, 0) => format!("jr {}", reg(rs1)),
+		("csrrs", rd, 0, _, num) => format!("csrr {}, {}", reg(rd), csr(num)),
+		("csrrw", 0, rs1, _, num) => format!("csrw {}, {}", csr(num), reg(rs1)),
+		("sfence.vma", _, 0, 0, _) => "sfence.vma".to_owned(),
+		("fsgnj.s" | "fsgnj.d", rd, rs1, rs2, _) if rs1 == rs2 => format!("fmv.{} {}, {}", &name[6..], freg(rd), freg(rs1)),
+		("fsgnjx.s" | "fsgnjx.d", rd, rs1, rs2, _) if rs1 == rs2 => format!("fabs.{} {}, {}", &name[7..], freg(rd), freg(rs1)),
+		("fsgnjn.s" | "fsgnjn.d", rd, rs1, rs2, _) if rs1 == rs2 => format!("fneg.{} {}, {}", &name[7..], freg(rd), freg(rs1)),
+		_ => return None,
+	})
+}
+
+fn reg(num: u32) -> &'static str {
+	REG_ALIASES[num as usize]
+}
+
+fn freg(num: u32) -> &'static str {
+	FREG_ALIASES[num as usize]
+}
+
+/// A CSR by name, or by number if it doesn't have one.
+fn csr(num: i32) -> String {
+	match def::csr_name(num as u32) {
+		Some(name) => name.to_owned(),
+		None => format!("{num:#x}"),
+	}
+}
+
+impl std::fmt::Display for Instruction {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{}", self.disassemble(0, &Options::default()))
+	}
+}
+
+#[test]
+fn test_disassemble() {
+	let source = "
+start:
+	addi a0 x0 -5
+	lw t0 8(sp)
+	sw t0 -4(s0)
+	lui a1 74565
+	slli a2 a1 3
+	beq a0 x0 start
+	jalr x0 ra 0
+	ecall
+	";
+	let image = crate::compile::compile(crate::parse::parse(source).unwrap(), &Default::default()).unwrap();
+	let code = image.text;
+	let texts = code.iter().map(|inst| inst.to_string()).collect::<Vec<_>>();
+	assert_eq!(
+		texts,
+		&[
+			"addi a0, zero, -5",
+			"lw t0, 8(sp)",
+			"sw t0, -4(s0)",
+			"lui a1, 74565",
+			"slli a2, a1, 3",
+			"beq a0, zero, -20",
+			"jalr zero, ra, 0",
+			"ecall",
+		]
+	);
+
+	let symbols = image.symbols.iter().map(|(name, &addr)| (addr, name.clone())).collect::<HashMap<_, _>>();
+	let options = Options {
+		pseudo: true,
+		symbols: Some(&symbols),
+	};
+	assert_eq!(code[0].disassemble(0, &options), "li a0, -5");
+	assert_eq!(code[5].disassemble(20, &options), "beqz a0, start");
+	assert_eq!(code[6].disassemble(24, &options), "ret");
+	assert_eq!(Instruction(0x00000013).disassemble(0, &options), "nop");
+	assert_eq!(Instruction(0xffffffff).to_string(), ".word 0xffffffff");
+	assert_eq!(Instruction(0x34059573).to_string(), "csrrw a0, mscratch, a1");
+	assert_eq!(Instruction(0x7c0fe073).to_string(), "csrrsi zero, 0x7c0, 31");
+	assert_eq!(Instruction(0x30002573).disassemble(0, &options), "csrr a0, mstatus");
+	assert_eq!(Instruction(0x00c5f553).to_string(), "fadd.s fa0, fa1, fa2");
+	assert_eq!(Instruction(0xc0051553).to_string(), "fcvt.w.s a0, fa0, rtz");
+	assert_eq!(Instruction(0x6ac58543).to_string(), "fmadd.d fa0, fa1, fa2, fa3, rne");
+	assert_eq!(Instruction(0x00813507).to_string(), "fld fa0, 8(sp)");
+	assert_eq!(Instruction(0x22b59553).disassemble(0, &options), "fneg.d fa0, fa1");
+}
+
+#[test]
+fn test_disassemble_round_trip() {
+	use crate::{def, InstructionFormat};
+	// every instruction in the table should reassemble to the same word
+	for elem in def::ISET_DEFINITION {
+		let operands = elem
+			.operands()
+			.iter()
+			.map(|operand| match (operand, elem.format()) {
+				(Operand::Rd, _) => "t0",
+				(Operand::Rs1, _) => "s1",
+				(Operand::Rs2, _) => "a7",
+				(Operand::Shamt, _) => "17",
+				(Operand::Mem, _) => "-12(s1)",
+				(Operand::Csr, _) => "mepc",
+				(Operand::Uimm, _) => "9",
+				(Operand::Frd, _) => "ft0",
+				(Operand::Frs1, _) => "fs1",
+				(Operand::Frs2, _) => "fa7",
+				(Operand::Frs3, _) => "ft11",
+				(Operand::Rm, _) => "rtz",
+				(_, InstructionFormat::B) => "-64",
+				(_, InstructionFormat::J) => "4096",
+				(_, _) => "-123",
+			})
+			.collect::<Vec<_>>();
+		let source = format!("{} {}", elem.name(), operands.join(", "));
+		let assemble = |source: &str| {
+			let program = crate::parse::parse_xlen(source, def::Xlen::Rv64).unwrap();
+			crate::compile::compile(program, &Default::default()).unwrap().text[0]
+		};
+		let code = assemble(&source);
+		assert_eq!(assemble(&code.to_string()), code, "{source}");
+	}
+}
@@ -3,6 +3,11 @@ use std::fmt;
 pub mod compile;
 pub mod parse;
 pub mod def;
+pub mod decode;
+pub mod diag;
+pub mod disasm;
+
+pub use decode::{decode, DecodeError, Decoded, DecodedInst};
 
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Instruction(pub u32);
@@ -31,6 +36,11 @@ impl Instruction {
 	pub fn funct7(self) -> u32 {
 		(self.0 >> (7 + 5 + 3 + 5 + 5)) & 0b1111111
 	}
+
+	/// The third source register of the R4 format, in the upper bits of funct7.
+	pub fn rs3(self) -> u32 {
+		self.0 >> 27
+	}
 	
 	pub fn set_opcode(&mut self, opcode: u32) {
 		self.0 &= !0b1111111;
@@ -62,31 +72,25 @@ impl Instruction {
 		self.0 |= funct7 << (7 + 5 + 3 + 5 + 5);
 	}
 
-	pub fn format(self) -> InstructionFormat {
-		use InstructionFormat::*;
-		match self.opcode() {
-			0b0110011 => R,
-			0b0010011 => I,
-			0b0000011 => I,
-			0b0100011 => S,
-			0b1100011 => B,
-			0b1101111 => J,
-			0b1100111 => I,
-			0b0010111 => U,
-			0b0110111 => U,
-			0b1110011 => I,
-			_ => panic!("Unknown opcode"),
-		}
+	pub fn set_rs3(&mut self, rs3: u32) {
+		self.0 &= !(0b11111 << 27);
+		self.0 |= rs3 << 27;
+	}
+
+	/// The format of the instruction, or `None` if its opcode isn't one of the instruction set's.
+	pub fn format(self) -> Option<InstructionFormat> {
+		def::ISET_DEFINITION.iter().find(|e| e.opcode() == self.opcode()).map(|e| e.format())
 	}
 
-	pub fn imm(self) -> i32 {
-		self.imm_by_format(self.format())
+	/// The immediate of the instruction, or `None` if its format isn't known.
+	pub fn imm(self) -> Option<i32> {
+		self.format().map(|format| self.imm_by_format(format))
 	}
 
 	pub fn imm_by_format(self, format: InstructionFormat) -> i32 {
 		use InstructionFormat::*;
 		match format {
-			R => 0,
+			R | R4 => 0,
 			I => self.imm_by_pieces(&[(20, 0, 12)], true),
 			S => self.imm_by_pieces(&[(7, 0, 5), (25, 5, 7)], true),
 			B => self.imm_by_pieces(&[(7, 11, 1), (8, 1, 4), (25, 5, 6), (31, 12, 1)], true),
@@ -115,24 +119,36 @@ impl Instruction {
 			let sign = output & (1 << highest) != 0;
 			let mask = !(2u32.pow(highest) - 1);
 			if sign {
-				output |= 0xFFFFFFFF & mask;
+				output |= mask;
 			}
 		}
 		i32::from_le_bytes(output.to_le_bytes())
 	}
 
-	pub fn set_imm_by_format(&mut self, format: InstructionFormat, imm: i32) {
+	/// Encode `imm` into the immediate field of `format`, failing if it doesn't fit in the field or
+	/// isn't aligned the way the format requires.
+	pub fn set_imm_by_format(&mut self, format: InstructionFormat, imm: i32) -> Result<(), EncodeError> {
 		use InstructionFormat::*;
+		let Some((min, max, align)) = format.imm_range() else {
+			return Err(EncodeError::NoImmediate(format));
+		};
+		if imm < min || imm > max {
+			return Err(EncodeError::OutOfRange { imm: imm.into(), min, max });
+		}
+		if imm % align != 0 {
+			return Err(EncodeError::Misaligned { imm, align });
+		}
 		match format {
-			R => panic!("R type instructions do not have immediates"),
+			R | R4 => unreachable!(),
 			I => self.set_imm_by_pieces(&[(20, 0, 12)], imm),
 			S => self.set_imm_by_pieces(&[(7, 0, 5), (25, 5, 7)], imm),
 			B => self.set_imm_by_pieces(&[(7, 11, 1), (8, 1, 4), (25, 5, 6), (31, 12, 1)], imm),
 			U => self.set_imm_by_pieces(&[(12, 0, 20)], imm),
 			J => self.set_imm_by_pieces(&[(12, 12, 8), (20, 11, 1), (21, 1, 10), (31, 20, 1)], imm),
 		}
+		Ok(())
 	}
-	
+
 	fn set_imm_by_pieces(&mut self, pieces: &[(u32, u32, u32)], imm: i32) {
 		let imm = imm as u32;
 		for &piece in pieces {
@@ -140,7 +156,7 @@ impl Instruction {
 			self.0 |= value << piece.0;
 		}
 	}
-	
+
 	pub fn from_bytes(bytes: [u8; 4]) -> Self {
 		Self(u32::from_le_bytes(bytes))
 	}
@@ -157,9 +173,11 @@ impl fmt::Debug for Instruction {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum InstructionFormat {
 	R,
+	/// R with a third source register, for the fused multiply-adds.
+	R4,
 	I,
 	S,
 	B,
@@ -167,6 +185,41 @@ pub enum InstructionFormat {
 	J,
 }
 
+impl InstructionFormat {
+	/// The smallest and largest immediate that can be encoded in this format, and the multiple that
+	/// the immediate has to be. U type immediates are the upper 20 bits and may be given signed or
+	/// unsigned.
+	pub fn imm_range(self) -> Option<(i32, i32, i32)> {
+		use InstructionFormat::*;
+		match self {
+			R | R4 => None,
+			I | S => Some((-2048, 2047, 1)),
+			B => Some((-4096, 4094, 2)),
+			U => Some((-(1 << 19), (1 << 20) - 1, 1)),
+			J => Some((-(1 << 20), (1 << 20) - 2, 2)),
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncodeError {
+	NoImmediate(InstructionFormat),
+	OutOfRange { imm: i64, min: i32, max: i32 },
+	Misaligned { imm: i32, align: i32 },
+}
+
+impl fmt::Display for EncodeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			EncodeError::NoImmediate(format) => write!(f, "{format:?} type instructions do not have immediates"),
+			EncodeError::OutOfRange { imm, min, max } => {
+				write!(f, "immediate {imm} is out of range, it must be between {min} and {max}")
+			},
+			EncodeError::Misaligned { imm, align } => write!(f, "immediate {imm} is not a multiple of {align}"),
+		}
+	}
+}
+
 #[test]
 fn test_imm_pieces() {
 	let tests = &[
@@ -206,4 +259,28 @@ fn test_imm_pieces_formats() {
 		println!("{output:0b}");
 		assert_eq!(output, test.2);
 	}
+	assert_eq!(Instruction(0xfff00013).imm(), Some(-1));
+	// words whose opcode isn't an instruction's have no format to read an immediate with
+	assert_eq!(Instruction(0xffff_ffff).format(), None);
+	assert_eq!(Instruction(0xffff_ffff).imm(), None);
+}
+
+#[test]
+fn test_set_imm_range() {
+	use InstructionFormat::*;
+	let mut inst = Instruction(0);
+	assert_eq!(inst.set_imm_by_format(I, 2047), Ok(()));
+	assert_eq!(inst.set_imm_by_format(I, 5000), Err(EncodeError::OutOfRange { imm: 5000, min: -2048, max: 2047 }));
+	assert_eq!(inst.set_imm_by_format(S, -2049), Err(EncodeError::OutOfRange { imm: -2049, min: -2048, max: 2047 }));
+	assert_eq!(inst.set_imm_by_format(B, 4096), Err(EncodeError::OutOfRange { imm: 4096, min: -4096, max: 4094 }));
+	assert_eq!(inst.set_imm_by_format(B, 3), Err(EncodeError::Misaligned { imm: 3, align: 2 }));
+	assert_eq!(inst.set_imm_by_format(J, -(1 << 20) - 2), Err(EncodeError::OutOfRange { imm: -(1 << 20) - 2, min: -(1 << 20), max: (1 << 20) - 2 }));
+	assert_eq!(inst.set_imm_by_format(R, 0), Err(EncodeError::NoImmediate(R)));
+
+	let mut inst = Instruction(0);
+	inst.set_imm_by_format(B, -4096).unwrap();
+	assert_eq!(inst.imm_by_format(B), -4096);
+	let mut inst = Instruction(0);
+	inst.set_imm_by_format(J, (1 << 20) - 2).unwrap();
+	assert_eq!(inst.imm_by_format(J), (1 << 20) - 2);
 }
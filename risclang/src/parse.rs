@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 
+use crate::def::{self, Operand, Xlen};
 use crate::compile;
+use crate::diag::{Diagnostic, Span};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Inst {
@@ -8,198 +10,1276 @@ pub struct Inst {
 	pub rd: Option<u32>,
 	pub rs1: Option<u32>,
 	pub rs2: Option<u32>,
+	pub rs3: Option<u32>,
+	/// The rounding mode, if one was given.
+	pub rm: Option<u32>,
 	pub imm: Option<Imm>,
+	/// Where in the source this instruction came from. Instructions expanded from a pseudo
+	/// instruction all share the span of the original.
+	pub span: Span,
 }
 
+impl Inst {
+	/// `addi zero, zero, 0`, which `.align` pads the text with.
+	pub(crate) fn nop(span: Span) -> Self {
+		Inst {
+			name: "addi".to_owned(),
+			rd: Some(0),
+			rs1: Some(0),
+			rs2: None,
+			rs3: None,
+			rm: None,
+			imm: Some(Imm::Value(0)),
+			span,
+		}
+	}
+}
+
+/// An operand expression. Parts that only involve numbers are folded into a `Value` while parsing,
+/// the rest is evaluated by the compiler once every label has an address.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Imm {
-	Value(i32),
+	Value(i64),
+	/// The address of a label or the value of a `.equ` symbol. `.` is the address of the instruction
+	/// or data item the expression belongs to.
 	Label(String),
+	Unary(UnaryOp, Box<Imm>),
+	Binary(BinaryOp, Box<Imm>, Box<Imm>),
+	Reloc(Reloc, Box<Imm>),
 }
 
-pub fn parse(input: &str) -> (Vec<Inst>, Vec<String>, HashMap<String, u32>) {
-    let mut insts = Vec::new();
-    let mut texts = Vec::new();
-	let mut labels = HashMap::new();
-	for line in input.lines() {
-		let full_line = line;
-		let line = line.split('#').next().unwrap();
-		let line = line.trim();
-		if line.is_empty() {
-			continue;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+	Neg,
+	Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+	Mul,
+	Div,
+	Rem,
+	Add,
+	Sub,
+	Shl,
+	Shr,
+	And,
+	Xor,
+	Or,
+}
+
+/// A relocation operator, which picks out part of an address for a `lui`/`auipc` and `addi` pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reloc {
+	/// `%hi(x)`: the upper 20 bits of `x`, rounded so that adding `%lo(x)` gives back `x`.
+	Hi,
+	/// `%lo(x)`: the lower 12 bits of `x`.
+	Lo,
+	/// `%pcrel_hi(x)`: the upper 20 bits of the offset from this instruction to `x`.
+	PcrelHi,
+	/// `%pcrel_lo(label)`: the lower 12 bits of the offset computed by the `%pcrel_hi` of the `auipc`
+	/// at `label`.
+	PcrelLo,
+}
+
+impl Imm {
+	fn unary(op: UnaryOp, operand: Imm, xlen: Xlen) -> Imm {
+		match operand {
+			Imm::Value(val) => Imm::Value(op.apply(val, xlen)),
+			operand => Imm::Unary(op, Box::new(operand)),
+		}
+	}
+
+	/// `None` if both sides are numbers and the operation divides by zero.
+	fn binary(op: BinaryOp, lhs: Imm, rhs: Imm, xlen: Xlen) -> Option<Imm> {
+		Some(match (lhs, rhs) {
+			(Imm::Value(lhs), Imm::Value(rhs)) => Imm::Value(op.apply(lhs, rhs, xlen)?),
+			(lhs, rhs) => Imm::Binary(op, Box::new(lhs), Box::new(rhs)),
+		})
+	}
+
+	fn reloc(reloc: Reloc, operand: Imm) -> Imm {
+		match (reloc, operand) {
+			(Reloc::Hi, Imm::Value(val)) => Imm::Value(compile::split_large_imm(val).0),
+			(Reloc::Lo, Imm::Value(val)) => Imm::Value(compile::split_large_imm(val).1),
+			(reloc, operand) => Imm::Reloc(reloc, Box::new(operand)),
+		}
+	}
+}
+
+impl UnaryOp {
+	pub fn apply(self, val: i64, xlen: Xlen) -> i64 {
+		xlen.wrap(match self {
+			UnaryOp::Neg => val.wrapping_neg(),
+			UnaryOp::Not => !val,
+		})
+	}
+}
+
+impl BinaryOp {
+	/// Arithmetic wraps around like it does on a machine with registers `xlen` wide. `None` when
+	/// dividing by zero.
+	pub fn apply(self, lhs: i64, rhs: i64, xlen: Xlen) -> Option<i64> {
+		use BinaryOp::*;
+		let shamt = rhs as u32 & (xlen.bits() - 1);
+		Some(xlen.wrap(match self {
+			Mul => lhs.wrapping_mul(rhs),
+			Div | Rem if rhs == 0 => return None,
+			Div => lhs.wrapping_div(rhs),
+			Rem => lhs.wrapping_rem(rhs),
+			Add => lhs.wrapping_add(rhs),
+			Sub => lhs.wrapping_sub(rhs),
+			Shl => lhs << shamt,
+			Shr => lhs >> shamt,
+			And => lhs & rhs,
+			Xor => lhs ^ rhs,
+			Or => lhs | rhs,
+		}))
+	}
+
+	/// How tightly the operator binds, following C.
+	fn precedence(self) -> u8 {
+		use BinaryOp::*;
+		match self {
+			Mul | Div | Rem => 5,
+			Add | Sub => 4,
+			Shl | Shr => 3,
+			And => 2,
+			Xor => 1,
+			Or => 0,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+	Text,
+	Data,
+}
+
+/// Where a label points, before the sections have been given addresses.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Symbol {
+	/// The index of an instruction in the text section.
+	Text(u32),
+	/// A byte offset into the data section.
+	Data(u32),
+	/// A symbol defined with `.equ` or `.set`. Only `.set` can redefine a symbol, and this is the last
+	/// definition.
+	Equ(Imm, Span),
+}
+
+/// A value in the data section that depends on a label, which is filled in once the label has an
+/// address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFixup {
+	pub offset: u32,
+	pub width: u32,
+	pub imm: Imm,
+	pub span: Span,
+}
+
+/// A `.align` in the text section, padded with `nops` nops starting at instruction `index`. Relaxing
+/// branches moves the code around, so the padding is worked out again after that.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Align {
+	pub index: u32,
+	pub nops: u32,
+	/// The alignment in bytes.
+	pub align: u32,
+	/// Where the directive is, and its source line, for the nops.
+	pub span: Span,
+	pub text: String,
+	/// The labels defined after the directive and before the next instruction. They stay after the
+	/// padding, even while there's none to tell them apart from the labels before it.
+	pub labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Program {
+	pub insts: Vec<Inst>,
+	/// The source line of each instruction.
+	pub texts: Vec<String>,
+	pub data: Vec<u8>,
+	pub fixups: Vec<DataFixup>,
+	pub labels: HashMap<String, Symbol>,
+	/// Labels named by `.globl`.
+	pub globals: Vec<String>,
+	/// The register width the program was written for.
+	pub xlen: Xlen,
+	pub aligns: Vec<Align>,
+}
+
+struct Parser {
+	program: Program,
+	section: Section,
+	label_spans: HashMap<String, Span>,
+	/// The `.equ` symbols with a known value so far, which are substituted as they're used.
+	constants: HashMap<String, i64>,
+}
+
+/// Parse a whole RV32 program. Every line is parsed even after an error is found, so that all of the
+/// problems in the source can be reported at once.
+pub fn parse(input: &str) -> Result<Program, Vec<Diagnostic>> {
+	parse_xlen(input, Xlen::Rv32)
+}
+
+/// Parse a whole program for RV32 or RV64. Literals are limited to `xlen` bits, and the RV64 only
+/// instructions like `ld` and `addw` are rejected on RV32.
+pub fn parse_xlen(input: &str, xlen: Xlen) -> Result<Program, Vec<Diagnostic>> {
+	let mut parser = Parser {
+		program: Program {
+			xlen,
+			..Program::default()
+		},
+		section: Section::Text,
+		label_spans: HashMap::new(),
+		constants: HashMap::new(),
+	};
+	let mut diags = Vec::new();
+	for (i, full_line) in input.lines().enumerate() {
+		if let Err(diag) = parser.parse_line(full_line, i + 1) {
+			diags.push(diag);
+		}
+	}
+	if diags.is_empty() {
+		compile::relax_branches(&mut parser.program);
+		Ok(parser.program)
+	} else {
+		Err(diags)
+	}
+}
+
+impl Parser {
+	fn parse_line(&mut self, full_line: &str, line_no: usize) -> Result<(), Diagnostic> {
+		let mut line = strip_comment(full_line);
+		let mut offset = 0;
+		// peel off any labels at the start of the line
+		while let Some(colon) = find_unquoted(line, ':') {
+			let label = line[..colon].trim();
+			if label.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'') {
+				break;
+			}
+			let start = offset + line.find(label).unwrap_or(0);
+			self.define_label(label, Span::new(line_no, start, start + label.len()))?;
+			offset += colon + 1;
+			line = &line[colon + 1..];
+		}
+		let tokens = tokenize(line, line_no, offset);
+		if tokens.is_empty() {
+			return Ok(());
+		}
+		if tokens[0].text.starts_with('.') {
+			return self.parse_directive(&tokens, full_line);
+		}
+		if self.section != Section::Text {
+			return Err(Diagnostic::error(tokens[0].span, "instructions must be in the text section"));
+		}
+		let xlen = self.program.xlen;
+		let insts = parse_inst(&tokens, &self.constants, xlen).and_then(|inst| compile::expand_pseudo(&inst, xlen))?;
+		// pseudo instructions like `sext.w` are RV64 only because of what they expand to
+		if xlen == Xlen::Rv32 && insts.iter().any(|inst| def::lookup(&inst.name).is_some_and(|elem| elem.rv64_only())) {
+			let name = tokens[0].text.to_lowercase();
+			return Err(Diagnostic::error(tokens[0].span, format!("`{name}` is only available on RV64")));
 		}
-		if line.contains(':') {
-			let label = line.split(':').next().unwrap().trim();
-			labels.insert(label.to_owned(), insts.len().try_into().unwrap());
+		for inst in insts {
+			self.program.insts.push(inst);
+			self.program.texts.push(full_line.trim_start().to_owned());
+		}
+		Ok(())
+	}
+
+	fn define_label(&mut self, label: &str, span: Span) -> Result<(), Diagnostic> {
+		if let Some(first) = self.label_spans.get(label) {
+			return Err(Diagnostic::error(
+				span,
+				format!("duplicate label `{label}`, first defined on line {}", first.line),
+			));
+		}
+		if !is_identifier(label) {
+			return Err(Diagnostic::error(span, format!("invalid label name `{label}`")));
+		}
+		let index = self.program.insts.len() as u32;
+		let last_align = self.program.aligns.last_mut().filter(|align| align.index + align.nops == index);
+		if let (Section::Text, Some(align)) = (self.section, last_align) {
+			align.labels.push(label.to_owned());
+		}
+		let symbol = match self.section {
+			Section::Text => Symbol::Text(index),
+			Section::Data => Symbol::Data(self.program.data.len().try_into().unwrap()),
+		};
+		self.program.labels.insert(label.to_owned(), symbol);
+		self.label_spans.insert(label.to_owned(), span);
+		Ok(())
+	}
+
+	fn parse_directive(&mut self, tokens: &[Token<'_>], full_line: &str) -> Result<(), Diagnostic> {
+		let name = tokens[0].text.to_lowercase();
+		let args = &tokens[1..];
+		let span = tokens[0].span.to(tokens[tokens.len() - 1].span);
+		let expect_one = || {
+			if args.len() == 1 {
+				Ok(args[0])
+			} else {
+				Err(Diagnostic::error(span, format!("`{name}` expects 1 operand, found {}", args.len())))
+			}
+		};
+		let data_only = || {
+			if self.section == Section::Data {
+				Ok(())
+			} else {
+				Err(Diagnostic::error(
+					tokens[0].span,
+					format!("`{name}` is only allowed in the data section, use `.data` first"),
+				))
+			}
+		};
+		match &*name {
+			".text" | ".data" => {
+				if !args.is_empty() {
+					return Err(Diagnostic::error(span, format!("`{name}` does not take operands")));
+				}
+				self.section = if name == ".text" { Section::Text } else { Section::Data };
+			},
+			".globl" | ".global" => {
+				for arg in args {
+					if !is_identifier(arg.text) {
+						return Err(Diagnostic::error(arg.span, format!("invalid label name `{}`", arg.text)));
+					}
+					self.program.globals.push(arg.text.to_owned());
+				}
+			},
+			".dword" | ".word" | ".half" | ".byte" => {
+				data_only()?;
+				let width = match &*name {
+					".dword" => 8,
+					".word" => 4,
+					".half" => 2,
+					_ => 1,
+				};
+				if args.is_empty() {
+					return Err(Diagnostic::error(span, format!("`{name}` expects at least 1 operand")));
+				}
+				for &arg in args {
+					let offset = self.program.data.len() as u32;
+					match parse_imm(arg, &self.constants, self.program.xlen)? {
+						Imm::Value(val) => {
+							check_data_range(val, width, arg.span)?;
+							self.program.data.extend_from_slice(&val.to_le_bytes()[..width as usize]);
+						},
+						imm => {
+							self.program.data.extend(std::iter::repeat_n(0, width as usize));
+							self.program.fixups.push(DataFixup { offset, width, imm, span: arg.span });
+						},
+					}
+				}
+			},
+			".float" | ".double" => {
+				data_only()?;
+				if args.is_empty() {
+					return Err(Diagnostic::error(span, format!("`{name}` expects at least 1 operand")));
+				}
+				for &arg in args {
+					let error = || Diagnostic::error(arg.span, format!("invalid floating-point number `{}`", arg.text));
+					if name == ".float" {
+						let val = arg.text.parse::<f32>().map_err(|_| error())?;
+						self.program.data.extend_from_slice(&val.to_le_bytes());
+					} else {
+						let val = arg.text.parse::<f64>().map_err(|_| error())?;
+						self.program.data.extend_from_slice(&val.to_le_bytes());
+					}
+				}
+			},
+			".ascii" | ".asciiz" | ".string" => {
+				data_only()?;
+				if args.is_empty() {
+					return Err(Diagnostic::error(span, format!("`{name}` expects at least 1 string")));
+				}
+				for &arg in args {
+					let bytes = parse_string(arg)?;
+					self.program.data.extend_from_slice(&bytes);
+					if name != ".ascii" {
+						self.program.data.push(0);
+					}
+				}
+			},
+			".space" | ".zero" => {
+				data_only()?;
+				let arg = expect_one()?;
+				match parse_imm(arg, &self.constants, self.program.xlen)? {
+					Imm::Value(size) if size >= 0 => self.program.data.extend(std::iter::repeat_n(0, size as usize)),
+					_ => return Err(Diagnostic::error(arg.span, format!("invalid size `{}`", arg.text))),
+				}
+			},
+			".align" | ".balign" => {
+				let arg = expect_one()?;
+				let align = match parse_imm(arg, &self.constants, self.program.xlen)? {
+					Imm::Value(n) if name == ".align" && (0..=16).contains(&n) => 1 << n,
+					Imm::Value(n) if name == ".balign" && n > 0 && n <= 1 << 16 && (n as u32).is_power_of_two() => n as usize,
+					_ => return Err(Diagnostic::error(arg.span, format!("invalid alignment `{}`", arg.text))),
+				};
+				match self.section {
+					Section::Data => {
+						while !self.program.data.len().is_multiple_of(align) {
+							self.program.data.push(0);
+						}
+					},
+					Section::Text => {
+						// instructions are always 4 byte aligned, beyond that pad with nops
+						let index = self.program.insts.len() as u32;
+						let text = full_line.trim_start().to_owned();
+						while !(self.program.insts.len() * 4).is_multiple_of(align) {
+							self.program.insts.push(Inst::nop(span));
+							self.program.texts.push(text.clone());
+						}
+						self.program.aligns.push(Align {
+							index,
+							nops: self.program.insts.len() as u32 - index,
+							align: align as u32,
+							span,
+							text,
+							labels: Vec::new(),
+						});
+					},
+				}
+			},
+			".equ" | ".set" => {
+				let [symbol, value] = args else {
+					return Err(Diagnostic::error(span, format!("`{name}` expects a name and a value")));
+				};
+				if !is_identifier(symbol.text) || symbol.text == "." {
+					return Err(Diagnostic::error(symbol.span, format!("invalid symbol name `{}`", symbol.text)));
+				}
+				if let Some(Symbol::Text(_) | Symbol::Data(_)) = self.program.labels.get(symbol.text) {
+					let first = self.label_spans[symbol.text];
+					return Err(Diagnostic::error(
+						symbol.span,
+						format!("`{}` is already a label, defined on line {}", symbol.text, first.line),
+					));
+				}
+				if name == ".equ" && self.program.labels.contains_key(symbol.text) {
+					let first = self.label_spans[symbol.text];
+					return Err(Diagnostic::error(
+						symbol.span,
+						format!("`{}` is already defined on line {}; use `.set` to redefine it", symbol.text, first.line),
+					));
+				}
+				let imm = parse_imm(*value, &self.constants, self.program.xlen)?;
+				match imm {
+					Imm::Value(val) => self.constants.insert(symbol.text.to_owned(), val),
+					_ => self.constants.remove(symbol.text),
+				};
+				self.program.labels.insert(symbol.text.to_owned(), Symbol::Equ(imm, value.span));
+				self.label_spans.insert(symbol.text.to_owned(), symbol.span);
+			},
+			_ => return Err(Diagnostic::error(tokens[0].span, format!("unknown directive `{name}`"))),
+		}
+		Ok(())
+	}
+}
+
+/// Check that a value fits in a `width` byte data directive, either as a signed or unsigned number.
+pub(crate) fn check_data_range(val: i64, width: u32, span: Span) -> Result<(), Diagnostic> {
+	if width >= 8 {
+		return Ok(());
+	}
+	let bits = width * 8;
+	let (min, max) = (-(1i64 << (bits - 1)), (1i64 << bits) - 1);
+	if val < min || val > max {
+		return Err(Diagnostic::error(span, format!("value {val} does not fit in {width} byte(s)")));
+	}
+	Ok(())
+}
+
+/// The line with any `#` comment removed.
+fn strip_comment(line: &str) -> &str {
+	match find_unquoted(line, '#') {
+		Some(i) => &line[..i],
+		None => line,
+	}
+}
+
+/// The index of the first `needle` in `line` that isn't inside a string or character literal.
+fn find_unquoted(line: &str, needle: char) -> Option<usize> {
+	let mut quote = None;
+	let mut escaped = false;
+	for (i, c) in line.char_indices() {
+		match quote {
+			Some(_) if escaped => escaped = false,
+			Some(_) if c == '\\' => escaped = true,
+			Some(q) if c == q => quote = None,
+			Some(_) => {},
+			None if c == needle => return Some(i),
+			None if c == '"' || c == '\'' => quote = Some(c),
+			None => {},
+		}
+	}
+	None
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Token<'a> {
+	text: &'a str,
+	span: Span,
+}
+
+/// Split a line into whitespace or comma separated tokens, keeping string and character literals
+/// whole. Whitespace inside parentheses or next to a binary operator doesn't split, so `%hi(a + b)`
+/// and `label + 4` each stay one token. `offset` is the column that `text` starts at in the original
+/// line.
+fn tokenize(text: &str, line: usize, offset: usize) -> Vec<Token<'_>> {
+	// the byte range of each piece, and whether there was a comma before it
+	let mut pieces = Vec::new();
+	let mut start = None;
+	let mut comma = false;
+	let mut depth = 0usize;
+	let mut rest = text;
+	let mut pos = 0;
+	while let Some(c) = rest.chars().next() {
+		let len = if c == '"' || c == '\'' {
+			// jump past the closing quote, or to the end of the line if there isn't one
+			find_closing_quote(rest).map_or(rest.len(), |end| end + 1)
+		} else {
+			c.len_utf8()
+		};
+		if depth == 0 && (c.is_whitespace() || c == ',') {
+			if let Some(s) = start.take() {
+				pieces.push((s, pos, comma));
+				comma = false;
+			}
+			comma |= c == ',';
 		} else {
-			let inst = parse_line(line);
-			let cinsts = compile::expand_pseudo(&inst);
-			for inst in cinsts {
-				insts.push(inst);
-				texts.push(full_line.trim_start().to_owned());
+			if start.is_none() {
+				start = Some(pos);
+			}
+			match c {
+				'(' => depth += 1,
+				')' => depth = depth.saturating_sub(1),
+				_ => {},
 			}
 		}
+		pos += len;
+		rest = &text[pos..];
+	}
+	if let Some(s) = start {
+		pieces.push((s, text.len(), comma));
+	}
+
+	let mut tokens: Vec<Token<'_>> = Vec::new();
+	for (start, end, comma) in pieces {
+		let piece = &text[start..end];
+		match tokens.last_mut() {
+			Some(last) if !comma && (ends_with_operator(last.text) || starts_with_operator(piece)) => {
+				let last_start = last.span.start - offset;
+				last.text = &text[last_start..end];
+				last.span.end = offset + end;
+			},
+			_ => tokens.push(Token {
+				text: piece,
+				span: Span::new(line, offset + start, offset + end),
+			}),
+		}
 	}
-	(insts, texts, labels)
+	tokens
 }
 
-pub fn parse_line(line: &str) -> Inst {
-	let line = line.to_lowercase();
-	let line = line.replace(',', " ");
-	let name = line.split_whitespace().next().unwrap();
-	let rest = &line[name.len()..];
+fn ends_with_operator(text: &str) -> bool {
+	text.ends_with(['+', '-', '*', '/', '%', '&', '|', '^', '<', '>', '~'])
+}
+
+/// Whether `text` continues an expression from the piece before it. A leading `+` or `-` only counts
+/// when it stands alone, since `addi a0 a0 -1` lists its operands without commas.
+fn starts_with_operator(text: &str) -> bool {
+	matches!(text, "+" | "-" | "%")
+		|| text.starts_with(['*', '/', '&', '|', '^', '<', '>'])
+		|| (text.starts_with('%') && !text[1..].starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+/// The index of the quote that closes the literal `text` starts with.
+fn find_closing_quote(text: &str) -> Option<usize> {
+	let quote = text.chars().next()?;
+	let mut escaped = false;
+	for (i, c) in text.char_indices().skip(1) {
+		if escaped {
+			escaped = false;
+		} else if c == '\\' {
+			escaped = true;
+		} else if c == quote {
+			return Some(i);
+		}
+	}
+	None
+}
+
+fn parse_inst(tokens: &[Token<'_>], constants: &HashMap<String, i64>, xlen: Xlen) -> Result<Inst, Diagnostic> {
+	let span = tokens[0].span.to(tokens[tokens.len() - 1].span);
+	let name = tokens[0].text.to_lowercase();
+	let args = &tokens[1..];
+	let forms = def::PSEUDO_INSTS
+		.iter()
+		.filter(|p| p.name() == name)
+		.map(|p| p.operands())
+		.chain(def::lookup(&name).map(|e| e.operands()));
+	// pick the form taking as many operands as were given, or report against the real instruction
+	let operands = forms
+		.clone()
+		.find(|o| takes_operands(o, args.len()))
+		.or_else(|| forms.last())
+		.ok_or_else(|| Diagnostic::error(tokens[0].span, format!("unknown instruction `{name}`")))?;
+	expect_operands(&name, operands, args, span)?;
 	let mut inst = Inst {
-		name: name.to_owned(),
+		name,
 		rd: None,
 		rs1: None,
 		rs2: None,
+		rs3: None,
+		rm: None,
 		imm: None,
+		span,
 	};
-	match name {
-		"add" | "sub" | "and" | "or" | "xor" | "sll" | "srl" | "sra" | "slt" | "sltu" | "mul" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.rs1 = Some(parse_register(args[1]));
-			inst.rs2 = Some(parse_register(args[2]));
-		},
-		"addi" | "andi" | "ori" | "xori" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.rs1 = Some(parse_register(args[1]));
-			inst.imm = Some(parse_imm(args[2]));
-		},
-		"slli" | "srli" | "srai" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.rs1 = Some(parse_register(args[1]));
-			inst.imm = Some(parse_imm(args[2]));
-		},
-		"lb" | "lbu" | "lh" | "lhu" | "lw" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			let rd = parse_register(args[0]);
-			let other = args[1].split(|c| c == '(' || c == ')').collect::<Vec<_>>();
-			let imm = parse_imm(other[0]);
-			let rs1 = parse_register(other[1]);
-			inst.rd = Some(rd);
-			inst.rs1 = Some(rs1);
-			inst.imm = Some(imm);
-		},
-		"sb" | "sh" | "sw" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			let rs2 = parse_register(args[0]);
-			let other = args[1].split(|c| c == '(' || c == ')').collect::<Vec<_>>();
-			let imm = parse_imm(other[0]);
-			let rs1 = parse_register(other[1]);
-			inst.rs2 = Some(rs2);
-			inst.rs1 = Some(rs1);
-			inst.imm = Some(imm);
-		},
-		"beq" | "bge"  | "bgeu" | "blt" | "bltu" | "bne" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rs1 = Some(parse_register(args[0]));
-			inst.rs2 = Some(parse_register(args[1]));
-			inst.imm = Some(parse_imm(args[2]));
-		},
-		"jal" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			if args.len() == 1 {
-				inst.rd = Some(1);
-				inst.imm = Some(parse_imm(args[0]));
-			} else {
-				inst.rd = Some(parse_register(args[0]));
-				inst.imm = Some(parse_imm(args[1]));
-			}
-		},
-		"jalr" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.rs1 = Some(parse_register(args[1]));
-			inst.imm = Some(parse_imm(args[2]));
-		},
-		"auipc" | "lui" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.imm = Some(parse_imm(args[1]));
-		},
-		"ebreak" => {
-			inst.imm = Some(Imm::Value(0));
-		},
-		"ecall" => {
-			inst.imm = Some(Imm::Value(1));
-		},
-		"beqz" | "bnez" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rs1 = Some(parse_register(args[0]));
-			inst.imm = Some(parse_imm(args[1]));
-		},
-		"j" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.imm = Some(parse_imm(args[0]));
-		},
-		"jr" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rs1 = Some(parse_register(args[0]));
-		},
-		"la" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.imm = Some(parse_imm(args[1]));
-		},
-		"li" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.imm = Some(parse_imm(args[1]));
-		},
-		"mv" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.rs1 = Some(parse_register(args[1]));
-		},
-		"neg" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.rs1 = Some(parse_register(args[1]));
-		},
-		"nop" => {},
-		"not" => {
-			let args = rest.split_whitespace().collect::<Vec<_>>();
-			inst.rd = Some(parse_register(args[0]));
-			inst.rs1 = Some(parse_register(args[1]));
-		},
-		"ret" => {},
-		u => panic!("Unknown instruction {u}"),
+	for (&operand, &token) in operands.iter().zip(args) {
+		match operand {
+			Operand::Rd => inst.rd = Some(parse_register(token)?),
+			Operand::Rs1 => inst.rs1 = Some(parse_register(token)?),
+			Operand::Rs2 => inst.rs2 = Some(parse_register(token)?),
+			Operand::Frd => inst.rd = Some(parse_fp_register(token)?),
+			Operand::Frs1 => inst.rs1 = Some(parse_fp_register(token)?),
+			Operand::Frs2 => inst.rs2 = Some(parse_fp_register(token)?),
+			Operand::Frs3 => inst.rs3 = Some(parse_fp_register(token)?),
+			Operand::Rm => inst.rm = Some(parse_rounding_mode(token)?),
+			Operand::Imm | Operand::Shamt | Operand::Label => inst.imm = Some(parse_imm(token, constants, xlen)?),
+			Operand::Mem => {
+				let (imm, rs1) = parse_mem(token, constants, xlen)?;
+				inst.rs1 = Some(rs1);
+				inst.imm = Some(imm);
+			},
+			Operand::Csr => inst.imm = Some(parse_csr(token, constants, xlen)?),
+			// the immediate of the CSR instructions takes the place of rs1
+			Operand::Uimm => inst.rs1 = Some(parse_uimm(token, constants, xlen)?),
+		}
 	}
-	inst
+	Ok(inst)
+}
+
+/// Whether an instruction with this syntax can be given `count` operands. A rounding mode at the end
+/// may be left out.
+fn takes_operands(operands: &[Operand], count: usize) -> bool {
+	count == operands.len() || (operands.last() == Some(&Operand::Rm) && count + 1 == operands.len())
 }
 
-fn parse_register(s: &str) -> u32 {
-	match s {
-		"zero" => return 0,
-		"ra" => return 1,
-		"sp" => return 2,
-		"gp" => return 3,
-		"tp" => return 4,
-		_ => {},
+/// Check that an instruction was given as many operands as its syntax lists.
+fn expect_operands(name: &str, operands: &[Operand], args: &[Token<'_>], span: Span) -> Result<(), Diagnostic> {
+	let expected = operands.len();
+	if takes_operands(operands, args.len()) {
+		return Ok(());
+	}
+	let span = if args.len() > expected {
+		args[expected].span.to(args[args.len() - 1].span)
+	} else {
+		span
+	};
+	let syntax = operands.iter().map(|o| o.to_string()).collect::<Vec<_>>().join(", ");
+	let usage = format!("{name} {syntax}");
+	let count = if operands.last() == Some(&Operand::Rm) {
+		format!("{} or {expected}", expected - 1)
+	} else {
+		expected.to_string()
 	};
-	let class = s.chars().next().unwrap();
-	let num = s[1..].parse::<u32>().unwrap();
-	if class == 'x' {
-		return num;
+	Err(Diagnostic::error(
+		span,
+		format!("`{name}` expects {count} operand(s) (`{}`), found {}", usage.trim_end(), args.len()),
+	))
+}
+
+fn parse_register(token: Token<'_>) -> Result<u32, Diagnostic> {
+	let s = token.text.to_lowercase();
+	if s == "fp" {
+		return Ok(8);
+	}
+	if let Some(num) = s.strip_prefix('x') {
+		if !num.is_empty() && num.bytes().all(|b| b.is_ascii_digit()) {
+			match num.parse::<u32>() {
+				Ok(num) if num < 32 => return Ok(num),
+				_ => {},
+			}
+		}
 	}
-	for (i, &reg) in crate::def::REG_ALIASES.iter().enumerate() {
+	for (i, &reg) in def::REG_ALIASES.iter().enumerate() {
 		if reg == s {
-			return i as u32;
+			return Ok(i as u32);
 		}
 	}
-	
-	panic!("invalid register {s}");
+	Err(Diagnostic::error(token.span, format!("invalid register `{}`", token.text)))
 }
 
-fn parse_imm(s: &str) -> Imm {
-	if s.as_bytes()[0].is_ascii_alphabetic() {
-		return Imm::Label(s.to_owned());
+fn parse_fp_register(token: Token<'_>) -> Result<u32, Diagnostic> {
+	let s = token.text.to_lowercase();
+	if let Some(num) = s.strip_prefix('f') {
+		if !num.is_empty() && num.bytes().all(|b| b.is_ascii_digit()) {
+			match num.parse::<u32>() {
+				Ok(num) if num < 32 => return Ok(num),
+				_ => {},
+			}
+		}
+	}
+	for (i, &reg) in def::FREG_ALIASES.iter().enumerate() {
+		if reg == s {
+			return Ok(i as u32);
+		}
+	}
+	Err(Diagnostic::error(token.span, format!("invalid floating-point register `{}`", token.text)))
+}
+
+fn parse_rounding_mode(token: Token<'_>) -> Result<u32, Diagnostic> {
+	let s = token.text.to_lowercase();
+	match def::ROUNDING_MODES.iter().find(|&&(name, _)| name == s) {
+		Some(&(_, rm)) => Ok(rm),
+		None => Err(Diagnostic::error(
+			token.span,
+			format!("invalid rounding mode `{}`, expected rne, rtz, rdn, rup, rmm or dyn", token.text),
+		)),
+	}
+}
+
+/// Parse a memory operand of the form `offset(register)`. The offset may be left out.
+fn parse_mem(token: Token<'_>, constants: &HashMap<String, i64>, xlen: Xlen) -> Result<(Imm, u32), Diagnostic> {
+	let error = || Diagnostic::error(token.span, format!("expected `offset(register)`, found `{}`", token.text));
+	// the offset can have parentheses of its own, the register is in the last pair
+	let open = token.text.rfind('(').ok_or_else(error)?;
+	let inner = token.text[open + 1..].strip_suffix(')').ok_or_else(error)?;
+	let span = token.span;
+	let imm = if open == 0 {
+		Imm::Value(0)
 	} else {
-		// TODO
-		return Imm::Value(s.parse().unwrap())
+		let text = &token.text[..open];
+		parse_imm(
+			Token {
+				text,
+				span: Span::new(span.line, span.start, span.start + open),
+			},
+			constants,
+			xlen,
+		)?
+	};
+	let reg = parse_register(Token {
+		text: inner,
+		span: Span::new(span.line, span.start + open + 1, span.end - 1),
+	})?;
+	Ok((imm, reg))
+}
+
+/// Parse a CSR given by name or as a number.
+fn parse_csr(token: Token<'_>, constants: &HashMap<String, i64>, xlen: Xlen) -> Result<Imm, Diagnostic> {
+	match def::csr_number(&token.text.to_lowercase()) {
+		Some(num) => Ok(Imm::Value(num as i64)),
+		None => parse_imm(token, constants, xlen),
+	}
+}
+
+/// Parse the 5 bit immediate of a CSR instruction, which has to be known right away since it's
+/// encoded in place of a register.
+fn parse_uimm(token: Token<'_>, constants: &HashMap<String, i64>, xlen: Xlen) -> Result<u32, Diagnostic> {
+	match parse_imm(token, constants, xlen)? {
+		Imm::Value(val @ 0..=31) => Ok(val as u32),
+		_ => Err(Diagnostic::error(token.span, format!("`{}` is not a constant from 0 to 31", token.text))),
 	}
-}
\ No newline at end of file
+}
+
+/// Parse an operand expression, replacing any symbols in `constants` with their values.
+fn parse_imm(token: Token<'_>, constants: &HashMap<String, i64>, xlen: Xlen) -> Result<Imm, Diagnostic> {
+	let mut parser = ExprParser {
+		token,
+		pos: 0,
+		constants,
+		xlen,
+	};
+	let imm = parser.expr(0)?;
+	parser.skip_whitespace();
+	match parser.rest().chars().next() {
+		None => Ok(imm),
+		Some(')') => Err(parser.error(parser.pos, parser.pos + 1, "unmatched `)`")),
+		Some(c) => Err(parser.error(parser.pos, parser.pos + c.len_utf8(), format!("unexpected `{c}`"))),
+	}
+}
+
+/// Recursive descent parser for operand expressions.
+struct ExprParser<'a> {
+	token: Token<'a>,
+	pos: usize,
+	constants: &'a HashMap<String, i64>,
+	xlen: Xlen,
+}
+
+impl<'a> ExprParser<'a> {
+	fn rest(&self) -> &'a str {
+		&self.token.text[self.pos..]
+	}
+
+	fn skip_whitespace(&mut self) {
+		self.pos = self.token.text.len() - self.rest().trim_start().len();
+	}
+
+	/// The part of the expression from `start` to `end` as its own token.
+	fn sub_token(&self, start: usize, end: usize) -> Token<'a> {
+		let span = self.token.span;
+		Token {
+			text: &self.token.text[start..end],
+			span: Span::new(span.line, span.start + start, span.start + end),
+		}
+	}
+
+	fn error(&self, start: usize, end: usize, message: impl std::fmt::Display) -> Diagnostic {
+		let span = self.sub_token(start, end).span;
+		Diagnostic::error(span, format!("invalid immediate `{}`: {message}", self.token.text))
+	}
+
+	/// Parse a chain of binary operators that bind at least as tightly as `min_precedence`.
+	fn expr(&mut self, min_precedence: u8) -> Result<Imm, Diagnostic> {
+		let mut lhs = self.unary()?;
+		loop {
+			self.skip_whitespace();
+			let Some((op, len)) = self.binary_op() else {
+				break;
+			};
+			if op.precedence() < min_precedence {
+				break;
+			}
+			let start = self.pos;
+			self.pos += len;
+			let rhs = self.expr(op.precedence() + 1)?;
+			lhs = Imm::binary(op, lhs, rhs, self.xlen).ok_or_else(|| self.error(start, start + len, "division by zero"))?;
+		}
+		Ok(lhs)
+	}
+
+	fn binary_op(&self) -> Option<(BinaryOp, usize)> {
+		use BinaryOp::*;
+		const OPS: &[(&str, BinaryOp)] = &[
+			("<<", Shl),
+			(">>", Shr),
+			("*", Mul),
+			("/", Div),
+			("%", Rem),
+			("+", Add),
+			("-", Sub),
+			("&", And),
+			("^", Xor),
+			("|", Or),
+		];
+		OPS.iter().find(|(text, _)| self.rest().starts_with(text)).map(|&(text, op)| (op, text.len()))
+	}
+
+	fn unary(&mut self) -> Result<Imm, Diagnostic> {
+		self.skip_whitespace();
+		let rest = self.rest();
+		let op = match rest.chars().next() {
+			// keep the sign on negative literals so that their range is checked as written
+			Some('-') if rest[1..].starts_with(|c: char| c.is_ascii_digit()) => return self.number(),
+			Some('-') => UnaryOp::Neg,
+			Some('~') => UnaryOp::Not,
+			Some('+') => {
+				self.pos += 1;
+				return self.unary();
+			},
+			_ => return self.primary(),
+		};
+		self.pos += 1;
+		Ok(Imm::unary(op, self.unary()?, self.xlen))
+	}
+
+	fn primary(&mut self) -> Result<Imm, Diagnostic> {
+		self.skip_whitespace();
+		let start = self.pos;
+		let rest = self.rest();
+		match rest.chars().next() {
+			None if start == 0 => Err(Diagnostic::error(self.token.span, "expected an immediate")),
+			None => Err(self.error(start, start, "expected a value")),
+			Some(c) if c.is_ascii_digit() => self.number(),
+			Some('\'') => {
+				let len = find_closing_quote(rest).map_or(rest.len(), |end| end + 1);
+				self.pos += len;
+				parse_char(self.sub_token(start, start + len)).map(Imm::Value)
+			},
+			Some('(') => {
+				self.pos += 1;
+				let imm = self.expr(0)?;
+				self.expect_close(start)?;
+				Ok(imm)
+			},
+			Some('%') => {
+				let name_len = rest[1..].find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len() - 1);
+				let name = &rest[1..1 + name_len];
+				let reloc = match name {
+					"hi" => Reloc::Hi,
+					"lo" => Reloc::Lo,
+					"pcrel_hi" => Reloc::PcrelHi,
+					"pcrel_lo" => Reloc::PcrelLo,
+					_ => return Err(self.error(start, start + 1 + name_len, format!("unknown operator `%{name}`"))),
+				};
+				self.pos += 1 + name_len;
+				if !self.rest().starts_with('(') {
+					return Err(self.error(start, self.pos, format!("expected `(` after `%{name}`")));
+				}
+				let open = self.pos;
+				self.pos += 1;
+				let operand = self.expr(0)?;
+				self.expect_close(open)?;
+				Ok(Imm::reloc(reloc, operand))
+			},
+			Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+				let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')).unwrap_or(rest.len());
+				let name = &rest[..len];
+				self.pos += len;
+				Ok(match self.constants.get(name) {
+					Some(&val) => Imm::Value(val),
+					None => Imm::Label(name.to_owned()),
+				})
+			},
+			Some(c) => Err(self.error(start, start + c.len_utf8(), format!("unexpected `{c}`"))),
+		}
+	}
+
+	/// Parse an integer literal, with its sign if it has one.
+	fn number(&mut self) -> Result<Imm, Diagnostic> {
+		let start = self.pos;
+		let rest = self.rest();
+		let sign = if rest.starts_with('-') { 1 } else { 0 };
+		let len = rest[sign..].find(|c: char| !c.is_ascii_alphanumeric() && c != '_').map_or(rest.len(), |len| len + sign);
+		self.pos += len;
+		parse_int(self.sub_token(start, start + len), self.xlen).map(Imm::Value)
+	}
+
+	fn expect_close(&mut self, open: usize) -> Result<(), Diagnostic> {
+		self.skip_whitespace();
+		if self.rest().starts_with(')') {
+			self.pos += 1;
+			Ok(())
+		} else {
+			Err(self.error(open, open + 1, "unclosed `(`"))
+		}
+	}
+}
+
+/// Parse an integer literal in decimal, hexadecimal (`0x`), binary (`0b`) or octal (`0o` or just `0`), with an
+/// optional sign. Anything that fits in `xlen` bits as either a signed or an unsigned number is
+/// accepted, so on RV32 `0xffffffff` is the same as `-1`.
+fn parse_int(token: Token<'_>, xlen: Xlen) -> Result<i64, Diagnostic> {
+	let s = token.text;
+	let (negative, unsigned) = match s.strip_prefix('-') {
+		Some(rest) => (true, rest),
+		None => (false, s.strip_prefix('+').unwrap_or(s)),
+	};
+	let prefix = unsigned.get(..2).map(str::to_ascii_lowercase);
+	let (radix, digits) = match prefix.as_deref() {
+		Some("0x") => (16, &unsigned[2..]),
+		Some("0b") => (2, &unsigned[2..]),
+		Some("0o") => (8, &unsigned[2..]),
+		// a leading zero means octal, like in the GNU assembler
+		_ if unsigned.len() > 1 && unsigned.starts_with('0') => (8, &unsigned[1..]),
+		_ => (10, unsigned),
+	};
+	if digits.is_empty() {
+		return Err(Diagnostic::error(token.span, format!("invalid immediate `{s}`")));
+	}
+	if let Some(c) = digits.chars().find(|c| !c.is_digit(radix)) {
+		let kind = match radix {
+			16 => "a hexadecimal",
+			2 => "a binary",
+			8 => "an octal",
+			_ => "a decimal",
+		};
+		return Err(Diagnostic::error(token.span, format!("invalid immediate `{s}`: `{c}` is not {kind} digit")));
+	}
+	let bits = xlen.bits();
+	let too_large = || Diagnostic::error(token.span, format!("literal `{s}` does not fit in {bits} bits"));
+	let magnitude = i128::from_str_radix(digits, radix).map_err(|_| too_large())?;
+	let value = if negative { -magnitude } else { magnitude };
+	if !(-(1i128 << (bits - 1))..1i128 << bits).contains(&value) {
+		return Err(too_large());
+	}
+	Ok(xlen.wrap(value as i64))
+}
+
+/// Parse a character literal like `'a'` or `'\n'` into its byte value.
+fn parse_char(token: Token<'_>) -> Result<i64, Diagnostic> {
+	let body = token.text.strip_prefix('\'').and_then(|s| s.strip_suffix('\''));
+	let body = match body {
+		Some(body) if token.text.len() >= 2 => body,
+		_ => return Err(Diagnostic::error(token.span, format!("unterminated character literal `{}`", token.text))),
+	};
+	match unescape(body, token.span)?[..] {
+		[byte] => Ok(byte as i64),
+		[] => Err(Diagnostic::error(token.span, "empty character literal")),
+		_ => Err(Diagnostic::error(token.span, format!("character literal `{}` must be a single byte", token.text))),
+	}
+}
+
+/// Parse a double quoted string literal into the bytes it stands for.
+fn parse_string(token: Token<'_>) -> Result<Vec<u8>, Diagnostic> {
+	let body = token.text.strip_prefix('"').and_then(|s| s.strip_suffix('"'));
+	match body {
+		Some(body) if token.text.len() >= 2 => unescape(body, token.span),
+		_ => Err(Diagnostic::error(token.span, format!("expected a string, found `{}`", token.text))),
+	}
+}
+
+/// Replace the backslash escapes in the body of a string or character literal.
+fn unescape(body: &str, span: Span) -> Result<Vec<u8>, Diagnostic> {
+	let mut bytes = Vec::new();
+	let mut chars = body.chars();
+	while let Some(c) = chars.next() {
+		if c != '\\' {
+			let mut buf = [0; 4];
+			bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+			continue;
+		}
+		let byte = match chars.next() {
+			Some('n') => b'\n',
+			Some('t') => b'\t',
+			Some('r') => b'\r',
+			Some('0') => 0,
+			Some('\\') => b'\\',
+			Some('"') => b'"',
+			Some('\'') => b'\'',
+			Some('x') => {
+				let hex = chars.as_str().get(..2).unwrap_or("");
+				let byte = u8::from_str_radix(hex, 16)
+					.map_err(|_| Diagnostic::error(span, format!("invalid escape `\\x{hex}`")))?;
+				chars.nth(1);
+				byte
+			},
+			Some(c) => return Err(Diagnostic::error(span, format!("unknown escape `\\{c}`"))),
+			None => return Err(Diagnostic::error(span, "unterminated escape")),
+		};
+		bytes.push(byte);
+	}
+	Ok(bytes)
+}
+
+fn is_identifier(s: &str) -> bool {
+	let mut chars = s.chars();
+	match chars.next() {
+		Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {},
+		_ => return false,
+	}
+	chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$')
+}
+
+#[test]
+fn test_parse_diagnostics() {
+	let source = "
+	addi t0 x0 1
+	fadd t0 t0 t1
+	add t0 t1
+	addi t0 x32 4
+	lw t0 8[sp]
+	addi t0 t0 12a
+	";
+	let diags = parse(source).unwrap_err();
+	let found = diags.iter().map(|d| (d.span.line, d.span.start, d.span.end)).collect::<Vec<_>>();
+	assert_eq!(found, &[(3, 1, 5), (4, 1, 10), (5, 9, 12), (6, 7, 12), (7, 12, 15)]);
+	assert!(diags[0].message.contains("unknown instruction `fadd`"));
+	assert!(diags[1].message.contains("expects 3 operand(s)"));
+	assert!(diags[2].message.contains("invalid register `x32`"));
+	assert!(diags[3].message.contains("expected `offset(register)`"));
+	assert!(diags[4].message.contains("invalid immediate `12a`"));
+}
+
+#[test]
+fn test_parse_label_with_instruction() {
+	let program = parse("start: addi t0 x0 1\nLoop:\n\tj Loop").unwrap();
+	assert_eq!(program.insts.len(), 2);
+	assert_eq!(program.texts[0], "start: addi t0 x0 1");
+	assert_eq!(program.labels["start"], Symbol::Text(0));
+	assert_eq!(program.labels["Loop"], Symbol::Text(1));
+	assert_eq!(program.insts[1].imm, Some(Imm::Label("Loop".to_owned())));
+}
+
+#[test]
+fn test_duplicate_label() {
+	let diags = parse("a:\nnop\nb: nop\n  a: nop").unwrap_err();
+	assert_eq!(diags.len(), 1);
+	assert_eq!(diags[0].span, Span::new(4, 2, 3));
+	assert_eq!(diags[0].message, "duplicate label `a`, first defined on line 1");
+}
+
+#[test]
+fn test_data_directives() {
+	let source = r#"
+	.data
+nums:	.word 1, -2, end
+	.half 65520, 7
+bytes:	.byte 255, -1, 120
+	.align 2
+msg:	.asciiz "a: b # \"c\"\n"
+	.ascii "xy"
+	.string "\x41"
+	.space 3
+end:
+	.globl main
+	.text
+main:	la a0, msg
+	"#;
+	let program = parse(source).unwrap();
+	assert_eq!(program.labels["nums"], Symbol::Data(0));
+	assert_eq!(program.labels["bytes"], Symbol::Data(16));
+	assert_eq!(program.labels["msg"], Symbol::Data(20));
+	assert_eq!(program.labels["end"], Symbol::Data(39));
+	assert_eq!(program.labels["main"], Symbol::Text(0));
+	assert_eq!(program.globals, &["main"]);
+	assert_eq!(&program.data[..16], &[1, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff, 0, 0, 0, 0, 0xf0, 0xff, 7, 0]);
+	assert_eq!(&program.data[16..20], &[255, 255, 120, 0]);
+	assert_eq!(&program.data[20..], b"a: b # \"c\"\n\0xyA\0\0\0\0");
+	assert_eq!(program.fixups, &[DataFixup { offset: 8, width: 4, imm: Imm::Label("end".to_owned()), span: Span::new(3, 19, 22) }]);
+	assert_eq!(program.insts.len(), 2);
+}
+
+#[test]
+fn test_directive_errors() {
+	let source = "
+	.word 1
+	.data
+	add a0 a0 a1
+	.byte 256
+	.asciiz hello
+	.align 3 4
+	.foo
+	";
+	let diags = parse(source).unwrap_err();
+	let messages = diags.iter().map(|d| (d.span.line, &*d.message)).collect::<Vec<_>>();
+	assert_eq!(
+		messages,
+		&[
+			(2, "`.word` is only allowed in the data section, use `.data` first"),
+			(4, "instructions must be in the text section"),
+			(5, "value 256 does not fit in 1 byte(s)"),
+			(6, "expected a string, found `hello`"),
+			(7, "`.align` expects 1 operand, found 2"),
+			(8, "unknown directive `.foo`"),
+		]
+	);
+}
+
+#[test]
+fn test_literals() {
+	let imm = |text| {
+		let token = Token {
+			text,
+			span: Span::new(1, 0, text.len()),
+		};
+		parse_imm(token, &HashMap::new(), Xlen::Rv32)
+	};
+	let value = |text| match imm(text) {
+		Ok(Imm::Value(val)) => val,
+		other => panic!("{text}: {other:?}"),
+	};
+	let error = |text| imm(text).unwrap_err().message;
+	assert_eq!(value("42"), 42);
+	assert_eq!(value("-42"), -42);
+	assert_eq!(value("+7"), 7);
+	assert_eq!(value("0x10"), 16);
+	assert_eq!(value("0XfF"), 255);
+	assert_eq!(value("-0x1"), -1);
+	assert_eq!(value("0b1010"), 10);
+	assert_eq!(value("0o17"), 15);
+	assert_eq!(value("017"), 15);
+	assert_eq!(value("-010"), -8);
+	assert_eq!(value("0"), 0);
+	assert_eq!(value("0xffffffff"), -1);
+	assert_eq!(value("-2147483648"), i32::MIN.into());
+	assert_eq!(value("'a'"), 97);
+	assert_eq!(value("' '"), 32);
+	assert_eq!(value("'\\n'"), 10);
+	assert_eq!(value("'\\''"), 39);
+	assert_eq!(value("'\\x41'"), 65);
+	assert_eq!(imm("loop").unwrap(), Imm::Label("loop".to_owned()));
+	assert_eq!(error("0x"), "invalid immediate `0x`");
+	assert_eq!(error("0x1g"), "invalid immediate `0x1g`: `g` is not a hexadecimal digit");
+	assert_eq!(error("0b102"), "invalid immediate `0b102`: `2` is not a binary digit");
+	assert_eq!(error("12a"), "invalid immediate `12a`: `a` is not a decimal digit");
+	assert_eq!(error("09"), "invalid immediate `09`: `9` is not an octal digit");
+	assert_eq!(error("0x100000000"), "literal `0x100000000` does not fit in 32 bits");
+	assert_eq!(error("-2147483649"), "literal `-2147483649` does not fit in 32 bits");
+	assert_eq!(error("99999999999999999999999"), "literal `99999999999999999999999` does not fit in 32 bits");
+	assert_eq!(error("'ab'"), "character literal `'ab'` must be a single byte");
+	assert_eq!(error("''"), "empty character literal");
+	assert_eq!(error("'a"), "unterminated character literal `'a`");
+
+	// literals work as operands and in data, and `#` in a character literal isn't a comment
+	let program = parse("addi a0, zero, '#'\nlw t0, 0x10(sp)\n.data\n.word 0xdeadbeef\n.byte '\\n'").unwrap();
+	assert_eq!(program.insts[0].imm, Some(Imm::Value(35)));
+	assert_eq!(program.insts[1].imm, Some(Imm::Value(16)));
+	assert_eq!(program.data, &[0xef, 0xbe, 0xad, 0xde, 10]);
+}
+
+#[test]
+fn test_expressions() {
+	let imm = |text: &str| parse(&format!("addi a0, a0, {text}")).map(|program| program.insts[0].imm.clone().unwrap());
+	let value = |text| match imm(text) {
+		Ok(Imm::Value(val)) => val,
+		other => panic!("{text}: {other:?}"),
+	};
+	let error = |text| imm(text).unwrap_err().remove(0);
+	assert_eq!(value("2 + 3 * 4"), 14);
+	assert_eq!(value("(2 + 3) * 4"), 20);
+	assert_eq!(value("1 << 4 | 3"), 19);
+	assert_eq!(value("0xff & ~0xf ^ 1"), 0xf1);
+	assert_eq!(value("-(7) / 2"), -3);
+	assert_eq!(value("-7 % 3"), -1);
+	assert_eq!(value("-8 >> 1"), -4);
+	assert_eq!(value("%hi(0x12345fff)"), 0x12346);
+	assert_eq!(value("%lo(0x12345fff)"), -1);
+	assert_eq!(
+		imm("label+8").unwrap(),
+		Imm::Binary(BinaryOp::Add, Box::new(Imm::Label("label".to_owned())), Box::new(Imm::Value(8)))
+	);
+	assert_eq!(imm("%lo(buf)").unwrap(), Imm::Reloc(Reloc::Lo, Box::new(Imm::Label("buf".to_owned()))));
+
+	let diag = error("4 / (2 - 2)");
+	assert_eq!(diag.message, "invalid immediate `4 / (2 - 2)`: division by zero");
+	assert_eq!(diag.span, Span::new(1, 15, 16));
+	assert_eq!(error("(1 + 2").message, "invalid immediate `(1 + 2`: unclosed `(`");
+	assert_eq!(error("1 + 2)").message, "invalid immediate `1 + 2)`: unmatched `)`");
+	assert_eq!(error("%high(x)").message, "invalid immediate `%high(x)`: unknown operator `%high`");
+	assert_eq!(error("1 +").message, "invalid immediate `1 +`: expected a value");
+
+	// the memory operand's register is the last parenthesized part
+	let program = parse("lw a0, %lo(buf)(a1)\nsw a0, (4 + 4) * 2(sp)").unwrap();
+	assert_eq!(program.insts[0].rs1, Some(11));
+	assert_eq!(program.insts[1].imm, Some(Imm::Value(16)));
+	assert_eq!(program.insts[1].rs1, Some(2));
+}
+
+#[test]
+fn test_equ() {
+	let source = "
+	.equ SIZE, 16
+	.set COUNT, SIZE / 4
+	li a0, SIZE * COUNT
+	.set COUNT, 1
+	addi a1, zero, COUNT
+	.equ END, buf + SIZE
+	addi a2, zero, LATER
+	.equ LATER, 5
+buf:
+	";
+	let program = parse(source).unwrap();
+	assert_eq!(program.insts[0].imm, Some(Imm::Value(64)));
+	assert_eq!(program.insts[1].imm, Some(Imm::Value(1)));
+	assert_eq!(program.insts[2].imm, Some(Imm::Label("LATER".to_owned())));
+	assert_eq!(program.labels["COUNT"], Symbol::Equ(Imm::Value(1), Span::new(5, 13, 14)));
+	assert!(matches!(program.labels["END"], Symbol::Equ(Imm::Binary(..), _)));
+
+	let diags = parse("x: nop\n.equ x, 1\n.equ y 1 2\n.equ 3, 1\n.equ z, 1\nz: nop\n.set w, x\n.equ w, 2").unwrap_err();
+	let messages = diags.iter().map(|d| &*d.message).collect::<Vec<_>>();
+	assert_eq!(
+		messages,
+		&[
+			"`x` is already a label, defined on line 1",
+			"`.equ` expects a name and a value",
+			"invalid symbol name `3`",
+			"duplicate label `z`, first defined on line 5",
+			"`w` is already defined on line 7; use `.set` to redefine it",
+		]
+	);
+}
@@ -1,9 +1,10 @@
 mod utils;
 
 use risclang::Instruction;
+use risclang::diag::Diagnostic;
 use serde::Serialize;
 use wasm_bindgen::prelude::*;
-use std::{convert::{TryFrom}};
+use std::convert::TryInto;
 #[wasm_bindgen(module = "src/lib/shims")]
 extern {
     fn wasm_print(text: &str);
@@ -15,7 +16,7 @@ pub fn print(text: String) {
 
 #[wasm_bindgen]
 pub fn test() -> u32 {
-    return 200;
+    200
 }
 
 #[derive(Serialize)]
@@ -24,51 +25,263 @@ pub struct CodeItem {
     text: String,
 }
 
-#[wasm_bindgen]
-pub fn compile(source: &str) -> JsValue {
-    let (insts, texts, labels) = risclang::parse::parse(source);
-    let code = risclang::compile::compile(insts, &labels);
-    assert_eq!(code.len(), texts.len());
-    let items = (0..code.len()).map(|i| CodeItem { code: code[i].0, text: texts[i].clone() }).collect::<Vec<_>>();
+#[derive(Serialize)]
+pub struct CompileOutput {
+    code: Vec<CodeItem>,
+    text_base: u32,
+    data: Vec<u8>,
+    data_base: u32,
+}
+
+#[derive(Serialize)]
+pub struct DiagnosticItem {
+    line: usize,
+    start: usize,
+    end: usize,
+    severity: String,
+    message: String,
+}
+
+impl From<&Diagnostic> for DiagnosticItem {
+    fn from(diag: &Diagnostic) -> Self {
+        Self {
+            line: diag.span.line,
+            start: diag.span.start,
+            end: diag.span.end,
+            severity: diag.severity.to_string(),
+            message: diag.message.clone(),
+        }
+    }
+}
+
+fn diagnostics_to_js(diags: &[Diagnostic]) -> JsValue {
+    let items = diags.iter().map(DiagnosticItem::from).collect::<Vec<_>>();
     serde_wasm_bindgen::to_value(&items).unwrap()
 }
 
+/// Assemble `source` for RV32, or RV64 if `rv64` is set. On failure the returned error is the list
+/// of diagnostics found in the source.
 #[wasm_bindgen]
-pub struct Machine {
-    inner: riscvm::Machine,
+pub fn compile(source: &str, rv64: bool) -> Result<JsValue, JsValue> {
+    let program = risclang::parse::parse_xlen(source, xlen(rv64)).map_err(|diags| diagnostics_to_js(&diags))?;
+    let texts = program.texts.clone();
+    let image = risclang::compile::compile(program, &Default::default()).map_err(|diags| diagnostics_to_js(&diags))?;
+    assert_eq!(image.text.len(), texts.len());
+    let code = image.text.iter().zip(texts).map(|(inst, text)| CodeItem { code: inst.0, text }).collect::<Vec<_>>();
+    let output = CompileOutput { code, text_base: image.text_base, data: image.data, data_base: image.data_base };
+    Ok(serde_wasm_bindgen::to_value(&output).unwrap())
 }
 
+/// Disassemble an instruction word located at `pc`, optionally showing pseudo instructions.
 #[wasm_bindgen]
-pub struct ExecResult {
-    pub a0: i32,
-    pub a1: i32,
+pub fn disassemble(inst: u32, pc: u32, pseudo: bool) -> String {
+    let options = risclang::disasm::Options { pseudo, symbols: None };
+    Instruction(inst).disassemble(pc, &options)
+}
+
+fn xlen(rv64: bool) -> risclang::def::Xlen {
+    if rv64 { risclang::def::Xlen::Rv64 } else { risclang::def::Xlen::Rv32 }
+}
+
+/// The environment calls a program gets to make.
+enum Syscalls {
+    Venus(riscvm::Venus),
+    Linux(riscvm::Linux),
+}
+
+impl Syscalls {
+    fn for_image(linux: bool, image: &risclang::compile::Image) -> Self {
+        if linux {
+            Syscalls::Linux(riscvm::Linux::for_image(image))
+        } else {
+            Syscalls::Venus(riscvm::Venus::for_image(riscvm::Convention::Venus, image))
+        }
+    }
+
+    fn input(&mut self) -> &mut std::collections::VecDeque<u8> {
+        match self {
+            Syscalls::Venus(venus) => &mut venus.input,
+            Syscalls::Linux(linux) => &mut linux.input,
+        }
+    }
+
+    fn take_output(&mut self) -> Vec<u8> {
+        match self {
+            Syscalls::Venus(venus) => venus.take_output(),
+            Syscalls::Linux(linux) => linux.take_output(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct Machine {
+    inner: riscvm::Machine,
+    uart: riscvm::Uart,
+    syscalls: Syscalls,
+    linux: bool,
+    text_base: u32,
+    text_len: usize,
 }
 
 #[wasm_bindgen]
 impl Machine {
-    pub fn new(memory: usize) -> Self {
+    /// Create a machine with `memory` bytes of RAM that runs RV64 code if `rv64` is set, or RV32 code
+    /// otherwise.
+    pub fn new(memory: usize, rv64: bool) -> Self {
+        Self::with_ram(0, memory, rv64)
+    }
+
+    /// Like `new`, but with the RAM starting at `ram_base`.
+    pub fn with_ram(ram_base: u32, memory: usize, rv64: bool) -> Self {
         utils::set_panic_hook();
-        let inner = riscvm::Machine::new(memory);
+        let mut inner = riscvm::Machine::with_ram(ram_base, memory, xlen(rv64));
+        let uart = riscvm::Uart::new();
+        inner.bus.map(riscvm::UART_BASE, riscvm::UART_SIZE, uart.clone());
+        inner.attach_clint();
         Self {
             inner,
+            uart,
+            syscalls: Syscalls::Venus(riscvm::Venus::new(riscvm::Convention::Venus, 0)),
+            linux: false,
+            text_base: 0,
+            text_len: 0,
+        }
+    }
+
+    /// Create an RV32 machine for a statically linked executable, with `memory` bytes of RAM starting
+    /// at the page of its lowest segment, and load it like `load_elf`.
+    pub fn for_elf(file: &[u8], memory: usize) -> Result<Machine, JsValue> {
+        let elf = riscvm::Elf::parse(file).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        let base = elf.segments.iter().map(|segment| segment.vaddr).min().unwrap_or(0) & !0xfff;
+        let (start, end) = (base as u64, base as u64 + memory as u64);
+        let devices = [(riscvm::UART_BASE, riscvm::UART_SIZE), (riscvm::CLINT_BASE, riscvm::CLINT_SIZE)];
+        let overlaps = |&(device, size): &(u32, u32)| start < (device + size) as u64 && (device as u64) < end;
+        if end > 1 << 32 || devices.iter().any(overlaps) {
+            return Err(JsValue::from_str(&format!("RAM at {base:#x} would overlap a device or leave the address space")));
         }
+        let mut machine = Self::with_ram(base, memory, false);
+        machine.load_parsed_elf(&elf)?;
+        Ok(machine)
     }
     
-    pub fn get_instruction_index(&self) -> usize {
-        print(format!("pc {}", self.inner.pc));
-        usize::try_from(self.inner.pc).unwrap() / 4
+    /// The index of the loaded instruction at the pc, or nothing if the pc is below the text.
+    pub fn get_instruction_index(&self) -> Option<usize> {
+        (self.inner.pc as u32).checked_sub(self.text_base).map(|offset| offset as usize / 4)
     }
     
-    pub fn get_registers(&self) -> Vec<i32> {
+    pub fn get_registers(&self) -> Vec<i64> {
         self.inner.regs.to_vec()
     }
+
+    /// The floating-point registers as numbers. NaN-boxed single precision values show as the single
+    /// they hold, anything else as a double.
+    pub fn get_fp_registers(&self) -> Vec<f64> {
+        self.inner
+            .fregs
+            .iter()
+            .map(|&bits| match bits >> 32 {
+                0xffff_ffff => f32::from_bits(bits as u32).into(),
+                _ => f64::from_bits(bits),
+            })
+            .collect()
+    }
+
+    /// The raw bits of the floating-point registers.
+    pub fn get_fp_register_bits(&self) -> Vec<u64> {
+        self.inner.fregs.to_vec()
+    }
     
+    /// Turn the M extension on or off, so that exercises can require a software multiply.
+    pub fn set_m_extension(&mut self, enabled: bool) {
+        self.inner.extensions.m = enabled;
+    }
+
+    /// Handle environment calls like Linux does instead of like Venus. Takes effect when the next
+    /// image is loaded.
+    pub fn set_linux_syscalls(&mut self, enabled: bool) {
+        self.linux = enabled;
+    }
+
+    /// Load assembled text and data into memory at their base addresses and jump to the text.
+    pub fn load_image(&mut self, text_base: u32, text: &[u32], data_base: u32, data: &[u8]) -> Result<(), JsValue> {
+        let image = risclang::compile::Image {
+            text_base,
+            text: text.iter().map(|&inst| Instruction(inst)).collect(),
+            data_base,
+            data: data.to_vec(),
+            symbols: Default::default(),
+        };
+        self.syscalls = Syscalls::for_image(self.linux, &image);
+        self.text_base = text_base;
+        self.text_len = text.len();
+        self.inner.load_image(&image).map_err(|err| JsValue::from_str(&err.to_string()))
+    }
+
+    /// Load a statically linked RV32 executable, which always gets Linux environment calls. Returns
+    /// its symbols by name, for showing addresses as labels.
+    pub fn load_elf(&mut self, file: &[u8]) -> Result<JsValue, JsValue> {
+        let elf = riscvm::Elf::parse(file).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        self.load_parsed_elf(&elf)?;
+        Ok(serde_wasm_bindgen::to_value(&elf.symbols).unwrap())
+    }
+
+    /// The address of the loaded text, which for an executable is the segment holding its entry point.
+    pub fn get_text_base(&self) -> u32 {
+        self.text_base
+    }
+
+    /// The instructions of the loaded text as they are now in memory.
+    pub fn get_text(&self) -> Vec<u32> {
+        let bytes = self.inner.peek_bytes(self.text_base, self.text_len * 4);
+        bytes.chunks_exact(4).map(|word| u32::from_le_bytes(word.try_into().unwrap())).collect()
+    }
+
+    fn load_parsed_elf(&mut self, elf: &riscvm::Elf) -> Result<(), JsValue> {
+        self.inner.load_elf(elf).map_err(|err| JsValue::from_str(&err.to_string()))?;
+        let end = elf.segments.iter().map(|segment| segment.vaddr.wrapping_add(segment.mem_size)).max().unwrap_or(0);
+        self.syscalls = Syscalls::Linux(riscvm::Linux::new(end.wrapping_add(15) & !15));
+        let text = elf.segments.iter().find(|segment| elf.entry.wrapping_sub(segment.vaddr) < segment.mem_size);
+        self.text_base = text.map_or(elf.entry, |segment| segment.vaddr);
+        self.text_len = text.map_or(0, |segment| segment.data.len() / 4);
+        Ok(())
+    }
+
+    /// Queue input for the program to read from the UART.
+    pub fn push_input(&mut self, input: &str) {
+        self.uart.push_input(input.as_bytes());
+    }
+
+    /// Queue input for the program to read with environment calls.
+    pub fn push_stdin(&mut self, input: &str) {
+        self.syscalls.input().extend(input.as_bytes());
+    }
+
+    /// Take what the program printed, through environment calls or the UART, since the last call.
+    pub fn take_output(&mut self) -> String {
+        let mut output = self.syscalls.take_output();
+        output.extend(self.uart.take_output());
+        String::from_utf8_lossy(&output).into_owned()
+    }
+
     pub fn get_memory_view(&self, start: usize, len: usize) -> Vec<u8> {
-        self.inner.mem[start..(start + len)].to_vec()
+        self.inner.peek_bytes(start as u32, len)
     }
     
-    pub fn exec(&mut self, inst: u32) -> Option<ExecResult> {
-        print(format!("executing {}", inst));
-        self.inner.exec(Instruction(inst)).map(|x| ExecResult { a0: x.0, a1: x.1 })
+    /// Run the instruction at the pc, handling environment calls the way Venus or Linux does. Returns
+    /// the exit code if the program exited, after which the pc is moved to the end of the text. Traps
+    /// are thrown as their message.
+    pub fn step(&mut self) -> Result<Option<i32>, JsValue> {
+        let outcome = match &mut self.syscalls {
+            Syscalls::Venus(venus) => self.inner.step_with(venus),
+            Syscalls::Linux(linux) => self.inner.step_with(linux),
+        };
+        match outcome {
+            Ok(riscvm::StepOutcome::Exited(code)) => {
+                self.inner.pc = self.inner.text_end as i32;
+                Ok(Some(code))
+            },
+            Ok(_) => Ok(None),
+            Err(trap) => Err(JsValue::from_str(&trap.to_string())),
+        }
     }
 }
\ No newline at end of file
@@ -75,6 +75,7 @@ export default function Debugger() {
 					<Execution/>
 					<div className="registers-and-output-panes">
 						<RegistersPane/>
+						<FpRegistersPane/>
 						<OutputPane/>
 					</div>
 					<MemoryPane/>
@@ -105,13 +106,39 @@ function RegistersPane() {
 	</div>
 }
 
-function Register(props: { index: number, value: number }) {
+function Register(props: { index: number, value: bigint }) {
 	return <div className="register">
 		<div className="name">x{props.index} =</div>
 		<div className="value"><input type="text" disabled={true} value={props.value.toString()}/></div>
 	</div>
 }
 
+function FpRegistersPane() {
+	const [execution, dispatch] = useExecution();
+	let rows = new Array();
+	for (let i = 0; i < 32; i++) {
+		rows.push(<tr key={i}>
+			<td><FpRegister index={i} value={execution.fpRegisters[i]} bits={execution.fpRegisterBits[i]}/></td>
+		</tr>)
+	}
+	return <div className="registers-pane">
+		<h2>Floating-point registers</h2>
+		<table>
+			<tbody>
+				{rows}
+			</tbody>
+		</table>
+	</div>
+}
+
+function FpRegister(props: { index: number, value: number, bits: bigint }) {
+	return <div className="register">
+		<div className="name">f{props.index} =</div>
+		<div className="value"><input type="text" disabled={true} value={props.value.toString()}/></div>
+		<div className="value"><input type="text" disabled={true} value={"0x" + props.bits.toString(16).padStart(16, "0")}/></div>
+	</div>
+}
+
 function OutputPane() {
 	return <div className="output-pane">
 		<h2>Output</h2>
@@ -208,7 +235,7 @@ function ExecutionControls() {
 			let activeIndex = execution.activeIndex;
 			while (activeIndex < execution.instructions.length && running) {
 				executionDispatch({ action: 'step' });
-				activeIndex = execution.machine.get_instruction_index();
+				activeIndex = execution.machine.get_instruction_index() ?? execution.instructions.length;
 				await sleep(10);
 			}
 			setStopper(null);
@@ -222,6 +249,15 @@ function ExecutionControls() {
 		executionDispatch({ action: 'reload' });
 	}
 	
+	function handleLoadElf(e: any) {
+		const file = e.target.files[0];
+		if (file) {
+			file.arrayBuffer().then((buffer: ArrayBuffer) => {
+				executionDispatch({ action: 'loadElf', elf: new Uint8Array(buffer) });
+			});
+		}
+	}
+	
 	let play;
 	if (stopper === null) {
 		play = <ExecutionButton name="Run" onClick={handleRun}/>;
@@ -233,6 +269,10 @@ function ExecutionControls() {
 		{play}
 		<ExecutionButton name="Step" onClick={() => { executionDispatch({ action: 'step' })}}/>
 		<ExecutionButton name="Reset" onClick={() => { executionDispatch({ action: 'reset' })}}/>
+		<label className="rounded-md m-2 p-2 bg-sky-300 hover:bg-sky-500 cursor-pointer">
+			Load ELF
+			<input type="file" className="hidden" onChange={handleLoadElf}/>
+		</label>
 	</div>;
 }
 
@@ -345,8 +385,9 @@ function MemoryPane() {
 		setRawStart(e.target.value);
 		let start = parseNumber(e.target.value);
 		if (start !== null) {
-			const actualStart = Math.min(start - (start % rowStride), 1024 * 1024);
-			const len = Math.min(nRows * nColumns * cellStride, 1024 * 1024 - actualStart);
+			// unmapped memory reads as zero, so anywhere in the address space can be viewed
+			const len = nRows * nColumns * cellStride;
+			const actualStart = Math.max(0, Math.min(start - (start % rowStride), 2 ** 32 - len));
 			dispatch({ action: 'updateMemoryView', start: actualStart, len: len });
 		}
 	}
@@ -9,8 +9,14 @@ type ExecutionState = {
 	machine: wasm.Machine,
 	instructions: Uint32Array,
 	instructionTexts: string[],
+	textBase: number,
+	data: Uint8Array,
+	dataBase: number,
+	elf: Uint8Array | null,
 	activeIndex: number,
-	registers: Int32Array,
+	registers: BigInt64Array,
+	fpRegisters: Float64Array,
+	fpRegisterBits: BigUint64Array,
 	memoryViewStart: number,
 	memoryViewLen: number,
 	memoryView: ArrayBuffer,
@@ -18,20 +24,28 @@ type ExecutionState = {
 }
 
 function loadSource(source: string): ExecutionState {
-	let compiled = wasm.compile(source);
-	let instructions = new Uint32Array(compiled.length);
+	let compiled = wasm.compile(source, false);
+	let instructions = new Uint32Array(compiled.code.length);
 	let instructionTexts = new Array();
-	for (let i = 0; i < compiled.length; i++) {
-		instructions[i] = compiled[i].code;
-		instructionTexts.push(compiled[i].text);
+	for (let i = 0; i < compiled.code.length; i++) {
+		instructions[i] = compiled.code[i].code;
+		instructionTexts.push(compiled.code[i].text);
 	}
-	let machine = wasm.Machine.new(1024 * 1024);
+	let data = new Uint8Array(compiled.data);
+	let machine = wasm.Machine.new(1024 * 1024, false);
+	machine.load_image(compiled.text_base, instructions, compiled.data_base, data);
 	return {
 		machine,
 		instructions,
 		instructionTexts,
+		textBase: compiled.text_base,
+		data,
+		dataBase: compiled.data_base,
+		elf: null,
 		activeIndex: 0,
 		registers: machine.get_registers(),
+		fpRegisters: machine.get_fp_registers(),
+		fpRegisterBits: machine.get_fp_register_bits(),
 		memoryViewStart: 0,
 		memoryViewLen: 1024 * 1024 / (16 * 4 * 4),
 		memoryView: machine.get_memory_view(0, 1024 * 1024 / (16 * 4 * 4)).buffer,
@@ -39,6 +53,32 @@ function loadSource(source: string): ExecutionState {
 	};
 }
 
+// executables get RAM where they were linked, usually 0x80000000 for riscv-tests and boards
+function loadElf(elf: Uint8Array): ExecutionState {
+	let machine = wasm.Machine.for_elf(elf, 1024 * 1024);
+	let instructions = new Uint32Array(machine.get_text());
+	let textBase = machine.get_text_base();
+	let instructionTexts = Array.from(instructions, (inst, i) => wasm.disassemble(inst, textBase + i * 4, true));
+	let memoryViewStart = textBase - textBase % 16;
+	return {
+		machine,
+		instructions,
+		instructionTexts,
+		textBase,
+		data: new Uint8Array(),
+		dataBase: 0,
+		elf,
+		activeIndex: machine.get_instruction_index() ?? instructions.length,
+		registers: machine.get_registers(),
+		fpRegisters: machine.get_fp_registers(),
+		fpRegisterBits: machine.get_fp_register_bits(),
+		memoryViewStart,
+		memoryViewLen: 1024 * 1024 / (16 * 4 * 4),
+		memoryView: machine.get_memory_view(memoryViewStart, 1024 * 1024 / (16 * 4 * 4)).buffer,
+		output: "",
+	};
+}
+
 export function ExecutionStateProvider(props: { source: string, children: any }) {
 	const [executionState, dispatch] = useImmerReducer(executionStateReducer, null, (arg) => {
 		return loadSource(props.source);
@@ -55,8 +95,10 @@ export function executionStateReducer(draft: Draft<ExecutionState>, action: any)
 	console.log("dispatch action " + JSON.stringify(action));
 	
 	function reload() {
-		draft.activeIndex = draft.machine.get_instruction_index();
+		draft.activeIndex = draft.machine.get_instruction_index() ?? draft.instructions.length;
 		draft.registers = draft.machine.get_registers();
+		draft.fpRegisters = draft.machine.get_fp_registers();
+		draft.fpRegisterBits = draft.machine.get_fp_register_bits();
 		draft.memoryView = draft.machine.get_memory_view(draft.memoryViewStart, draft.memoryViewLen).buffer;
 	}
 	
@@ -69,77 +111,38 @@ export function executionStateReducer(draft: Draft<ExecutionState>, action: any)
 			if (draft.activeIndex >= draft.instructions.length) {
 				break;
 			}
-			let result = draft.machine.exec(draft.instructions[draft.activeIndex]);
+			let result;
+			try {
+				result = draft.machine.step();
+			} catch (trap) {
+				draft.output += "\n" + trap + "\n";
+			}
+			draft.output += draft.machine.take_output();
 			reload();
 			if (result !== undefined) {
-				// ecall same as venus: https://github.com/kvakil/venus/wiki/Environmental-Calls
-				let a1 = result.a1;
-				switch (result.a0) {
-					case 1: {
-						draft.output += a1.toString();
-						break;
-					}
-					case 4: {
-						let full = new Uint8Array(64 * 1024);
-						let end = false;
-						for (let i = 0; i < 64 && !end; i++) {
-							let buf = draft.machine.get_memory_view(a1 + 1024 * i, 1024);
-							for (let j = 0; j < 1024; j++) {
-								let byte = buf.at(1024 * i + j);
-								if (byte == 0) {
-									full.set(buf.slice(0, j), 1024 * i);
-									end = true;
-								}
-							}
-							
-							if (end) {
-								break;
-							} else {
-								full.set(buf, 1024 * i);
-							}
-						}
-						if (!end) {
-							console.error("didn't find end of string within 64KiB");
-						}
-						for (let i = 0; i < full.length && full.at(i) !== 0; i++) {
-							let val = full.at(i)!;
-							if (32 <= val && val < 127) {
-								draft.output += String.fromCharCode(val);
-							} else {
-								draft.output += "\\x" + val.toString(16).padStart(2, '0');
-							}
-						}
-						break;
-					}
-					case 9: {
-						throw Error("unimplemented");
-						break;
-					}
-					case 10: {
-						// exit
-						throw Error("unimplemented");
-					}
-					case 11: {
-						if (32 <= a1 && a1 < 127) {
-							draft.output += String.fromCharCode(a1);
-						} else {
-							draft.output += "\\x" + a1.toString(16).padStart(2, '0');
-						}
-					}
-					case 17: {
-						// exit2, code in a1
-						throw Error("unimplemented");
-					}
-				}
+				draft.output += "\nexited with code " + result + "\n";
 			}
 			break;
 		}
 		case 'reset': {
-			draft.machine = wasm.Machine.new(1024 * 1024);
+			if (draft.elf !== null) {
+				draft.machine = wasm.Machine.for_elf(draft.elf, 1024 * 1024);
+			} else {
+				draft.machine = wasm.Machine.new(1024 * 1024, false);
+				draft.machine.load_image(draft.textBase, draft.instructions, draft.dataBase, draft.data);
+			}
 			draft.output = "";
 			reload();
 			break;
 		}
+		case 'loadElf': {
+			try {
+				Object.assign(draft, loadElf(action.elf));
+			} catch (err) {
+				draft.output += "\n" + err + "\n";
+			}
+			break;
+		}
 		case 'updateMemoryView': {
 			draft.memoryViewStart = action.start;
 			draft.memoryViewLen = action.len;
@@ -0,0 +1,174 @@
+/// An access to an address that nothing answers to, or that the device there refuses. The machine
+/// turns it into the access fault trap matching the kind of access.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessFault;
+
+/// Something that can be read and written at an address, either all of a machine's memory or a
+/// single device mapped into it. `width` is the size of the access in bytes: 1, 2 or 4. Values are
+/// little endian and zero extended.
+pub trait Bus {
+	fn read(&mut self, addr: u32, width: usize) -> Result<u32, AccessFault>;
+
+	fn write(&mut self, addr: u32, width: usize, val: u32) -> Result<(), AccessFault>;
+
+	/// Read a byte without any of the side effects `read` might have, for debuggers and memory views.
+	/// Devices that can't do that don't have to.
+	fn peek(&self, addr: u32) -> Option<u8> {
+		let _ = addr;
+		None
+	}
+
+	/// Whether all of `addr..addr + len` is plain memory, which can be filled without side effects.
+	fn is_memory(&self, addr: u32, len: usize) -> bool {
+		let _ = (addr, len);
+		false
+	}
+}
+
+/// Plain memory starting at address zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ram {
+	pub bytes: Vec<u8>,
+}
+
+impl Ram {
+	pub fn new(size: usize) -> Self {
+		Self { bytes: vec![0; size] }
+	}
+}
+
+impl Bus for Ram {
+	fn read(&mut self, addr: u32, width: usize) -> Result<u32, AccessFault> {
+		let start = addr as usize;
+		let end = start.checked_add(width).ok_or(AccessFault)?;
+		let src = self.bytes.get(start..end).ok_or(AccessFault)?;
+		let mut bytes = [0; 4];
+		bytes[..width].copy_from_slice(src);
+		Ok(u32::from_le_bytes(bytes))
+	}
+
+	fn write(&mut self, addr: u32, width: usize, val: u32) -> Result<(), AccessFault> {
+		let start = addr as usize;
+		let end = start.checked_add(width).ok_or(AccessFault)?;
+		let dest = self.bytes.get_mut(start..end).ok_or(AccessFault)?;
+		dest.copy_from_slice(&val.to_le_bytes()[..width]);
+		Ok(())
+	}
+
+	fn peek(&self, addr: u32) -> Option<u8> {
+		self.bytes.get(addr as usize).copied()
+	}
+
+	fn is_memory(&self, addr: u32, len: usize) -> bool {
+		(addr as usize).checked_add(len).is_some_and(|end| end <= self.bytes.len())
+	}
+}
+
+struct Region {
+	base: u32,
+	size: u32,
+	device: Box<dyn Bus>,
+}
+
+impl Region {
+	/// The offset of an access into this region, if all of it falls inside.
+	fn offset(&self, addr: u32, width: usize) -> Option<u32> {
+		let offset = addr.wrapping_sub(self.base);
+		(offset < self.size && width as u32 <= self.size - offset).then_some(offset)
+	}
+}
+
+/// An address space made of RAM and devices mapped at arbitrary addresses. Each one sees addresses
+/// relative to where it's mapped, and accesses that don't fall entirely inside one region fault.
+#[derive(Default)]
+pub struct MemoryMap {
+	regions: Vec<Region>,
+}
+
+impl MemoryMap {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Map `device` at `base..base + size`.
+	///
+	/// Panics if the range overlaps a region that's already mapped or wraps around the address space.
+	pub fn map(&mut self, base: u32, size: u32, device: impl Bus + 'static) {
+		let end = base.checked_add(size).expect("mapped region wraps around the address space");
+		if let Some(other) = self.regions.iter().find(|other| base < other.base + other.size && other.base < end) {
+			panic!("region at {base:#x} overlaps the one at {:#x}", other.base);
+		}
+		self.regions.push(Region {
+			base,
+			size,
+			device: Box::new(device),
+		});
+	}
+
+	fn region(&mut self, addr: u32, width: usize) -> Result<(&mut (dyn Bus + 'static), u32), AccessFault> {
+		self.regions
+			.iter_mut()
+			.find_map(|region| {
+				let offset = region.offset(addr, width)?;
+				Some((&mut *region.device, offset))
+			})
+			.ok_or(AccessFault)
+	}
+}
+
+impl Bus for MemoryMap {
+	fn read(&mut self, addr: u32, width: usize) -> Result<u32, AccessFault> {
+		let (device, offset) = self.region(addr, width)?;
+		device.read(offset, width)
+	}
+
+	fn write(&mut self, addr: u32, width: usize, val: u32) -> Result<(), AccessFault> {
+		let (device, offset) = self.region(addr, width)?;
+		device.write(offset, width, val)
+	}
+
+	fn peek(&self, addr: u32) -> Option<u8> {
+		let region = self.regions.iter().find(|region| region.offset(addr, 1).is_some())?;
+		region.device.peek(addr - region.base)
+	}
+
+	fn is_memory(&self, addr: u32, len: usize) -> bool {
+		self.regions.iter().any(|region| {
+			let offset = addr.wrapping_sub(region.base);
+			offset < region.size && len as u64 <= (region.size - offset) as u64 && region.device.is_memory(offset, len)
+		})
+	}
+}
+
+#[test]
+fn test_memory_map() {
+	let mut bus = MemoryMap::new();
+	bus.map(0x1000, 16, Ram::new(16));
+	bus.map(0x8000_0000, 8, Ram::new(4));
+
+	bus.write(0x1004, 4, 0x12345678).unwrap();
+	assert_eq!(bus.read(0x1004, 2), Ok(0x5678));
+	assert_eq!(bus.read(0x1007, 1), Ok(0x12));
+	assert_eq!(bus.peek(0x1005), Some(0x56));
+	assert_eq!(bus.peek(0x1010), None);
+
+	// unmapped, straddling the end of a region, and past the end of a too small device
+	assert_eq!(bus.read(0x0ffc, 4), Err(AccessFault));
+	assert_eq!(bus.write(0x100e, 4, 0), Err(AccessFault));
+	assert_eq!(bus.read(0x8000_0004, 4), Err(AccessFault));
+	assert_eq!(bus.read(0x8000_0000, 4), Ok(0));
+
+	// only whole ranges of RAM count as memory
+	assert!(bus.is_memory(0x1000, 16));
+	assert!(!bus.is_memory(0x1008, 16));
+	assert!(!bus.is_memory(0x8000_0004, 1));
+	assert_eq!(Ram::new(4).read(u32::MAX, 4), Err(AccessFault));
+}
+
+#[test]
+#[should_panic(expected = "overlaps")]
+fn test_memory_map_overlap() {
+	let mut bus = MemoryMap::new();
+	bus.map(0x1000, 16, Ram::new(16));
+	bus.map(0x100c, 16, Ram::new(16));
+}
@@ -0,0 +1,146 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::bus::{AccessFault, Bus};
+
+/// Where the CLINT sits in the address space of machines that have one, the same place as on QEMU's
+/// `virt` board.
+pub const CLINT_BASE: u32 = 0x0200_0000;
+
+/// The number of bytes of address space the CLINT's registers take up.
+pub const CLINT_SIZE: u32 = 0x1_0000;
+
+/// Writing 1 raises a software interrupt and writing 0 clears it.
+const MSIP: u32 = 0;
+/// The timer interrupt is pending while `mtime` is at least this. 64 bits, accessed as two words.
+const MTIMECMP: u32 = 0x4000;
+/// The timer, which counts up by one for every instruction. 64 bits, accessed as two words.
+const MTIME: u32 = 0xbff8;
+
+/// The software and timer interrupt bits of `mip` and `mie`.
+pub(crate) const MSI: u32 = 1 << 3;
+pub(crate) const MTI: u32 = 1 << 7;
+
+#[derive(Debug)]
+struct State {
+	msip: bool,
+	mtime: u64,
+	mtimecmp: u64,
+}
+
+impl Default for State {
+	fn default() -> Self {
+		Self {
+			msip: false,
+			mtime: 0,
+			// far enough in the future that the timer doesn't go off until it's set
+			mtimecmp: u64::MAX,
+		}
+	}
+}
+
+/// The core local interruptor of a single hart: the machine timer and software interrupt registers
+/// of SiFive's CLINT, as QEMU and most RISC-V boards have it. Only whole word accesses are allowed.
+///
+/// A `Clint` is a handle, so the machine keeps a clone to tick the timer and see which interrupts are
+/// pending after mapping another into a [`MemoryMap`](crate::MemoryMap).
+#[derive(Debug, Clone, Default)]
+pub struct Clint {
+	state: Rc<RefCell<State>>,
+}
+
+impl Clint {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn mtime(&self) -> u64 {
+		self.state.borrow().mtime
+	}
+
+	/// Move the timer forward by one tick.
+	pub fn tick(&self) {
+		let mut state = self.state.borrow_mut();
+		state.mtime = state.mtime.wrapping_add(1);
+	}
+
+	/// The interrupts the CLINT is raising, as `mip` bits.
+	pub fn pending(&self) -> u32 {
+		let state = self.state.borrow();
+		let software = if state.msip { MSI } else { 0 };
+		let timer = if state.mtime >= state.mtimecmp { MTI } else { 0 };
+		software | timer
+	}
+
+	/// The value of the register the word at `addr` is part of, and how far up it the word is.
+	fn register(state: &State, addr: u32) -> Option<(u64, u32)> {
+		if addr == MSIP {
+			return Some((state.msip as u64, 0));
+		}
+		let val = match addr & !7 {
+			MTIMECMP => state.mtimecmp,
+			MTIME => state.mtime,
+			_ => return None,
+		};
+		Some((val, (addr & 4) * 8))
+	}
+}
+
+/// `old` with the 32 bits `shift` bits up replaced by `val`.
+fn replace_word(old: u64, shift: u32, val: u32) -> u64 {
+	(old & !(0xffff_ffff << shift)) | (val as u64) << shift
+}
+
+impl Bus for Clint {
+	fn read(&mut self, addr: u32, width: usize) -> Result<u32, AccessFault> {
+		if width != 4 {
+			return Err(AccessFault);
+		}
+		let (val, shift) = Self::register(&self.state.borrow(), addr).ok_or(AccessFault)?;
+		Ok((val >> shift) as u32)
+	}
+
+	fn write(&mut self, addr: u32, width: usize, val: u32) -> Result<(), AccessFault> {
+		if width != 4 {
+			return Err(AccessFault);
+		}
+		let mut state = self.state.borrow_mut();
+		let (old, shift) = Self::register(&state, addr).ok_or(AccessFault)?;
+		match addr & !7 {
+			MSIP => state.msip = val & 1 != 0,
+			MTIMECMP => state.mtimecmp = replace_word(old, shift, val),
+			_ => state.mtime = replace_word(old, shift, val),
+		}
+		Ok(())
+	}
+
+	fn peek(&self, addr: u32) -> Option<u8> {
+		let (val, shift) = Self::register(&self.state.borrow(), addr & !3)?;
+		Some((val >> (shift + (addr & 3) * 8)) as u8)
+	}
+}
+
+#[test]
+fn test_clint() {
+	let host = Clint::new();
+	let mut clint = host.clone();
+	assert_eq!(host.pending(), 0);
+	clint.write(MSIP, 4, 1).unwrap();
+	assert_eq!(host.pending(), MSI);
+	clint.write(MSIP, 4, 0).unwrap();
+
+	clint.write(MTIMECMP, 4, 2).unwrap();
+	clint.write(MTIMECMP + 4, 4, 0).unwrap();
+	host.tick();
+	assert_eq!(host.pending(), 0);
+	host.tick();
+	assert_eq!(host.pending(), MTI);
+	assert_eq!(clint.read(MTIME, 4), Ok(2));
+	assert_eq!(clint.peek(MTIMECMP), Some(2));
+
+	clint.write(MTIME + 4, 4, 1).unwrap();
+	assert_eq!(host.mtime(), (1 << 32) | 2);
+	assert_eq!(clint.read(MTIME + 4, 4), Ok(1));
+	assert_eq!(clint.read(MTIME, 1), Err(AccessFault));
+	assert_eq!(clint.read(0x10, 4), Err(AccessFault));
+}
@@ -0,0 +1,232 @@
+/// The privilege mode the hart runs in. Higher modes can do everything lower ones can.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Privilege {
+	User = 0,
+	Supervisor = 1,
+	#[default]
+	Machine = 3,
+}
+
+impl Privilege {
+	/// The mode in an `mstatus.MPP` or `SPP` field. The reserved value 2 is treated as user mode.
+	pub(crate) fn from_bits(bits: u32) -> Self {
+		match bits {
+			3 => Privilege::Machine,
+			1 => Privilege::Supervisor,
+			_ => Privilege::User,
+		}
+	}
+}
+
+/// Supervisor interrupts are enabled.
+pub(crate) const MSTATUS_SIE: u32 = 1 << 1;
+/// Machine interrupts are enabled.
+pub(crate) const MSTATUS_MIE: u32 = 1 << 3;
+/// What SIE was before the current supervisor trap.
+pub(crate) const MSTATUS_SPIE: u32 = 1 << 5;
+/// What MIE was before the current machine trap.
+pub(crate) const MSTATUS_MPIE: u32 = 1 << 7;
+/// The privilege mode before the current supervisor trap, user or supervisor.
+pub(crate) const MSTATUS_SPP: u32 = 1 << 8;
+/// The privilege mode before the current machine trap.
+pub(crate) const MSTATUS_MPP: u32 = 0b11 << 11;
+/// Loads and stores are translated and checked as if the privilege mode were MPP.
+pub(crate) const MSTATUS_MPRV: u32 = 1 << 17;
+/// Supervisor mode may access user pages.
+pub(crate) const MSTATUS_SUM: u32 = 1 << 18;
+/// Loads from pages that are only executable are allowed.
+pub(crate) const MSTATUS_MXR: u32 = 1 << 19;
+/// Supervisor mode can't touch `satp` or run `sfence.vma`.
+pub(crate) const MSTATUS_TVM: u32 = 1 << 20;
+/// `wfi` is illegal below machine mode.
+pub(crate) const MSTATUS_TW: u32 = 1 << 21;
+/// `sret` is illegal in supervisor mode.
+pub(crate) const MSTATUS_TSR: u32 = 1 << 22;
+
+/// The bits of `mstatus` that `sstatus` shows.
+const SSTATUS_MASK: u32 = MSTATUS_SIE | MSTATUS_SPIE | MSTATUS_SPP | MSTATUS_SUM | MSTATUS_MXR;
+/// The bits of `mstatus` that can be written. The rest read as zero.
+const MSTATUS_MASK: u32 =
+	SSTATUS_MASK | MSTATUS_MIE | MSTATUS_MPIE | MSTATUS_MPP | MSTATUS_MPRV | MSTATUS_TVM | MSTATUS_TW | MSTATUS_TSR;
+/// The supervisor and machine software, timer and external interrupt bits.
+const INTERRUPT_MASK: u32 = 0xaaa;
+/// The supervisor interrupts, which are the ones that can be delegated and that machine mode can
+/// raise by writing `mip`.
+const SUPERVISOR_INTERRUPTS: u32 = 0x222;
+/// Every exception except an `ecall` from machine mode can be delegated.
+const MEDELEG_MASK: u32 = 0xb3ff;
+/// Sv32 translation, and the root page table's page number. Address space IDs aren't implemented.
+const SATP_MASK: u32 = 1 << 31 | 0x3f_ffff;
+
+/// Read only CSRs have both of the top bits of their number set.
+const fn is_read_only(num: u32) -> bool {
+	num >> 10 == 0b11
+}
+
+/// The lowest privilege mode that can access CSR `num`.
+pub(crate) fn required_privilege(num: u32) -> Privilege {
+	Privilege::from_bits(num >> 8 & 3)
+}
+
+/// The machine and supervisor mode control and status registers. Bits that aren't implemented read
+/// as zero and ignore writes, like the specification allows. The supervisor views of `mstatus`, `mie`
+/// and `mip` are `sstatus`, `sie` and `sip`.
+///
+/// `fcsr` holds the accrued floating-point exception flags in its low 5 bits and the dynamic rounding
+/// mode above them. `fflags` and `frm` are those fields on their own.
+///
+/// `cycle` and `instret` both count retired instructions. `time` reads the same on machines without
+/// a CLINT, so a program sees one tick per instruction, and the CLINT's `mtime` otherwise. `misa`
+/// and `time` depend on the rest of the machine, so [`Machine`](crate::Machine) handles them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Csrs {
+	pub mstatus: u32,
+	pub medeleg: u32,
+	pub mideleg: u32,
+	pub mie: u32,
+	pub mip: u32,
+	pub mtvec: u32,
+	pub mscratch: u32,
+	pub mepc: u32,
+	pub mcause: u32,
+	pub mtval: u32,
+	pub mhartid: u32,
+	pub stvec: u32,
+	pub sscratch: u32,
+	pub sepc: u32,
+	pub scause: u32,
+	pub stval: u32,
+	pub satp: u32,
+	pub fcsr: u32,
+	/// Whether `mtvec` and `stvec` have been written, which is how a program installs its trap
+	/// handlers. A handler can be at any address, zero included.
+	pub mtvec_set: bool,
+	pub stvec_set: bool,
+	pub cycle: u64,
+	pub instret: u64,
+}
+
+impl Csrs {
+	/// The value of CSR `num`, or `None` if the machine doesn't have it.
+	pub fn read(&self, num: u32) -> Option<u32> {
+		Some(match num {
+			0x001 => self.fcsr & 0x1f,
+			0x002 => self.fcsr >> 5 & 7,
+			0x003 => self.fcsr,
+			0x100 => self.mstatus & SSTATUS_MASK,
+			0x104 => self.mie & self.mideleg,
+			0x105 => self.stvec,
+			0x140 => self.sscratch,
+			0x141 => self.sepc,
+			0x142 => self.scause,
+			0x143 => self.stval,
+			0x144 => self.mip & self.mideleg,
+			0x180 => self.satp,
+			0x300 => self.mstatus,
+			0x302 => self.medeleg,
+			0x303 => self.mideleg,
+			0x304 => self.mie,
+			0x305 => self.mtvec,
+			0x340 => self.mscratch,
+			0x341 => self.mepc,
+			0x342 => self.mcause,
+			0x343 => self.mtval,
+			0x344 => self.mip,
+			0xb00 | 0xc00 | 0xc01 => self.cycle as u32,
+			0xb02 | 0xc02 => self.instret as u32,
+			0xb80 | 0xc80 | 0xc81 => (self.cycle >> 32) as u32,
+			0xb82 | 0xc82 => (self.instret >> 32) as u32,
+			0xf14 => self.mhartid,
+			_ => return None,
+		})
+	}
+
+	/// Write `val` to CSR `num`. Returns `None` if the machine doesn't have it or it's read only.
+	pub fn write(&mut self, num: u32, val: u32) -> Option<()> {
+		if is_read_only(num) {
+			return None;
+		}
+		// bits outside `mask` keep their value
+		let update = |old: u32, mask: u32| (old & !mask) | (val & mask);
+		match num {
+			0x001 => self.fcsr = update(self.fcsr, 0x1f),
+			0x002 => self.fcsr = (self.fcsr & 0x1f) | (val & 7) << 5,
+			0x003 => self.fcsr = val & 0xff,
+			0x100 => self.mstatus = update(self.mstatus, SSTATUS_MASK),
+			0x104 => self.mie = update(self.mie, self.mideleg),
+			// only direct and vectored modes exist
+			0x105 => (self.stvec, self.stvec_set) = (val & !2, true),
+			0x140 => self.sscratch = val,
+			0x141 => self.sepc = val & !3,
+			0x142 => self.scause = val,
+			0x143 => self.stval = val,
+			// supervisor software interrupts are the only ones supervisor mode can raise itself
+			0x144 => self.mip = update(self.mip, self.mideleg & 0x2),
+			0x180 => self.satp = val & SATP_MASK,
+			0x300 => {
+				self.mstatus = update(self.mstatus, MSTATUS_MASK);
+				// MPP can't hold the reserved mode
+				if self.mstatus & MSTATUS_MPP == 0b10 << 11 {
+					self.mstatus &= !MSTATUS_MPP;
+				}
+			},
+			0x302 => self.medeleg = val & MEDELEG_MASK,
+			0x303 => self.mideleg = val & SUPERVISOR_INTERRUPTS,
+			0x304 => self.mie = val & INTERRUPT_MASK,
+			0x305 => (self.mtvec, self.mtvec_set) = (val & !2, true),
+			0x340 => self.mscratch = val,
+			0x341 => self.mepc = val & !3,
+			0x342 => self.mcause = val,
+			0x343 => self.mtval = val,
+			// the machine pending bits come from devices
+			0x344 => self.mip = update(self.mip, SUPERVISOR_INTERRUPTS),
+			0xb00 => self.cycle = (self.cycle & !0xffff_ffff) | val as u64,
+			0xb02 => self.instret = (self.instret & !0xffff_ffff) | val as u64,
+			0xb80 => self.cycle = (self.cycle & 0xffff_ffff) | (val as u64) << 32,
+			0xb82 => self.instret = (self.instret & 0xffff_ffff) | (val as u64) << 32,
+			_ => return None,
+		}
+		Some(())
+	}
+}
+
+#[test]
+fn test_csrs() {
+	let mut csrs = Csrs::default();
+	assert_eq!(csrs.write(0x300, u32::MAX), Some(()));
+	assert_eq!(csrs.read(0x300), Some(0x7e19aa));
+	assert_eq!(csrs.read(0x100), Some(0xc0122));
+	csrs.write(0x100, 0).unwrap();
+	assert_eq!(csrs.read(0x300), Some(0x721888));
+	csrs.write(0x300, 0b10 << 11).unwrap();
+	assert_eq!(csrs.read(0x300), Some(0));
+	assert_eq!(csrs.write(0x305, 0x1003), Some(()));
+	assert_eq!(csrs.read(0x305), Some(0x1001));
+
+	// the supervisor views only show delegated interrupts
+	csrs.write(0x304, u32::MAX).unwrap();
+	csrs.write(0x303, 0x20).unwrap();
+	assert_eq!((csrs.read(0x304), csrs.read(0x104)), (Some(0xaaa), Some(0x20)));
+	csrs.write(0x104, 0).unwrap();
+	assert_eq!(csrs.read(0x304), Some(0xa8a));
+	csrs.write(0x344, u32::MAX).unwrap();
+	assert_eq!((csrs.read(0x344), csrs.read(0x144)), (Some(0x222), Some(0x20)));
+
+	csrs.write(0xb80, 1).unwrap();
+	csrs.write(0xb00, 5).unwrap();
+	assert_eq!(csrs.cycle, (1 << 32) | 5);
+	assert_eq!((csrs.read(0xc00), csrs.read(0xc81)), (Some(5), Some(1)));
+
+	assert_eq!(csrs.write(0xc00, 0), None);
+	assert_eq!(csrs.write(0xf14, 0), None);
+	assert_eq!(csrs.read(0x7c0), None);
+	assert_eq!(csrs.write(0x7c0, 0), None);
+	assert_eq!(required_privilege(0x141), Privilege::Supervisor);
+	assert_eq!(required_privilege(0xc00), Privilege::User);
+
+	csrs.write(0x003, u32::MAX).unwrap();
+	assert_eq!((csrs.read(0x001), csrs.read(0x002), csrs.read(0x003)), (Some(0x1f), Some(7), Some(0xff)));
+	csrs.write(0x002, 1).unwrap();
+	csrs.write(0x001, 0x21).unwrap();
+	assert_eq!(csrs.fcsr, 0x21);
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const SHN_UNDEF: u16 = 0;
+
+const PHDR_SIZE: usize = 32;
+const SHDR_SIZE: usize = 40;
+const SYM_SIZE: usize = 16;
+
+/// Why a file couldn't be loaded as an executable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+	NotElf,
+	/// An ELF file for something other than 32 bit little endian RISC-V.
+	WrongTarget,
+	/// An object file or shared library instead of a statically linked executable.
+	NotExecutable,
+	/// A header or segment points past the end of the file.
+	Truncated,
+	/// A segment runs past the end of the 32 bit address space.
+	BadSegment,
+	/// The program or section headers aren't the size of 32 bit ones.
+	BadHeaderSize,
+}
+
+impl std::fmt::Display for ElfError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.write_str(match self {
+			ElfError::NotElf => "not an ELF file",
+			ElfError::WrongTarget => "not a 32 bit little endian RISC-V ELF file",
+			ElfError::NotExecutable => "not a statically linked executable",
+			ElfError::Truncated => "the ELF file is truncated",
+			ElfError::BadSegment => "a segment runs past the end of the address space",
+			ElfError::BadHeaderSize => "the program or section headers have the wrong size",
+		})
+	}
+}
+
+impl std::error::Error for ElfError {}
+
+/// Part of the program to copy into memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+	pub vaddr: u32,
+	pub data: Vec<u8>,
+	/// The size in memory, which is bigger than `data` when the rest (usually `.bss`) starts zeroed.
+	pub mem_size: u32,
+}
+
+/// A statically linked RV32 executable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Elf {
+	pub entry: u32,
+	pub segments: Vec<Segment>,
+	/// The address of every named function and object in the symbol table, if the file has one.
+	pub symbols: HashMap<String, u32>,
+}
+
+/// Little endian fields of a byte slice, failing past its end.
+#[derive(Clone, Copy)]
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+	fn bytes(self, offset: usize, len: usize) -> Result<&'a [u8], ElfError> {
+		offset.checked_add(len).and_then(|end| self.0.get(offset..end)).ok_or(ElfError::Truncated)
+	}
+
+	/// Entry `index` of the table at `offset` whose entries are `size` bytes.
+	fn entry(self, offset: usize, index: usize, size: usize) -> Result<Reader<'a>, ElfError> {
+		let start = index.checked_mul(size).and_then(|start| start.checked_add(offset)).ok_or(ElfError::Truncated)?;
+		self.bytes(start, size).map(Reader)
+	}
+
+	fn u8(self, offset: usize) -> Result<u8, ElfError> {
+		Ok(self.bytes(offset, 1)?[0])
+	}
+
+	fn u16(self, offset: usize) -> Result<u16, ElfError> {
+		Ok(u16::from_le_bytes(self.bytes(offset, 2)?.try_into().unwrap()))
+	}
+
+	fn u32(self, offset: usize) -> Result<u32, ElfError> {
+		Ok(u32::from_le_bytes(self.bytes(offset, 4)?.try_into().unwrap()))
+	}
+
+	/// The NUL terminated string at `offset`.
+	fn str(self, offset: usize) -> Result<&'a str, ElfError> {
+		let rest = self.0.get(offset..).ok_or(ElfError::Truncated)?;
+		let len = rest.iter().position(|&byte| byte == 0).ok_or(ElfError::Truncated)?;
+		std::str::from_utf8(&rest[..len]).map_err(|_| ElfError::Truncated)
+	}
+}
+
+impl Elf {
+	pub fn parse(file: &[u8]) -> Result<Self, ElfError> {
+		let file = Reader(file);
+		if file.bytes(0, 4).ok() != Some(b"\x7fELF") {
+			return Err(ElfError::NotElf);
+		}
+		if file.u8(4)? != ELFCLASS32 || file.u8(5)? != ELFDATA2LSB || file.u16(18)? != EM_RISCV {
+			return Err(ElfError::WrongTarget);
+		}
+		if file.u16(16)? != ET_EXEC {
+			return Err(ElfError::NotExecutable);
+		}
+		let entry = file.u32(24)?;
+		let (phoff, shoff) = (file.u32(28)? as usize, file.u32(32)? as usize);
+		let (phnum, shnum) = (file.u16(44)? as usize, file.u16(48)? as usize);
+		if (phnum > 0 && file.u16(42)? as usize != PHDR_SIZE) || (shnum > 0 && file.u16(46)? as usize != SHDR_SIZE) {
+			return Err(ElfError::BadHeaderSize);
+		}
+
+		let mut segments = Vec::new();
+		for i in 0..phnum {
+			let phdr = file.entry(phoff, i, PHDR_SIZE)?;
+			if phdr.u32(0)? != PT_LOAD {
+				continue;
+			}
+			let (offset, vaddr) = (phdr.u32(4)? as usize, phdr.u32(8)?);
+			let (file_size, mem_size) = (phdr.u32(16)? as usize, phdr.u32(20)?);
+			let mem_size = mem_size.max(file_size as u32);
+			if vaddr as u64 + mem_size as u64 > 1 << 32 {
+				return Err(ElfError::BadSegment);
+			}
+			segments.push(Segment {
+				vaddr,
+				data: file.bytes(offset, file_size)?.to_vec(),
+				mem_size,
+			});
+		}
+
+		let mut symbols = HashMap::new();
+		for i in 0..shnum {
+			let shdr = file.entry(shoff, i, SHDR_SIZE)?;
+			if shdr.u32(4)? != SHT_SYMTAB {
+				continue;
+			}
+			let table = file.bytes(shdr.u32(16)? as usize, shdr.u32(20)? as usize)?;
+			let strtab_shdr = file.entry(shoff, shdr.u32(24)? as usize, SHDR_SIZE)?;
+			let strtab = Reader(file.bytes(strtab_shdr.u32(16)? as usize, strtab_shdr.u32(20)? as usize)?);
+			for sym in table.chunks_exact(SYM_SIZE).map(Reader) {
+				let kind = sym.u8(12)? & 0xf;
+				if kind == STT_SECTION || kind == STT_FILE || sym.u16(14)? == SHN_UNDEF {
+					continue;
+				}
+				let name = strtab.str(sym.u32(0)? as usize)?;
+				if !name.is_empty() {
+					symbols.insert(name.to_owned(), sym.u32(4)?);
+				}
+			}
+		}
+
+		Ok(Self { entry, segments, symbols })
+	}
+}
+
+/// Build an executable with one segment holding `text` followed by `bss` zeroed bytes, and a symbol
+/// table, for testing without a toolchain.
+#[cfg(test)]
+pub(crate) fn build_elf(base: u32, entry: u32, text: &[u8], bss: u32, symbols: &[(&str, u32)]) -> Vec<u8> {
+	let mut strtab = vec![0];
+	let mut symtab = vec![0; SYM_SIZE];
+	for &(name, value) in symbols {
+		symtab.extend((strtab.len() as u32).to_le_bytes());
+		symtab.extend(value.to_le_bytes());
+		// no size, STB_GLOBAL/STT_NOTYPE, defined in section 1
+		symtab.extend([0, 0, 0, 0, 0x10, 0, 1, 0]);
+		strtab.extend(name.as_bytes());
+		strtab.push(0);
+	}
+	let phoff = 52;
+	let text_off = phoff + PHDR_SIZE;
+	let symtab_off = text_off + text.len();
+	let strtab_off = symtab_off + symtab.len();
+	let shoff = strtab_off + strtab.len();
+
+	let mut elf = b"\x7fELF\x01\x01\x01".to_vec();
+	elf.resize(16, 0);
+	for half in [ET_EXEC, EM_RISCV] {
+		elf.extend(half.to_le_bytes());
+	}
+	for word in [1, entry, phoff as u32, shoff as u32, 0] {
+		elf.extend(word.to_le_bytes());
+	}
+	for half in [52, PHDR_SIZE as u16, 1, SHDR_SIZE as u16, 3, 0] {
+		elf.extend(half.to_le_bytes());
+	}
+	let len = text.len() as u32;
+	for word in [PT_LOAD, text_off as u32, base, base, len, len + bss, 0b111, 4] {
+		elf.extend(word.to_le_bytes());
+	}
+	elf.extend(text);
+	elf.extend(&symtab);
+	elf.extend(&strtab);
+	elf.extend([0; SHDR_SIZE]);
+	for word in [0, SHT_SYMTAB, 0, 0, symtab_off as u32, symtab.len() as u32, 2, 1, 4, SYM_SIZE as u32] {
+		elf.extend(word.to_le_bytes());
+	}
+	for word in [0, 3, 0, 0, strtab_off as u32, strtab.len() as u32, 0, 0, 1, 0] {
+		elf.extend(word.to_le_bytes());
+	}
+	elf
+}
+
+#[test]
+fn test_parse_elf() {
+	let elf = build_elf(0x1000, 0x1004, &[1, 2, 3, 4, 5, 6, 7, 8], 8, &[("_start", 0x1004), ("buf", 0x1008)]);
+	let parsed = Elf::parse(&elf).unwrap();
+	assert_eq!(parsed.entry, 0x1004);
+	assert_eq!(
+		parsed.segments,
+		[Segment {
+			vaddr: 0x1000,
+			data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+			mem_size: 16,
+		}]
+	);
+	assert_eq!(parsed.symbols, HashMap::from([("_start".to_owned(), 0x1004), ("buf".to_owned(), 0x1008)]));
+
+	assert_eq!(Elf::parse(b"#!/bin/sh"), Err(ElfError::NotElf));
+	assert_eq!(Elf::parse(&elf[..100]), Err(ElfError::Truncated));
+	let mut wrong = elf.clone();
+	wrong[4] = 2;
+	assert_eq!(Elf::parse(&wrong), Err(ElfError::WrongTarget));
+	let mut object = elf.clone();
+	object[16] = 1;
+	assert_eq!(Elf::parse(&object), Err(ElfError::NotExecutable));
+	let mut elf64_headers = elf.clone();
+	elf64_headers[42] = 56;
+	assert_eq!(Elf::parse(&elf64_headers), Err(ElfError::BadHeaderSize));
+	// header tables at the end of the address space fail instead of overflowing
+	let mut far = elf;
+	far[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+	far[44] = 0xff;
+	assert_eq!(Elf::parse(&far), Err(ElfError::Truncated));
+	let wrapping = build_elf(0xffff_fff0, 0xffff_fff0, &[0; 8], 16, &[]);
+	assert_eq!(Elf::parse(&wrapping), Err(ElfError::BadSegment));
+}
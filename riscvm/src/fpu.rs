@@ -0,0 +1,814 @@
+use std::cmp::Ordering;
+
+use risclang::def::Fmt;
+
+/// Invalid operation.
+pub(crate) const NV: u32 = 1 << 4;
+/// Division by zero.
+pub(crate) const DZ: u32 = 1 << 3;
+/// Overflow.
+pub(crate) const OF: u32 = 1 << 2;
+/// Underflow.
+pub(crate) const UF: u32 = 1 << 1;
+/// Inexact.
+pub(crate) const NX: u32 = 1 << 0;
+
+/// The IEEE 754 rounding modes, in the order of their `rm` encodings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rounding {
+	NearestEven,
+	TowardZero,
+	Down,
+	Up,
+	NearestMaxMagnitude,
+}
+
+impl Rounding {
+	/// The mode in an `rm` field or `frm`. The reserved values and the dynamic mode aren't modes.
+	pub(crate) fn from_bits(bits: u32) -> Option<Self> {
+		Some(match bits {
+			0 => Rounding::NearestEven,
+			1 => Rounding::TowardZero,
+			2 => Rounding::Down,
+			3 => Rounding::Up,
+			4 => Rounding::NearestMaxMagnitude,
+			_ => return None,
+		})
+	}
+}
+
+/// The widths of the fields of a format.
+#[derive(Debug, Clone, Copy)]
+struct Format {
+	exp_bits: u32,
+	frac_bits: u32,
+}
+
+impl Format {
+	fn of(fmt: Fmt) -> Self {
+		match fmt {
+			Fmt::S => Self {
+				exp_bits: 8,
+				frac_bits: 23,
+			},
+			Fmt::D => Self {
+				exp_bits: 11,
+				frac_bits: 52,
+			},
+		}
+	}
+
+	fn bias(self) -> i32 {
+		(1 << (self.exp_bits - 1)) - 1
+	}
+
+	/// The exponent field of infinities and NaNs.
+	fn max_exp(self) -> u64 {
+		(1 << self.exp_bits) - 1
+	}
+
+	fn sign(self, sign: bool) -> u64 {
+		(sign as u64) << (self.exp_bits + self.frac_bits)
+	}
+
+	fn zero(self, sign: bool) -> u64 {
+		self.sign(sign)
+	}
+
+	fn inf(self, sign: bool) -> u64 {
+		self.sign(sign) | self.max_exp() << self.frac_bits
+	}
+
+	fn max_finite(self, sign: bool) -> u64 {
+		self.inf(sign) - 1
+	}
+}
+
+/// A finite nonzero number, `sig * 2^exp`. The significand isn't normalized.
+#[derive(Debug, Clone, Copy)]
+struct Num {
+	sign: bool,
+	exp: i32,
+	sig: u128,
+}
+
+impl Num {
+	/// The same number with the top bit of the significand at bit 125, which leaves room for a sum.
+	fn normalized(self) -> Self {
+		let shift = self.sig.leading_zeros() as i32 - 2;
+		Self {
+			sig: self.sig << shift,
+			exp: self.exp - shift,
+			..self
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+	Nan { signaling: bool },
+	Inf(bool),
+	Zero(bool),
+	Finite(Num),
+}
+
+impl Value {
+	fn sign(self) -> bool {
+		match self {
+			Value::Nan { .. } => false,
+			Value::Inf(sign) | Value::Zero(sign) => sign,
+			Value::Finite(num) => num.sign,
+		}
+	}
+
+	fn is_nan(self) -> bool {
+		matches!(self, Value::Nan { .. })
+	}
+}
+
+fn unpack(fmt: Fmt, bits: u64) -> Value {
+	let format = Format::of(fmt);
+	let sign = sign_bit(fmt, bits);
+	let exp = bits >> format.frac_bits & format.max_exp();
+	let frac = bits & ((1 << format.frac_bits) - 1);
+	match (exp, frac) {
+		(0, 0) => Value::Zero(sign),
+		(0, _) => Value::Finite(Num {
+			sign,
+			exp: 1 - format.bias() - format.frac_bits as i32,
+			sig: frac.into(),
+		}),
+		(exp, 0) if exp == format.max_exp() => Value::Inf(sign),
+		(exp, _) if exp == format.max_exp() => Value::Nan {
+			signaling: frac >> (format.frac_bits - 1) == 0,
+		},
+		(exp, _) => Value::Finite(Num {
+			sign,
+			exp: exp as i32 - format.bias() - format.frac_bits as i32,
+			sig: (frac | 1 << format.frac_bits).into(),
+		}),
+	}
+}
+
+/// The NaN that every operation returning a NaN gives, whatever NaNs went in.
+pub(crate) fn canonical_nan(fmt: Fmt) -> u64 {
+	let format = Format::of(fmt);
+	format.inf(false) | 1 << (format.frac_bits - 1)
+}
+
+pub(crate) fn sign_bit(fmt: Fmt, bits: u64) -> bool {
+	let format = Format::of(fmt);
+	bits >> (format.exp_bits + format.frac_bits) & 1 != 0
+}
+
+/// `bits` with its sign bit replaced by `sign`.
+pub(crate) fn with_sign(fmt: Fmt, bits: u64, sign: bool) -> u64 {
+	let format = Format::of(fmt);
+	bits & !format.sign(true) | format.sign(sign)
+}
+
+/// The `fclass` mask of a number: a single bit for negative infinity, normal, subnormal and zero,
+/// then the same for positive numbers in reverse, then signaling and quiet NaNs.
+pub(crate) fn classify(fmt: Fmt, bits: u64) -> u32 {
+	let format = Format::of(fmt);
+	let subnormal = bits >> format.frac_bits & format.max_exp() == 0;
+	let class = match unpack(fmt, bits) {
+		Value::Inf(sign) => [7, 0][sign as usize],
+		Value::Finite(num) if subnormal => [5, 2][num.sign as usize],
+		Value::Finite(num) => [6, 1][num.sign as usize],
+		Value::Zero(sign) => [4, 3][sign as usize],
+		Value::Nan { signaling } => [9, 8][signaling as usize],
+	};
+	1 << class
+}
+
+/// A number that orders like the value of `bits`, which isn't a NaN. Both zeros are equal.
+fn order_key(fmt: Fmt, bits: u64) -> i64 {
+	let magnitude = with_sign(fmt, bits, false) as i64;
+	if sign_bit(fmt, bits) {
+		-magnitude
+	} else {
+		magnitude
+	}
+}
+
+/// `sig` shifted right by `shift` bits, with the lowest bit set if any of the bits shifted out were.
+fn shift_right_jam(sig: u128, shift: i32) -> u128 {
+	if shift >= 128 {
+		(sig != 0) as u128
+	} else {
+		sig >> shift | (sig & ((1 << shift) - 1) != 0) as u128
+	}
+}
+
+/// The state of a floating-point operation: the rounding mode it uses and the exception flags it
+/// raised, in the bit order of `fflags`. Operands and results are the bits of a number in the given
+/// format, and NaN results are always the canonical NaN.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Env {
+	pub rounding: Rounding,
+	pub flags: u32,
+}
+
+impl Env {
+	pub(crate) fn add(&mut self, fmt: Fmt, a: u64, b: u64) -> u64 {
+		self.sum(fmt, unpack(fmt, a), unpack(fmt, b))
+	}
+
+	pub(crate) fn sub(&mut self, fmt: Fmt, a: u64, b: u64) -> u64 {
+		self.add(fmt, a, b ^ Format::of(fmt).sign(true))
+	}
+
+	pub(crate) fn mul(&mut self, fmt: Fmt, a: u64, b: u64) -> u64 {
+		let (x, y) = (unpack(fmt, a), unpack(fmt, b));
+		if x.is_nan() || y.is_nan() {
+			return self.nan(fmt, &[x, y]);
+		}
+		match product(x, y) {
+			Some(Value::Finite(num)) => self.round(fmt, num),
+			Some(Value::Inf(sign)) => Format::of(fmt).inf(sign),
+			Some(_) => Format::of(fmt).zero(x.sign() ^ y.sign()),
+			None => self.invalid(fmt),
+		}
+	}
+
+	/// `a * b + c` with a single rounding. Infinity times zero is invalid even if `c` is a quiet NaN.
+	pub(crate) fn fused(&mut self, fmt: Fmt, a: u64, b: u64, c: u64) -> u64 {
+		let (x, y, z) = (unpack(fmt, a), unpack(fmt, b), unpack(fmt, c));
+		let invalid_product = matches!((x, y), (Value::Inf(_), Value::Zero(_)) | (Value::Zero(_), Value::Inf(_)));
+		if x.is_nan() || y.is_nan() || z.is_nan() {
+			if invalid_product {
+				self.flags |= NV;
+			}
+			return self.nan(fmt, &[x, y, z]);
+		}
+		match product(x, y) {
+			Some(product) => self.sum(fmt, product, z),
+			None => self.invalid(fmt),
+		}
+	}
+
+	pub(crate) fn div(&mut self, fmt: Fmt, a: u64, b: u64) -> u64 {
+		let format = Format::of(fmt);
+		let (x, y) = (unpack(fmt, a), unpack(fmt, b));
+		let sign = x.sign() ^ y.sign();
+		match (x, y) {
+			(Value::Nan { .. }, _) | (_, Value::Nan { .. }) => self.nan(fmt, &[x, y]),
+			(Value::Inf(_), Value::Inf(_)) | (Value::Zero(_), Value::Zero(_)) => self.invalid(fmt),
+			(Value::Inf(_), _) => format.inf(sign),
+			(_, Value::Zero(_)) => {
+				self.flags |= DZ;
+				format.inf(sign)
+			},
+			(Value::Zero(_), _) | (_, Value::Inf(_)) => format.zero(sign),
+			(Value::Finite(x), Value::Finite(y)) => {
+				// 64 bit significands give a quotient of at least 64 bits, and the remainder is sticky
+				let (x_shift, y_shift) = (x.sig.leading_zeros() as i32 - 64, y.sig.leading_zeros() as i32 - 64);
+				let dividend = x.sig << x_shift << 64;
+				let divisor = y.sig << y_shift;
+				let quotient = dividend / divisor;
+				let sticky = dividend % divisor != 0;
+				let exp = (x.exp - x_shift) - (y.exp - y_shift) - 64 - 1;
+				self.round(
+					fmt,
+					Num {
+						sign,
+						exp,
+						sig: quotient << 1 | sticky as u128,
+					},
+				)
+			},
+		}
+	}
+
+	pub(crate) fn sqrt(&mut self, fmt: Fmt, a: u64) -> u64 {
+		match unpack(fmt, a) {
+			x @ Value::Nan { .. } => self.nan(fmt, &[x]),
+			Value::Zero(sign) => Format::of(fmt).zero(sign),
+			x if x.sign() => self.invalid(fmt),
+			Value::Inf(_) => Format::of(fmt).inf(false),
+			Value::Finite(num) => {
+				// an even exponent halves exactly, and the big significand leaves a 63 bit root
+				let mut shift = num.sig.leading_zeros() as i32 - 2;
+				if (num.exp - shift) & 1 != 0 {
+					shift += 1;
+				}
+				let (sig, exp) = (num.sig << shift, num.exp - shift);
+				let root = sig.isqrt();
+				let sticky = root * root != sig;
+				self.round(
+					fmt,
+					Num {
+						sign: false,
+						exp: exp / 2 - 1,
+						sig: root << 1 | sticky as u128,
+					},
+				)
+			},
+		}
+	}
+
+	/// `fmin` and `fmax`: a NaN operand gives the other one, and -0 is less than +0.
+	pub(crate) fn min_max(&mut self, fmt: Fmt, a: u64, b: u64, max: bool) -> u64 {
+		let (x, y) = (unpack(fmt, a), unpack(fmt, b));
+		self.signal(&[x, y]);
+		match (x.is_nan(), y.is_nan()) {
+			(true, true) => canonical_nan(fmt),
+			(true, false) => b,
+			(false, true) => a,
+			(false, false) => {
+				let pick_a = match order_key(fmt, a).cmp(&order_key(fmt, b)) {
+					Ordering::Less => !max,
+					Ordering::Greater => max,
+					Ordering::Equal => sign_bit(fmt, a) != max,
+				};
+				if pick_a {
+					a
+				} else {
+					b
+				}
+			},
+		}
+	}
+
+	/// How `a` compares to `b`, or `None` if either is a NaN. Any NaN is invalid for a signaling
+	/// comparison, otherwise only signaling NaNs are.
+	pub(crate) fn compare(&mut self, fmt: Fmt, a: u64, b: u64, signaling: bool) -> Option<Ordering> {
+		let (x, y) = (unpack(fmt, a), unpack(fmt, b));
+		if x.is_nan() || y.is_nan() {
+			self.signal(&[x, y]);
+			if signaling {
+				self.flags |= NV;
+			}
+			return None;
+		}
+		Some(order_key(fmt, a).cmp(&order_key(fmt, b)))
+	}
+
+	/// `a` rounded to a `bits` wide integer, sign extended to 64 bits. Out of range values, NaNs and
+	/// infinities are invalid and give the closest integer, with NaNs counting as positive.
+	pub(crate) fn float_to_int(&mut self, fmt: Fmt, a: u64, signed: bool, bits: u32) -> i64 {
+		let (min, max): (i128, i128) = if signed {
+			(-(1 << (bits - 1)), (1 << (bits - 1)) - 1)
+		} else {
+			(0, (1 << bits) - 1)
+		};
+		let val = match unpack(fmt, a) {
+			Value::Nan { .. } => {
+				self.flags |= NV;
+				max
+			},
+			Value::Inf(sign) => {
+				self.flags |= NV;
+				if sign {
+					min
+				} else {
+					max
+				}
+			},
+			Value::Zero(_) => 0,
+			Value::Finite(num) => {
+				// anything shifted left by 64 or more is out of range anyway
+				let (magnitude, inexact) = match num.exp {
+					64.. => (u64::MAX as u128 + 1, false),
+					0.. => (num.sig << num.exp, false),
+					_ => self.shift_round(num.sig, -num.exp, num.sign),
+				};
+				let val = if num.sign { -(magnitude as i128) } else { magnitude as i128 };
+				if val < min || val > max {
+					self.flags |= NV;
+					val.clamp(min, max)
+				} else {
+					if inexact {
+						self.flags |= NX;
+					}
+					val
+				}
+			},
+		};
+		if bits == 32 {
+			val as i32 as i64
+		} else {
+			val as i64
+		}
+	}
+
+	pub(crate) fn int_to_float(&mut self, fmt: Fmt, val: i128) -> u64 {
+		if val == 0 {
+			return Format::of(fmt).zero(false);
+		}
+		self.round(
+			fmt,
+			Num {
+				sign: val < 0,
+				exp: 0,
+				sig: val.unsigned_abs(),
+			},
+		)
+	}
+
+	/// `a` converted from format `from` to format `to`.
+	pub(crate) fn convert(&mut self, from: Fmt, to: Fmt, a: u64) -> u64 {
+		match unpack(from, a) {
+			x @ Value::Nan { .. } => self.nan(to, &[x]),
+			Value::Inf(sign) => Format::of(to).inf(sign),
+			Value::Zero(sign) => Format::of(to).zero(sign),
+			Value::Finite(num) => self.round(to, num),
+		}
+	}
+
+	/// The sum of two numbers that may be the exact result of a multiplication.
+	fn sum(&mut self, fmt: Fmt, x: Value, y: Value) -> u64 {
+		let format = Format::of(fmt);
+		match (x, y) {
+			(Value::Nan { .. }, _) | (_, Value::Nan { .. }) => self.nan(fmt, &[x, y]),
+			(Value::Inf(s), Value::Inf(t)) if s != t => self.invalid(fmt),
+			(Value::Inf(sign), _) | (_, Value::Inf(sign)) => format.inf(sign),
+			// zeros of opposite signs add up to +0, except when rounding down
+			(Value::Zero(s), Value::Zero(t)) => format.zero(if s == t { s } else { self.rounding == Rounding::Down }),
+			(Value::Zero(_), Value::Finite(num)) | (Value::Finite(num), Value::Zero(_)) => self.round(fmt, num),
+			(Value::Finite(x), Value::Finite(y)) => {
+				// the smaller one shifted out of range only matters for rounding, which a sticky bit
+				// far below the rounding position captures
+				let (mut x, mut y) = (x.normalized(), y.normalized());
+				if x.exp < y.exp {
+					std::mem::swap(&mut x, &mut y);
+				}
+				let y_sig = shift_right_jam(y.sig, x.exp - y.exp);
+				let (sign, sig) = if x.sign == y.sign {
+					(x.sign, x.sig + y_sig)
+				} else if x.sig >= y_sig {
+					(x.sign, x.sig - y_sig)
+				} else {
+					(y.sign, y_sig - x.sig)
+				};
+				if sig == 0 {
+					return format.zero(self.rounding == Rounding::Down);
+				}
+				self.round(fmt, Num { sign, exp: x.exp, sig })
+			},
+		}
+	}
+
+	/// `num` rounded to the format. Tininess is detected after rounding, so a result that rounds up
+	/// to the smallest normal number with an unbounded exponent doesn't underflow.
+	fn round(&mut self, fmt: Fmt, num: Num) -> u64 {
+		let format = Format::of(fmt);
+		let frac_bits = format.frac_bits as i32;
+		let min_exp = 1 - format.bias();
+		// the exponent of the leading bit, and of the last bit that fits
+		let exp = num.exp + 127 - num.sig.leading_zeros() as i32;
+		let last = exp.max(min_exp) - frac_bits;
+		let (sig, inexact) = self.shift_round(num.sig, last - num.exp, num.sign);
+		if exp <= format.bias() {
+			// a significand rounded up to the next power of two carries into the exponent field
+			let bits = ((last + frac_bits + format.bias() - 1) as u64) << frac_bits;
+			let bits = bits + sig as u64;
+			if bits < format.inf(false) {
+				if inexact {
+					self.flags |= NX;
+					let carries = || self.shift_round(num.sig, exp - frac_bits - num.exp, num.sign).0 >> (frac_bits + 1) != 0;
+					if exp < min_exp && !(exp + 1 == min_exp && carries()) {
+						self.flags |= UF;
+					}
+				}
+				return bits | format.sign(num.sign);
+			}
+		}
+		self.flags |= OF | NX;
+		let to_inf = match self.rounding {
+			Rounding::NearestEven | Rounding::NearestMaxMagnitude => true,
+			Rounding::TowardZero => false,
+			Rounding::Down => num.sign,
+			Rounding::Up => !num.sign,
+		};
+		if to_inf {
+			format.inf(num.sign)
+		} else {
+			format.max_finite(num.sign)
+		}
+	}
+
+	/// `sig` shifted right by `shift` bits and rounded to an integer, and whether that was inexact.
+	fn shift_round(&self, sig: u128, shift: i32, sign: bool) -> (u128, bool) {
+		if shift <= 0 {
+			return (sig << -shift, false);
+		}
+		let (quotient, half, sticky) = match shift {
+			129.. => (0, false, sig != 0),
+			_ => (
+				sig.checked_shr(shift as u32).unwrap_or(0),
+				sig >> (shift - 1) & 1 != 0,
+				sig & ((1 << (shift - 1)) - 1) != 0,
+			),
+		};
+		let up = match self.rounding {
+			Rounding::NearestEven => half && (sticky || quotient & 1 != 0),
+			Rounding::NearestMaxMagnitude => half,
+			Rounding::TowardZero => false,
+			Rounding::Down => sign && (half || sticky),
+			Rounding::Up => !sign && (half || sticky),
+		};
+		(quotient + up as u128, half || sticky)
+	}
+
+	/// The canonical NaN, raising the invalid flag if any of `values` is a signaling NaN.
+	fn nan(&mut self, fmt: Fmt, values: &[Value]) -> u64 {
+		self.signal(values);
+		canonical_nan(fmt)
+	}
+
+	fn signal(&mut self, values: &[Value]) {
+		if values.iter().any(|value| matches!(value, Value::Nan { signaling: true })) {
+			self.flags |= NV;
+		}
+	}
+
+	fn invalid(&mut self, fmt: Fmt) -> u64 {
+		self.flags |= NV;
+		canonical_nan(fmt)
+	}
+}
+
+/// The exact product of two numbers that aren't NaNs, or `None` for infinity times zero.
+fn product(x: Value, y: Value) -> Option<Value> {
+	let sign = x.sign() ^ y.sign();
+	Some(match (x, y) {
+		(Value::Inf(_), Value::Zero(_)) | (Value::Zero(_), Value::Inf(_)) => return None,
+		(Value::Inf(_), _) | (_, Value::Inf(_)) => Value::Inf(sign),
+		(Value::Zero(_), _) | (_, Value::Zero(_)) => Value::Zero(sign),
+		(Value::Finite(x), Value::Finite(y)) => Value::Finite(Num {
+			sign,
+			exp: x.exp + y.exp,
+			sig: x.sig * y.sig,
+		}),
+		_ => unreachable!("NaNs have no product"),
+	})
+}
+
+#[cfg(test)]
+fn test_operands(mut state: u64, count: usize, exp_bits: u32, frac_bits: u32) -> Vec<u64> {
+	// special values, then random numbers with exponents close enough together to interact
+	let format = Format { exp_bits, frac_bits };
+	let quiet = format.inf(false) | 1 << (frac_bits - 1);
+	let mut operands = vec![
+		0,
+		format.sign(true),
+		format.inf(false),
+		format.inf(true),
+		1,
+		format.max_finite(false),
+		quiet,
+		format.inf(true) | 1,
+	];
+	while operands.len() < count {
+		state ^= state << 13;
+		state ^= state >> 7;
+		state ^= state << 17;
+		let exp = match state % 4 {
+			0 => state >> 8,
+			1 => format.bias() as u64 + (state >> 8) % 8,
+			2 => (state >> 8) % 4,
+			_ => format.bias() as u64 - (state >> 8) % 64,
+		} & format.max_exp();
+		operands.push(format.sign(state >> 63 != 0) | exp << frac_bits | state >> 11 & ((1 << frac_bits) - 1));
+	}
+	operands
+}
+
+#[test]
+fn test_against_host() {
+	fn check(fmt: Fmt, result: u64, host: u64, host_is_nan: bool, what: &str) {
+		if host_is_nan {
+			assert_eq!(result, canonical_nan(fmt), "{what}");
+		} else {
+			assert_eq!(result, host, "{what}");
+		}
+	}
+	let mut env = Env {
+		rounding: Rounding::NearestEven,
+		flags: 0,
+	};
+	let singles = test_operands(0x9e37_79b9_7f4a_7c15, 300, 8, 23);
+	for (i, &a) in singles.iter().enumerate() {
+		let (x, z) = (
+			f32::from_bits(a as u32),
+			f32::from_bits(singles[(i * 13 + 5) % singles.len()] as u32),
+		);
+		for &b in &singles {
+			let y = f32::from_bits(b as u32);
+			let what = format!("{x:e} {y:e}");
+			check(
+				Fmt::S,
+				env.add(Fmt::S, a, b),
+				(x + y).to_bits().into(),
+				(x + y).is_nan(),
+				&what,
+			);
+			check(
+				Fmt::S,
+				env.sub(Fmt::S, a, b),
+				(x - y).to_bits().into(),
+				(x - y).is_nan(),
+				&what,
+			);
+			check(
+				Fmt::S,
+				env.mul(Fmt::S, a, b),
+				(x * y).to_bits().into(),
+				(x * y).is_nan(),
+				&what,
+			);
+			check(
+				Fmt::S,
+				env.div(Fmt::S, a, b),
+				(x / y).to_bits().into(),
+				(x / y).is_nan(),
+				&what,
+			);
+			let fused = x.mul_add(y, z);
+			check(
+				Fmt::S,
+				env.fused(Fmt::S, a, b, z.to_bits().into()),
+				fused.to_bits().into(),
+				fused.is_nan(),
+				&what,
+			);
+		}
+		check(
+			Fmt::S,
+			env.sqrt(Fmt::S, a),
+			x.sqrt().to_bits().into(),
+			x.sqrt().is_nan(),
+			&format!("{x:e}"),
+		);
+		let double = x as f64;
+		check(
+			Fmt::D,
+			env.convert(Fmt::S, Fmt::D, a),
+			double.to_bits(),
+			double.is_nan(),
+			&format!("{x:e}"),
+		);
+	}
+
+	let doubles = test_operands(0x2545_f491_4f6c_dd1d, 300, 11, 52);
+	for (i, &a) in doubles.iter().enumerate() {
+		let (x, z) = (f64::from_bits(a), f64::from_bits(doubles[(i * 13 + 5) % doubles.len()]));
+		for &b in &doubles {
+			let y = f64::from_bits(b);
+			let what = format!("{x:e} {y:e}");
+			check(Fmt::D, env.add(Fmt::D, a, b), (x + y).to_bits(), (x + y).is_nan(), &what);
+			check(Fmt::D, env.sub(Fmt::D, a, b), (x - y).to_bits(), (x - y).is_nan(), &what);
+			check(Fmt::D, env.mul(Fmt::D, a, b), (x * y).to_bits(), (x * y).is_nan(), &what);
+			check(Fmt::D, env.div(Fmt::D, a, b), (x / y).to_bits(), (x / y).is_nan(), &what);
+			let fused = x.mul_add(y, z);
+			check(
+				Fmt::D,
+				env.fused(Fmt::D, a, b, z.to_bits()),
+				fused.to_bits(),
+				fused.is_nan(),
+				&what,
+			);
+		}
+		check(
+			Fmt::D,
+			env.sqrt(Fmt::D, a),
+			x.sqrt().to_bits(),
+			x.sqrt().is_nan(),
+			&format!("{x:e}"),
+		);
+		let single = x as f32;
+		check(
+			Fmt::S,
+			env.convert(Fmt::D, Fmt::S, a),
+			single.to_bits().into(),
+			single.is_nan(),
+			&format!("{x:e}"),
+		);
+		check(
+			Fmt::D,
+			env.int_to_float(Fmt::D, a as i64 as i128),
+			(a as i64 as f64).to_bits(),
+			false,
+			&format!("{a}"),
+		);
+		check(
+			Fmt::S,
+			env.int_to_float(Fmt::S, a.into()),
+			(a as f32).to_bits().into(),
+			false,
+			&format!("{a}"),
+		);
+	}
+}
+
+#[test]
+fn test_rounding_and_flags() {
+	let env = |rounding| Env { rounding, flags: 0 };
+	let one = 1.0f32.to_bits() as u64;
+	let third = (1.0f32 / 3.0).to_bits() as u64;
+
+	// 1/3 is inexact, and rounds up to nearest
+	let three = 3.0f32.to_bits() as u64;
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.div(Fmt::S, one, three), third);
+	assert_eq!(rne.flags, NX);
+	assert_eq!(env(Rounding::Up).div(Fmt::S, one, three), third);
+	assert_eq!(env(Rounding::TowardZero).div(Fmt::S, one, three), third - 1);
+	let minus_one = with_sign(Fmt::S, one, true);
+	assert_eq!(env(Rounding::Down).div(Fmt::S, minus_one, three), with_sign(Fmt::S, third, true));
+	assert_eq!(env(Rounding::Up).div(Fmt::S, minus_one, three), with_sign(Fmt::S, third - 1, true));
+	// 2.5 to an integer in every mode, and -2.5
+	let half = 2.5f64.to_bits();
+	let modes = [
+		Rounding::NearestEven,
+		Rounding::TowardZero,
+		Rounding::Down,
+		Rounding::Up,
+		Rounding::NearestMaxMagnitude,
+	];
+	let ints = modes.map(|rounding| env(rounding).float_to_int(Fmt::D, half, true, 32));
+	assert_eq!(ints, [2, 2, 2, 3, 3]);
+	let ints = modes.map(|rounding| env(rounding).float_to_int(Fmt::D, with_sign(Fmt::D, half, true), true, 32));
+	assert_eq!(ints, [-2, -2, -3, -2, -3]);
+
+	// out of range conversions saturate, NaNs count as positive
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.float_to_int(Fmt::S, 3e9f32.to_bits().into(), true, 32), i32::MAX.into());
+	assert_eq!(rne.flags, NV);
+	assert_eq!(
+		rne.float_to_int(Fmt::S, 3e9f32.to_bits().into(), false, 32),
+		3_000_000_000u32 as i32 as i64
+	);
+	assert_eq!(rne.float_to_int(Fmt::S, (-1.5f32).to_bits().into(), false, 64), 0);
+	assert_eq!(rne.float_to_int(Fmt::D, canonical_nan(Fmt::D), false, 32), -1);
+	assert_eq!(rne.float_to_int(Fmt::D, f64::NEG_INFINITY.to_bits(), true, 64), i64::MIN);
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.float_to_int(Fmt::S, (-0.5f32).to_bits().into(), false, 32), 0);
+	assert_eq!(rne.flags, NX);
+
+	// division by zero, invalid operations and overflow
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.div(Fmt::D, 1f64.to_bits(), 0f64.to_bits()), f64::INFINITY.to_bits());
+	assert_eq!(rne.flags, DZ);
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.sqrt(Fmt::D, (-1f64).to_bits()), canonical_nan(Fmt::D));
+	assert_eq!(rne.sqrt(Fmt::D, (-0f64).to_bits()), (-0f64).to_bits());
+	assert_eq!(rne.flags, NV);
+	let max = f32::MAX.to_bits() as u64;
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.mul(Fmt::S, max, 2f32.to_bits().into()), f32::INFINITY.to_bits().into());
+	assert_eq!(rne.flags, OF | NX);
+	let mut rtz = env(Rounding::TowardZero);
+	assert_eq!(rtz.add(Fmt::S, max, max), max);
+	assert_eq!(env(Rounding::Down).add(Fmt::S, max, max), max);
+	assert_eq!(env(Rounding::Down).sub(Fmt::S, one, one), (-0f32).to_bits().into());
+	assert_eq!(env(Rounding::NearestEven).sub(Fmt::S, one, one), 0);
+
+	// infinity times zero is invalid even with a quiet NaN to add, a quiet NaN alone isn't
+	let quiet = canonical_nan(Fmt::S);
+	let signaling = f32::INFINITY.to_bits() as u64 | 1;
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.fused(Fmt::S, f32::INFINITY.to_bits().into(), 0, quiet), quiet);
+	assert_eq!(rne.flags, NV);
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.fused(Fmt::S, one, one, quiet), quiet);
+	assert_eq!(rne.add(Fmt::S, quiet | 0x1234, one), quiet);
+	assert_eq!(rne.compare(Fmt::S, quiet, one, false), None);
+	assert_eq!(rne.flags, 0);
+	assert_eq!(rne.compare(Fmt::S, quiet, one, true), None);
+	assert_eq!(rne.flags, NV);
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.min_max(Fmt::S, signaling, one, false), one);
+	assert_eq!(rne.flags, NV);
+	assert_eq!(
+		rne.min_max(Fmt::S, 0, with_sign(Fmt::S, 0, true), false),
+		with_sign(Fmt::S, 0, true)
+	);
+	assert_eq!(rne.min_max(Fmt::S, with_sign(Fmt::S, 0, true), 0, true), 0);
+	assert_eq!(
+		rne.compare(Fmt::S, 0, with_sign(Fmt::S, 0, true), false),
+		Some(Ordering::Equal)
+	);
+
+	// (1 - 2^-54) * 2^-1022 rounds to the smallest normal number, so it's only tiny when that
+	// rounding goes down
+	let x = f64::MIN_POSITIVE.to_bits() - (1 << 25);
+	let y = (1.0 + 2f64.powi(-27)).to_bits();
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.mul(Fmt::D, x, y), f64::MIN_POSITIVE.to_bits());
+	assert_eq!(rne.flags, NX);
+	let mut rtz = env(Rounding::TowardZero);
+	assert_eq!(rtz.mul(Fmt::D, x, y), f64::MIN_POSITIVE.to_bits() - 1);
+	assert_eq!(rtz.flags, UF | NX);
+	// an exact subnormal result doesn't underflow
+	let mut rne = env(Rounding::NearestEven);
+	assert_eq!(rne.mul(Fmt::S, 1, 2f32.to_bits().into()), 2);
+	assert_eq!(rne.flags, 0);
+
+	let classes =
+		[f32::NEG_INFINITY, -1.0, -1e-40, -0.0, 0.0, 1e-40, 1.0, f32::INFINITY].map(|x| classify(Fmt::S, x.to_bits().into()));
+	assert_eq!(classes, [1, 2, 4, 8, 16, 32, 64, 128]);
+	assert_eq!((classify(Fmt::S, signaling), classify(Fmt::S, quiet)), (256, 512));
+}
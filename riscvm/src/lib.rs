@@ -1,151 +1,976 @@
+use std::cmp::Ordering;
+
 use risclang::*;
 
-pub fn compile(text: &str) -> Vec<u8> {
-	let (parsed_insts, _texts, labels) = parse::parse(&text);
-	let code = dbg!(compile::compile(parsed_insts, &labels));
-	let code = code.into_iter().map(|inst| inst.0.to_le_bytes()).flatten().collect::<Vec<_>>();
-	code
+use def::{Fmt, Xlen};
+use mmu::Access;
+
+mod bus;
+mod clint;
+mod csr;
+mod elf;
+mod fpu;
+mod linux;
+mod mmu;
+mod syscall;
+mod trap;
+mod uart;
+mod vfs;
+
+pub use bus::{AccessFault, Bus, MemoryMap, Ram};
+pub use clint::{Clint, CLINT_BASE, CLINT_SIZE};
+pub use csr::{Csrs, Privilege};
+pub use elf::{Elf, ElfError, Segment};
+pub use linux::Linux;
+pub use mmu::Tlb;
+pub use syscall::{Convention, SyscallHandler, SyscallOutcome, Venus};
+pub use trap::{StepOutcome, Trap, TrapCause};
+pub use uart::{Uart, UART_BASE, UART_SIZE};
+pub use vfs::{FileSystem, FsError, OpenOptions};
+
+pub fn assemble(text: &str) -> Result<compile::Image, Vec<diag::Diagnostic>> {
+	assemble_at(text, &compile::Layout::default())
+}
+
+/// Assemble `text` with its sections placed according to `layout`.
+pub fn assemble_at(text: &str, layout: &compile::Layout) -> Result<compile::Image, Vec<diag::Diagnostic>> {
+	let program = parse::parse(text)?;
+	compile::compile(program, layout)
+}
+
+/// Assemble `text` and return just the machine code of the text section.
+pub fn compile(text: &str) -> Result<Vec<u8>, Vec<diag::Diagnostic>> {
+	Ok(assemble(text)?.text_bytes())
+}
+
+/// The optional ISA extensions a machine implements. Instructions from a missing extension are
+/// illegal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extensions {
+	pub m: bool,
+	pub zicsr: bool,
+	pub f: bool,
+	pub d: bool,
+}
+
+impl Extensions {
+	pub fn has(&self, extension: def::Extension) -> bool {
+		match extension {
+			def::Extension::I => true,
+			def::Extension::M => self.m,
+			def::Extension::Zicsr => self.zicsr,
+			def::Extension::F => self.f,
+			def::Extension::D => self.d && self.f,
+		}
+	}
+
+	/// The value of the `misa` CSR: an `xlen` machine with these extensions, and supervisor and user
+	/// modes.
+	pub fn misa(&self, xlen: Xlen) -> u64 {
+		let letter = |letter: u8| 1 << (letter - b'A');
+		let m = if self.m { letter(b'M') } else { 0 };
+		let f = if self.has(def::Extension::F) { letter(b'F') } else { 0 };
+		let d = if self.has(def::Extension::D) { letter(b'D') } else { 0 };
+		let mxl: u64 = match xlen {
+			Xlen::Rv32 => 1 << 30,
+			Xlen::Rv64 => 2 << 62,
+		};
+		mxl | letter(b'I') | m | f | d | letter(b'S') | letter(b'U')
+	}
+}
+
+impl Default for Extensions {
+	fn default() -> Self {
+		Self {
+			m: true,
+			zicsr: true,
+			f: true,
+			d: true,
+		}
+	}
+}
+
+/// A segment of an image that doesn't fit in the machine's memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadError {
+	pub base: u32,
+	pub len: usize,
+}
+
+impl std::fmt::Display for LoadError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "a segment of {} bytes at {:#x} doesn't fit in memory", self.len, self.base)
+	}
 }
 
-pub struct Machine {
-	pub regs: [i32; 32],
-	pub mem: Vec<u8>,
+impl std::error::Error for LoadError {}
+
+pub struct Machine<B = MemoryMap> {
+	/// The integer registers. On RV32 they hold the sign extension of their 32 bit value.
+	pub regs: [i64; 32],
+	/// The floating-point registers, 64 bits wide for D. Single precision values are NaN-boxed: the
+	/// upper 32 bits are all ones.
+	pub fregs: [u64; 32],
+	pub bus: B,
 	pub pc: i32,
+	/// Whether this is an RV32 or an RV64 machine. Addresses stay 32 bits on RV64, so its pc is the
+	/// same and anything a register points at beyond 4 GiB is an access fault. Sv39 isn't implemented,
+	/// so RV64 machines run without paging.
+	pub xlen: Xlen,
+	/// The address right after the loaded text. Reaching it ends the program, like returning from
+	/// `main` would. Executables loaded from ELF files exit through an environment call instead, so
+	/// for them it's `u32::MAX`, which the pc never gets to.
+	pub text_end: u32,
+	pub extensions: Extensions,
+	pub csrs: Csrs,
+	pub privilege: Privilege,
+	/// Cached Sv32 translations, used outside machine mode once `satp` turns paging on.
+	pub tlb: Tlb,
+	/// The CLINT mapped into `bus`, if there is one. It's ticked every step and raises the timer and
+	/// software interrupts.
+	pub clint: Option<Clint>,
 }
 
 impl Machine {
+	/// An RV32 machine with `mem_size` bytes of RAM at address zero and the stack pointer at its end.
+	/// More devices can be mapped into `bus`.
 	pub fn new(mem_size: usize) -> Self {
-		let mut this = Self {
+		Self::with_xlen(mem_size, Xlen::Rv32)
+	}
+
+	/// Like `new`, but with `xlen` wide registers.
+	pub fn with_xlen(mem_size: usize, xlen: Xlen) -> Self {
+		Self::with_ram(0, mem_size, xlen)
+	}
+
+	/// Like `with_xlen`, but with the RAM at `base`, where executables linked for real boards expect it.
+	pub fn with_ram(base: u32, mem_size: usize, xlen: Xlen) -> Self {
+		let mut bus = MemoryMap::new();
+		bus.map(base, mem_size.try_into().unwrap(), Ram::new(mem_size));
+		let mut this = Self::with_bus(bus);
+		this.xlen = xlen;
+		this.regs[2] = xlen.wrap(base as i64 + mem_size as i64);
+		this
+	}
+
+	/// Map a CLINT at `CLINT_BASE` and let it interrupt the machine. Returns a handle for the host.
+	pub fn attach_clint(&mut self) -> Clint {
+		let clint = Clint::new();
+		self.bus.map(CLINT_BASE, CLINT_SIZE, clint.clone());
+		self.clint = Some(clint.clone());
+		clint
+	}
+}
+
+impl<B: Bus> Machine<B> {
+	pub fn with_bus(bus: B) -> Self {
+		Self {
 			regs: [0; 32],
-			mem: vec![0; mem_size],
+			fregs: [0; 32],
+			bus,
 			pc: 0,
+			xlen: Xlen::Rv32,
+			text_end: 0,
+			extensions: Extensions::default(),
+			csrs: Csrs::default(),
+			privilege: Privilege::Machine,
+			tlb: Tlb::default(),
+			clint: None,
+		}
+	}
+
+	/// Copy the text and data of an assembled program into memory at their base addresses and point
+	/// the pc at the start of the text.
+	pub fn load_image(&mut self, image: &compile::Image) -> Result<(), LoadError> {
+		self.load_segment(image.text_base, &image.text_bytes())?;
+		self.load_segment(image.data_base, &image.data)?;
+		self.pc = image.text_base as i32;
+		self.text_end = image.text_base.wrapping_add(image.text.len() as u32 * 4);
+		Ok(())
+	}
+
+	/// Copy the segments of an executable into memory, zeroing the parts that aren't in the file, and
+	/// jump to its entry point. `gp` is set to `__global_pointer$` if the executable defines it and `sp`
+	/// is aligned down to the 16 bytes the ABI asks for.
+	pub fn load_elf(&mut self, elf: &Elf) -> Result<(), LoadError> {
+		for segment in &elf.segments {
+			// the zeroed part isn't buffered, its size comes from the file and fails once it leaves memory
+			let zeros = std::iter::repeat_n(0, segment.mem_size as usize - segment.data.len());
+			self.write_bytes(segment.vaddr, segment.mem_size as usize, segment.data.iter().copied().chain(zeros))?;
+		}
+		self.pc = elf.entry as i32;
+		self.text_end = u32::MAX;
+		if let Some(&gp) = elf.symbols.get("__global_pointer$") {
+			self.regs[3] = self.xlen.wrap(gp.into());
+		}
+		self.regs[2] &= !15;
+		Ok(())
+	}
+
+	/// Copy `bytes` into memory starting at `base`.
+	pub fn load_segment(&mut self, base: u32, bytes: &[u8]) -> Result<(), LoadError> {
+		self.write_bytes(base, bytes.len(), bytes.iter().copied())
+	}
+
+	/// Write the `len` bytes of `bytes` into memory starting at `base`. Nothing is written unless all
+	/// of them land in plain memory, so devices never see a load.
+	fn write_bytes(&mut self, base: u32, len: usize, bytes: impl Iterator<Item = u8>) -> Result<(), LoadError> {
+		if len > 0 && !self.bus.is_memory(base, len) {
+			return Err(LoadError { base, len });
+		}
+		for (i, byte) in bytes.enumerate() {
+			self.bus.write(base + i as u32, 1, byte as u32).map_err(|AccessFault| LoadError { base, len })?;
+		}
+		Ok(())
+	}
+
+	/// Read physical memory for display, without disturbing any devices. Bytes that can't be read that
+	/// way show up as zero.
+	pub fn peek_bytes(&self, addr: u32, len: usize) -> Vec<u8> {
+		(0..len).map(|i| self.bus.peek(addr.wrapping_add(i as u32)).unwrap_or(0)).collect()
+	}
+
+	/// Run until the end of the text is reached or an instruction traps. Environment calls are traps
+	/// too, after which the pc still points at the `ecall`. Once the program has set `mtvec`, traps go
+	/// to its handler instead.
+	pub fn run(&mut self) -> Result<(), Trap> {
+		while self.step()? == StepOutcome::Continue {}
+		Ok(())
+	}
+
+	/// Like `run`, but environment calls the program has no handler for go to `handler`. Stops at the
+	/// end of the text, when the program exits or on a trap neither the handler nor the program takes
+	/// care of.
+	pub fn run_with(&mut self, handler: &mut impl SyscallHandler<B>) -> Result<StepOutcome, Trap> {
+		loop {
+			match self.step_with(handler)? {
+				StepOutcome::Continue => {},
+				outcome => return Ok(outcome),
+			}
+		}
+	}
+
+	/// Like `step`, but an `ecall` the program has no handler for is handled by `handler` and stepped
+	/// over, the way a kernel would. Calls the handler doesn't know are returned as traps.
+	pub fn step_with(&mut self, handler: &mut impl SyscallHandler<B>) -> Result<StepOutcome, Trap> {
+		match self.step_raw() {
+			Err(trap) if trap.cause.is_environment_call() && !self.has_handler(self.trap_target(trap.cause.code())) => {
+				let result = match handler.ecall(self) {
+					Ok(SyscallOutcome::Continue) => {
+						self.pc = self.pc.wrapping_add(4);
+						Ok(StepOutcome::Continue)
+					},
+					Ok(SyscallOutcome::Exit(code)) => Ok(StepOutcome::Exited(code)),
+					Ok(SyscallOutcome::Unknown) => Err(trap),
+					Err(trap) => Err(trap),
+				};
+				self.take_trap(result)
+			},
+			result => self.take_trap(result),
+		}
+	}
+
+	/// Fetch the instruction at the pc from memory and run it, or take an interrupt if one is pending
+	/// and enabled. Traps are returned unless the program has a handler for them in `mtvec`.
+	pub fn step(&mut self) -> Result<StepOutcome, Trap> {
+		let result = self.step_raw();
+		self.take_trap(result)
+	}
+
+	/// `step` without sending traps to the program.
+	fn step_raw(&mut self) -> Result<StepOutcome, Trap> {
+		let pc = self.pc as u32;
+		if pc == self.text_end {
+			return Ok(StepOutcome::Finished);
+		}
+		if let Some(clint) = &self.clint {
+			clint.tick();
+			self.csrs.mip = (self.csrs.mip & !(clint::MSI | clint::MTI)) | clint.pending();
+		}
+		if let Some(code) = self.pending_interrupt() {
+			self.enter_trap(1 << 31 | code, 0);
+			return Ok(StepOutcome::Continue);
+		}
+		let inst = self.fetch(pc)?;
+		self.exec(inst)
+	}
+
+	/// The code of the interrupt to take, if any. Interrupts are taken in the order the specification
+	/// gives: external, software, then timer, machine before supervisor. Delegated interrupts are only
+	/// taken below machine mode.
+	fn pending_interrupt(&self) -> Option<u32> {
+		let pending = self.csrs.mip & self.csrs.mie;
+		let enabled = |code: u32| {
+			let (mode, enable_bit) = if self.csrs.mideleg >> code & 1 != 0 {
+				(Privilege::Supervisor, csr::MSTATUS_SIE)
+			} else {
+				(Privilege::Machine, csr::MSTATUS_MIE)
+			};
+			self.privilege < mode || (self.privilege == mode && self.csrs.mstatus & enable_bit != 0)
 		};
-		this.regs[2] = mem_size.try_into().unwrap();
-		this
+		[11, 3, 7, 9, 1, 5].into_iter().find(|&code| pending >> code & 1 != 0 && enabled(code))
 	}
 
-	pub fn run(&mut self, code: &[u8]) {
-		while (self.pc as usize) < code.len() {
-			let pc = self.pc as usize;
-			let inst = Instruction::from_bytes([code[pc], code[pc+1], code[pc+2], code[pc+3]]);
-			self.exec(inst);
-			self.regs[0] = 0;
-		}
-	}
-
-	pub fn exec(&mut self, inst: Instruction) -> Option<(i32, i32)> {
-		let rs1 = self.regs[inst.rs1() as usize];
-		let rs2 = self.regs[inst.rs2() as usize];
-		let rd = &mut self.regs[inst.rd() as usize];
-		let imm = inst.imm();
-		let mut pcmod = false;
-		let mut ret = None;
-		match inst.opcode() {
-			0b0110011 => match (inst.funct3(), inst.funct7()) {
-				(0b000, 0b0000000) => *rd = rs1 + rs2,
-				(0b000, 0b0100000) => *rd = rs1 - rs2,
-				(0b111, 0b0000000) => *rd = rs1 & rs2,
-				(0b110, 0b0000000) => *rd = rs1 | rs2,
-				(0b100, 0b0000000) => *rd = rs1 ^ rs2,
-				(0b001, 0b0000000) => *rd = rs1 << rs2,
-				(0b101, 0b0000000) => *rd = (rs1 as u32 >> rs2 as u32) as i32,
-				(0b101, 0b0100000) => *rd = rs1 >> rs2,
-				(0b010, 0b0000000) => *rd = if rs1 < rs2 { 1 } else { 0 },
-				(0b011, 0b0000000) => *rd = if (rs1 as u32) < (rs2 as u32) { 1 } else { 0 },
-				(0b000, 0b0000001) => *rd = rs1 * rs2,
-				_ => panic!("invalid instruction"),
-			},
-			0b0010011 => match (inst.funct3(), inst.funct7()) {
-				(0b000, _) => *rd = rs1 + imm,
-				(0b111, _) => *rd = rs1 & imm,
-				(0b110, _) => *rd = rs1 | imm,
-				(0b100, _) => *rd = rs1 ^ imm,
-				(0b001, 0b0000000) => *rd = rs1 << imm,
-				(0b101, 0b0000000) => *rd = ((rs1 as u32) >> imm) as i32,
-				(0b101, 0b0100000) => *rd = rs1 >> imm,
-				(0b010, _) => *rd = if rs1 < imm { 1 } else { 0 },
-				(0b011, _) => *rd = if (rs1 as u32) < (imm as u32) { 1 } else { 0 },
-				_ => panic!("invalid instruction"),
-			},
-			0b0000011 => {
-				let addr: usize = (rs1 + imm).try_into().unwrap();
-				match inst.funct3() {
-					0b000 => *rd = self.mem[addr] as i8 as i32,
-					0b100 => *rd = self.mem[addr] as u32 as i32,
-					0b001 => *rd = i16::from_le_bytes([self.mem[addr], self.mem[addr + 1]]) as i32,
-					0b101 => *rd = u16::from_le_bytes([self.mem[addr], self.mem[addr + 1]]) as u32 as i32,
-					0b010 => *rd = i32::from_le_bytes([self.mem[addr], self.mem[addr + 1], self.mem[addr + 2], self.mem[addr + 3]]),
-					_ => panic!("invalid instruction"),
+	/// The mode that handles a trap with this `mcause`: supervisor mode if it was delegated and didn't
+	/// happen in machine mode, otherwise machine mode.
+	fn trap_target(&self, cause: u32) -> Privilege {
+		let code = cause & !(1 << 31);
+		let delegated = if cause >> 31 != 0 { self.csrs.mideleg } else { self.csrs.medeleg };
+		if self.privilege < Privilege::Machine && delegated >> code & 1 != 0 {
+			Privilege::Supervisor
+		} else {
+			Privilege::Machine
+		}
+	}
+
+	/// Whether the program has installed a handler for the traps `mode` takes.
+	fn has_handler(&self, mode: Privilege) -> bool {
+		match mode {
+			Privilege::Supervisor => self.csrs.stvec_set,
+			_ => self.csrs.mtvec_set,
+		}
+	}
+
+	/// Send a trap to the program's handler if the mode that takes it has installed one. Programs that
+	/// never write its trap vector get the trap returned like before.
+	fn take_trap(&mut self, result: Result<StepOutcome, Trap>) -> Result<StepOutcome, Trap> {
+		match result {
+			Err(trap) => {
+				if !self.has_handler(self.trap_target(trap.cause.code())) {
+					return Err(trap);
+				}
+				self.enter_trap(trap.cause.code(), trap.tval);
+				Ok(StepOutcome::Continue)
+			},
+			result => result,
+		}
+	}
+
+	/// Save the pc, cause and privilege mode of a trap, disable interrupts and jump to the handler in
+	/// the mode that takes it. Interrupts go to their own entry of the vector table if the trap vector
+	/// is in vectored mode.
+	fn enter_trap(&mut self, cause: u32, tval: u32) {
+		let pc = self.pc as u32;
+		let target = self.trap_target(cause);
+		let csrs = &mut self.csrs;
+		let tvec = if target == Privilege::Supervisor {
+			(csrs.sepc, csrs.scause, csrs.stval) = (pc, cause, tval);
+			let spie = if csrs.mstatus & csr::MSTATUS_SIE != 0 { csr::MSTATUS_SPIE } else { 0 };
+			let spp = if self.privilege == Privilege::Supervisor { csr::MSTATUS_SPP } else { 0 };
+			csrs.mstatus = (csrs.mstatus & !(csr::MSTATUS_SIE | csr::MSTATUS_SPIE | csr::MSTATUS_SPP)) | spie | spp;
+			csrs.stvec
+		} else {
+			(csrs.mepc, csrs.mcause, csrs.mtval) = (pc, cause, tval);
+			let mpie = if csrs.mstatus & csr::MSTATUS_MIE != 0 { csr::MSTATUS_MPIE } else { 0 };
+			let mpp = (self.privilege as u32) << 11;
+			csrs.mstatus = (csrs.mstatus & !(csr::MSTATUS_MIE | csr::MSTATUS_MPIE | csr::MSTATUS_MPP)) | mpie | mpp;
+			csrs.mtvec
+		};
+		self.privilege = target;
+		let base = tvec & !3;
+		let vectored = tvec & 1 != 0 && cause >> 31 != 0;
+		self.pc = if vectored { base.wrapping_add((cause & !(1 << 31)) * 4) } else { base } as i32;
+	}
+
+	fn fetch(&mut self, pc: u32) -> Result<Instruction, Trap> {
+		if !pc.is_multiple_of(4) {
+			return Err(self.trap(TrapCause::InstructionMisaligned, pc));
+		}
+		let addr = self.translate(pc, Access::Fetch)?;
+		let word = self.bus.read(addr, 4).map_err(|AccessFault| self.trap(TrapCause::InstructionAccessFault, pc))?;
+		Ok(Instruction(word))
+	}
+	/// Run one instruction as if it was at the pc. If it traps, the machine is left as it was.
+	pub fn exec(&mut self, inst: Instruction) -> Result<StepOutcome, Trap> {
+		use DecodedInst::*;
+		let decoded = decode(inst.0).ok().filter(|decoded| self.implements(decoded));
+		let Some(decoded) = decoded else {
+			return Err(self.trap(TrapCause::IllegalInstruction, inst.0));
+		};
+		let decoded = decoded.inst;
+		let pc = self.pc;
+		let mut next_pc = pc.wrapping_add(4);
+		// shifts only use as many low bits of rs2 as it takes to shift out every bit
+		let shamt_mask = self.xlen.bits() as i64 - 1;
+		// the CSR this instruction wrote, if any
+		let mut written = None;
+		match decoded {
+			Add { rd, rs1, rs2 } => self.set_reg(rd, self.reg(rs1).wrapping_add(self.reg(rs2))),
+			Sub { rd, rs1, rs2 } => self.set_reg(rd, self.reg(rs1).wrapping_sub(self.reg(rs2))),
+			And { rd, rs1, rs2 } => self.set_reg(rd, self.reg(rs1) & self.reg(rs2)),
+			Or { rd, rs1, rs2 } => self.set_reg(rd, self.reg(rs1) | self.reg(rs2)),
+			Xor { rd, rs1, rs2 } => self.set_reg(rd, self.reg(rs1) ^ self.reg(rs2)),
+			Sll { rd, rs1, rs2 } => self.set_reg(rd, self.reg(rs1) << (self.reg(rs2) & shamt_mask)),
+			Srl { rd, rs1, rs2 } => self.set_reg(rd, (self.ureg(rs1) >> (self.reg(rs2) & shamt_mask)) as i64),
+			Sra { rd, rs1, rs2 } => self.set_reg(rd, self.reg(rs1) >> (self.reg(rs2) & shamt_mask)),
+			Slt { rd, rs1, rs2 } => self.set_reg(rd, (self.reg(rs1) < self.reg(rs2)) as i64),
+			Sltu { rd, rs1, rs2 } => self.set_reg(rd, (self.ureg(rs1) < self.ureg(rs2)) as i64),
+			Mul { rd, rs1, rs2 } => self.set_reg(rd, self.reg(rs1).wrapping_mul(self.reg(rs2))),
+			Mulh { rd, rs1, rs2 } => self.set_reg(rd, self.high(self.reg(rs1) as i128 * self.reg(rs2) as i128)),
+			Mulhsu { rd, rs1, rs2 } => self.set_reg(rd, self.high(self.reg(rs1) as i128 * self.ureg(rs2) as i128)),
+			Mulhu { rd, rs1, rs2 } => self.set_reg(rd, self.high(self.ureg(rs1) as i128 * self.ureg(rs2) as i128)),
+			// division never traps: dividing by zero gives all ones (or the dividend for the remainder)
+			// and the one overflowing case, the most negative number divided by -1, wraps
+			Div { rd, rs1, rs2 } => {
+				let (a, b) = (self.reg(rs1), self.reg(rs2));
+				self.set_reg(rd, if b == 0 { -1 } else { a.wrapping_div(b) })
+			},
+			Divu { rd, rs1, rs2 } => {
+				let (a, b) = (self.ureg(rs1), self.ureg(rs2));
+				self.set_reg(rd, a.checked_div(b).unwrap_or(u64::MAX) as i64)
+			},
+			Rem { rd, rs1, rs2 } => {
+				let (a, b) = (self.reg(rs1), self.reg(rs2));
+				self.set_reg(rd, if b == 0 { a } else { a.wrapping_rem(b) })
+			},
+			Remu { rd, rs1, rs2 } => {
+				let (a, b) = (self.ureg(rs1), self.ureg(rs2));
+				self.set_reg(rd, a.checked_rem(b).unwrap_or(a) as i64)
+			},
+			Addi { rd, rs1, imm } => self.set_reg(rd, self.reg(rs1).wrapping_add(imm.into())),
+			Andi { rd, rs1, imm } => self.set_reg(rd, self.reg(rs1) & imm as i64),
+			Ori { rd, rs1, imm } => self.set_reg(rd, self.reg(rs1) | imm as i64),
+			Xori { rd, rs1, imm } => self.set_reg(rd, self.reg(rs1) ^ imm as i64),
+			Slti { rd, rs1, imm } => self.set_reg(rd, (self.reg(rs1) < imm.into()) as i64),
+			Sltiu { rd, rs1, imm } => self.set_reg(rd, (self.ureg(rs1) < self.unsigned(imm.into())) as i64),
+			Slli { rd, rs1, shamt } => self.set_reg(rd, self.reg(rs1) << shamt),
+			Srli { rd, rs1, shamt } => self.set_reg(rd, (self.ureg(rs1) >> shamt) as i64),
+			Srai { rd, rs1, shamt } => self.set_reg(rd, self.reg(rs1) >> shamt),
+			// the word instructions work on the low 32 bits and sign extend the result
+			Addw { rd, rs1, rs2 } => self.set_reg(rd, (self.reg(rs1) as i32).wrapping_add(self.reg(rs2) as i32).into()),
+			Subw { rd, rs1, rs2 } => self.set_reg(rd, (self.reg(rs1) as i32).wrapping_sub(self.reg(rs2) as i32).into()),
+			Sllw { rd, rs1, rs2 } => self.set_reg(rd, (self.reg(rs1) as i32).wrapping_shl(self.reg(rs2) as u32).into()),
+			Srlw { rd, rs1, rs2 } => {
+				self.set_reg(rd, ((self.reg(rs1) as u32).wrapping_shr(self.reg(rs2) as u32) as i32).into())
+			},
+			Sraw { rd, rs1, rs2 } => self.set_reg(rd, (self.reg(rs1) as i32).wrapping_shr(self.reg(rs2) as u32).into()),
+			Mulw { rd, rs1, rs2 } => self.set_reg(rd, (self.reg(rs1) as i32).wrapping_mul(self.reg(rs2) as i32).into()),
+			Divw { rd, rs1, rs2 } => {
+				let (a, b) = (self.reg(rs1) as i32, self.reg(rs2) as i32);
+				self.set_reg(rd, if b == 0 { -1 } else { a.wrapping_div(b).into() })
+			},
+			Divuw { rd, rs1, rs2 } => {
+				let (a, b) = (self.reg(rs1) as u32, self.reg(rs2) as u32);
+				self.set_reg(rd, (a.checked_div(b).unwrap_or(u32::MAX) as i32).into())
+			},
+			Remw { rd, rs1, rs2 } => {
+				let (a, b) = (self.reg(rs1) as i32, self.reg(rs2) as i32);
+				self.set_reg(rd, if b == 0 { a } else { a.wrapping_rem(b) }.into())
+			},
+			Remuw { rd, rs1, rs2 } => {
+				let (a, b) = (self.reg(rs1) as u32, self.reg(rs2) as u32);
+				self.set_reg(rd, (a.checked_rem(b).unwrap_or(a) as i32).into())
+			},
+			Addiw { rd, rs1, imm } => self.set_reg(rd, (self.reg(rs1) as i32).wrapping_add(imm).into()),
+			Slliw { rd, rs1, shamt } => self.set_reg(rd, ((self.reg(rs1) as i32) << shamt).into()),
+			Srliw { rd, rs1, shamt } => self.set_reg(rd, ((self.reg(rs1) as u32 >> shamt) as i32).into()),
+			Sraiw { rd, rs1, shamt } => self.set_reg(rd, ((self.reg(rs1) as i32) >> shamt).into()),
+			Lb { rd, rs1, offset } => {
+				let val = self.load(self.addr(rs1, offset, Access::Load)?, 1)?;
+				self.set_reg(rd, (val as i8).into())
+			},
+			Lbu { rd, rs1, offset } => {
+				let val = self.load(self.addr(rs1, offset, Access::Load)?, 1)?;
+				self.set_reg(rd, val.into())
+			},
+			Lh { rd, rs1, offset } => {
+				let val = self.load(self.addr(rs1, offset, Access::Load)?, 2)?;
+				self.set_reg(rd, (val as i16).into())
+			},
+			Lhu { rd, rs1, offset } => {
+				let val = self.load(self.addr(rs1, offset, Access::Load)?, 2)?;
+				self.set_reg(rd, val.into())
+			},
+			Lw { rd, rs1, offset } => {
+				let val = self.load(self.addr(rs1, offset, Access::Load)?, 4)?;
+				self.set_reg(rd, (val as i32).into())
+			},
+			Lwu { rd, rs1, offset } => {
+				let val = self.load(self.addr(rs1, offset, Access::Load)?, 4)?;
+				self.set_reg(rd, val.into())
+			},
+			Ld { rd, rs1, offset } => {
+				let val = self.load_double(self.addr(rs1, offset, Access::Load)?)?;
+				self.set_reg(rd, val as i64)
+			},
+			Sb { rs1, rs2, offset } => self.store(self.addr(rs1, offset, Access::Store)?, 1, self.reg(rs2) as u32)?,
+			Sh { rs1, rs2, offset } => self.store(self.addr(rs1, offset, Access::Store)?, 2, self.reg(rs2) as u32)?,
+			Sw { rs1, rs2, offset } => self.store(self.addr(rs1, offset, Access::Store)?, 4, self.reg(rs2) as u32)?,
+			Sd { rs1, rs2, offset } => self.store_double(self.addr(rs1, offset, Access::Store)?, self.reg(rs2) as u64)?,
+			Beq { rs1, rs2, offset } => {
+				if self.reg(rs1) == self.reg(rs2) {
+					next_pc = self.jump_target(pc.wrapping_add(offset))?;
+				}
+			},
+			Bne { rs1, rs2, offset } => {
+				if self.reg(rs1) != self.reg(rs2) {
+					next_pc = self.jump_target(pc.wrapping_add(offset))?;
 				}
 			},
-			0b0100011 => {
-				let addr: usize = (rs1 + imm).try_into().unwrap();
-				let bytes = rs2.to_le_bytes();
-				match inst.funct3() {
-					0b000 => self.mem[addr] = bytes[0],
-					0b001 => self.mem[addr..addr+2].copy_from_slice(&bytes[0..2]),
-					0b010 => self.mem[addr..addr+4].copy_from_slice(&bytes[0..4]),
-					_ => panic!("invalid instruction"),
+			Blt { rs1, rs2, offset } => {
+				if self.reg(rs1) < self.reg(rs2) {
+					next_pc = self.jump_target(pc.wrapping_add(offset))?;
 				}
 			},
-			0b1100011 => {
-				let cond = match inst.funct3() {
-					0b000 => rs1 == rs2,
-					0b101 => rs1 >= rs2,
-					0b111 => (rs1 as u32) >= (rs2 as u32),
-					0b100 => rs1 < rs2,
-					0b110 => (rs1 as u32) < (rs2 as u32),
-					0b001 => rs1 != rs2,
-					_ => panic!("invalid instruction"),
+			Bge { rs1, rs2, offset } => {
+				if self.reg(rs1) >= self.reg(rs2) {
+					next_pc = self.jump_target(pc.wrapping_add(offset))?;
+				}
+			},
+			Bltu { rs1, rs2, offset } => {
+				if self.ureg(rs1) < self.ureg(rs2) {
+					next_pc = self.jump_target(pc.wrapping_add(offset))?;
+				}
+			},
+			Bgeu { rs1, rs2, offset } => {
+				if self.ureg(rs1) >= self.ureg(rs2) {
+					next_pc = self.jump_target(pc.wrapping_add(offset))?;
+				}
+			},
+			Jal { rd, offset } => {
+				self.set_reg(rd, self.addr_value(pc.wrapping_add(4) as u32));
+				next_pc = self.jump_target(pc.wrapping_add(offset))?;
+			},
+			Jalr { rd, rs1, offset } => {
+				// the lowest bit of the target is dropped
+				let target = self.reg(rs1).wrapping_add(offset.into()) & !1;
+				let target = self.reg_addr(target).ok_or_else(|| self.trap(TrapCause::InstructionAccessFault, target as u32))?;
+				next_pc = self.jump_target(target as i32)?;
+				self.set_reg(rd, self.addr_value(pc.wrapping_add(4) as u32));
+			},
+			Lui { rd, imm } => self.set_reg(rd, imm.into()),
+			Auipc { rd, imm } => self.set_reg(rd, self.addr_value(pc as u32).wrapping_add(imm.into())),
+			Ecall => {
+				let cause = match self.privilege {
+					Privilege::User => TrapCause::UserEnvironmentCall,
+					Privilege::Supervisor => TrapCause::SupervisorEnvironmentCall,
+					Privilege::Machine => TrapCause::EnvironmentCall,
 				};
-				if cond {
-					self.pc += imm;
-					pcmod = true;
+				return Err(self.trap(cause, 0));
+			},
+			Ebreak => return Err(self.trap(TrapCause::Breakpoint, pc as u32)),
+			// returning goes back to the mode the trap came from, and leaves user mode as the previous one
+			Mret => {
+				self.require_privilege(Privilege::Machine, inst)?;
+				let mstatus = self.csrs.mstatus;
+				let mie = if mstatus & csr::MSTATUS_MPIE != 0 { csr::MSTATUS_MIE } else { 0 };
+				self.csrs.mstatus = (mstatus & !(csr::MSTATUS_MIE | csr::MSTATUS_MPP)) | mie | csr::MSTATUS_MPIE;
+				self.privilege = Privilege::from_bits((mstatus & csr::MSTATUS_MPP) >> 11);
+				// leaving machine mode turns MPRV off
+				if self.privilege < Privilege::Machine {
+					self.csrs.mstatus &= !csr::MSTATUS_MPRV;
 				}
+				next_pc = self.csrs.mepc as i32;
+			},
+			Sret => {
+				self.require_privilege(Privilege::Supervisor, inst)?;
+				self.require_unrestricted(csr::MSTATUS_TSR, inst)?;
+				let mstatus = self.csrs.mstatus;
+				let sie = if mstatus & csr::MSTATUS_SPIE != 0 { csr::MSTATUS_SIE } else { 0 };
+				self.csrs.mstatus =
+					(mstatus & !(csr::MSTATUS_SIE | csr::MSTATUS_SPP | csr::MSTATUS_MPRV)) | sie | csr::MSTATUS_SPIE;
+				self.privilege = Privilege::from_bits((mstatus & csr::MSTATUS_SPP) >> 8);
+				next_pc = self.csrs.sepc as i32;
+			},
+			SfenceVma { rs1, .. } => {
+				self.require_privilege(Privilege::Supervisor, inst)?;
+				self.require_unrestricted(csr::MSTATUS_TVM, inst)?;
+				self.tlb.flush((rs1 != 0).then(|| self.reg(rs1) as u32));
+			},
+			// nothing else can happen while waiting, so carry on until the next step takes the interrupt
+			Wfi => self.require_unrestricted(csr::MSTATUS_TW, inst)?,
+			Csrrw { rd, rs1, csr } => written = self.exec_csr(inst, rd, csr, def::CsrOp::Write, self.reg(rs1) as u64, true)?,
+			Csrrs { rd, rs1, csr } => written = self.exec_csr(inst, rd, csr, def::CsrOp::Set, self.reg(rs1) as u64, rs1 != 0)?,
+			Csrrc { rd, rs1, csr } => written = self.exec_csr(inst, rd, csr, def::CsrOp::Clear, self.reg(rs1) as u64, rs1 != 0)?,
+			Csrrwi { rd, uimm, csr } => written = self.exec_csr(inst, rd, csr, def::CsrOp::Write, uimm.into(), true)?,
+			Csrrsi { rd, uimm, csr } => written = self.exec_csr(inst, rd, csr, def::CsrOp::Set, uimm.into(), uimm != 0)?,
+			Csrrci { rd, uimm, csr } => written = self.exec_csr(inst, rd, csr, def::CsrOp::Clear, uimm.into(), uimm != 0)?,
+			Flw { rd, rs1, offset } => {
+				let val = self.load(self.addr(rs1, offset, Access::Load)?, 4)?;
+				self.set_freg(Fmt::S, rd, val.into())
 			},
-			0b1101111 => {
-				*rd = self.pc + 4;
-				self.pc += imm;
-				pcmod = true;
+			Fld { rd, rs1, offset } => {
+				let val = self.load_double(self.addr(rs1, offset, Access::Load)?)?;
+				self.set_freg(Fmt::D, rd, val)
 			},
-			0b1100111 => match inst.funct3() {
-				0b000 => {
-					*rd = self.pc + 4;
-					self.pc = rs1 + imm;
-					pcmod = true;
+			Fsw { rs1, rs2, offset } => self.store(self.addr(rs1, offset, Access::Store)?, 4, self.fregs[rs2 as usize] as u32)?,
+			Fsd { rs1, rs2, offset } => self.store_double(self.addr(rs1, offset, Access::Store)?, self.fregs[rs2 as usize])?,
+			// the negated forms negate the product, the subtracting ones the addend
+			Fmadd { fmt, rd, rs1, rs2, rs3, rm }
+			| Fmsub { fmt, rd, rs1, rs2, rs3, rm }
+			| Fnmsub { fmt, rd, rs1, rs2, rs3, rm }
+			| Fnmadd { fmt, rd, rs1, rs2, rs3, rm } => {
+				let (negate_product, negate_addend) = match decoded {
+					Fmadd { .. } => (false, false),
+					Fmsub { .. } => (false, true),
+					Fnmsub { .. } => (true, false),
+					_ => (true, true),
+				};
+				let a = self.freg(fmt, rs1);
+				let a = fpu::with_sign(fmt, a, fpu::sign_bit(fmt, a) ^ negate_product);
+				let c = self.freg(fmt, rs3);
+				let c = fpu::with_sign(fmt, c, fpu::sign_bit(fmt, c) ^ negate_addend);
+				let b = self.freg(fmt, rs2);
+				let val = self.fp(inst, Some(rm), |env| env.fused(fmt, a, b, c))?;
+				self.set_freg(fmt, rd, val)
+			},
+			Fadd { fmt, rd, rs1, rs2, rm }
+			| Fsub { fmt, rd, rs1, rs2, rm }
+			| Fmul { fmt, rd, rs1, rs2, rm }
+			| Fdiv { fmt, rd, rs1, rs2, rm } => {
+				let (a, b) = (self.freg(fmt, rs1), self.freg(fmt, rs2));
+				let val = self.fp(inst, Some(rm), |env| match decoded {
+					Fadd { .. } => env.add(fmt, a, b),
+					Fsub { .. } => env.sub(fmt, a, b),
+					Fmul { .. } => env.mul(fmt, a, b),
+					_ => env.div(fmt, a, b),
+				})?;
+				self.set_freg(fmt, rd, val)
+			},
+			Fsqrt { fmt, rd, rs1, rm } => {
+				let a = self.freg(fmt, rs1);
+				let val = self.fp(inst, Some(rm), |env| env.sqrt(fmt, a))?;
+				self.set_freg(fmt, rd, val)
+			},
+			Fsgnj { fmt, rd, rs1, rs2 } => self.sign_inject(fmt, rd, rs1, rs2, |_, sign| sign),
+			Fsgnjn { fmt, rd, rs1, rs2 } => self.sign_inject(fmt, rd, rs1, rs2, |_, sign| !sign),
+			Fsgnjx { fmt, rd, rs1, rs2 } => self.sign_inject(fmt, rd, rs1, rs2, |own, sign| own ^ sign),
+			Fmin { fmt, rd, rs1, rs2 } | Fmax { fmt, rd, rs1, rs2 } => {
+				let (a, b) = (self.freg(fmt, rs1), self.freg(fmt, rs2));
+				let val = self.fp(inst, None, |env| env.min_max(fmt, a, b, matches!(decoded, Fmax { .. })))?;
+				self.set_freg(fmt, rd, val)
+			},
+			// equality is a quiet comparison, so only signaling NaNs are invalid for it
+			Feq { fmt, rd, rs1, rs2 } | Flt { fmt, rd, rs1, rs2 } | Fle { fmt, rd, rs1, rs2 } => {
+				let (a, b) = (self.freg(fmt, rs1), self.freg(fmt, rs2));
+				let order = self.fp(inst, None, |env| env.compare(fmt, a, b, !matches!(decoded, Feq { .. })))?;
+				let holds = match decoded {
+					Feq { .. } => order == Some(Ordering::Equal),
+					Flt { .. } => order == Some(Ordering::Less),
+					_ => matches!(order, Some(Ordering::Less | Ordering::Equal)),
+				};
+				self.set_reg(rd, holds as i64)
+			},
+			Fclass { fmt, rd, rs1 } => self.set_reg(rd, fpu::classify(fmt, self.freg(fmt, rs1)).into()),
+			Fcvt { to, from, rd, rs1, rm } => match (to.fmt(), from.fmt()) {
+				(Some(to), Some(from)) => {
+					let a = self.freg(from, rs1);
+					let val = self.fp(inst, Some(rm), |env| env.convert(from, to, a))?;
+					self.set_freg(to, rd, val)
+				},
+				(None, Some(from)) => {
+					let a = self.freg(from, rs1);
+					let (signed, bits) = match to {
+						def::CvtType::W => (true, 32),
+						def::CvtType::Wu => (false, 32),
+						def::CvtType::L => (true, 64),
+						_ => (false, 64),
+					};
+					let val = self.fp(inst, Some(rm), |env| env.float_to_int(from, a, signed, bits))?;
+					self.set_reg(rd, val)
 				},
-				_ => panic!("invalid instruction"),
-			},
-			0b0010111 => {
-				*rd = self.pc + imm;	
-			},
-			0b0110111 => {
-				*rd = imm;
-			},
-			0b1110011 => match inst.funct3() {
-				0b000 => match imm {
-					0 => {
-						// ebreak
-					}
-					1 => {
-						// ecall
-						ret = Some((self.regs[10], self.regs[11]));
-					}
-					_ => panic!("invalid environment instruction immediate")
+				(Some(to), None) => {
+					let val = match from {
+						def::CvtType::W => self.reg(rs1) as i32 as i128,
+						def::CvtType::Wu => self.reg(rs1) as u32 as i128,
+						def::CvtType::L => self.reg(rs1) as i128,
+						_ => self.reg(rs1) as u64 as i128,
+					};
+					let val = self.fp(inst, Some(rm), |env| env.int_to_float(to, val))?;
+					self.set_freg(to, rd, val)
 				},
-				_ => panic!("invalid instruction"),
+				(None, None) => unreachable!("no conversion between two integer types"),
 			},
-			_ => panic!("invalid instruction"),
+			// moves copy the bits as they are, without unboxing single precision values
+			FmvX { fmt: Fmt::S, rd, rs1 } => self.set_reg(rd, (self.fregs[rs1 as usize] as i32).into()),
+			FmvX { fmt: Fmt::D, rd, rs1 } => self.set_reg(rd, self.fregs[rs1 as usize] as i64),
+			FmvF { fmt: Fmt::S, rd, rs1 } => self.set_freg(Fmt::S, rd, self.reg(rs1) as u32 as u64),
+			FmvF { fmt: Fmt::D, rd, rs1 } => self.set_freg(Fmt::D, rd, self.reg(rs1) as u64),
 		}
-		
+
 		self.regs[0] = 0;
-		if !pcmod {
-			self.pc += 4;
+		self.pc = next_pc;
+		// an explicit write to a counter takes the place of this instruction's increment
+		let wrote = |nums: [u32; 2]| written.is_some_and(|csr| nums.contains(&csr));
+		if !wrote([0xb00, 0xb80]) {
+			self.csrs.cycle = self.csrs.cycle.wrapping_add(1);
+		}
+		if !wrote([0xb02, 0xb82]) {
+			self.csrs.instret = self.csrs.instret.wrapping_add(1);
+		}
+
+		Ok(StepOutcome::Continue)
+	}
+
+	/// Whether the machine has `decoded`: it needs its extension, and RV32 has neither the RV64 only
+	/// instructions nor shifts by 32 or more.
+	fn implements(&self, decoded: &Decoded) -> bool {
+		let elem = decoded.elem;
+		let wide_shift = elem.operands().contains(&def::Operand::Shamt) && decoded.fields.imm >= Some(32);
+		self.extensions.has(elem.extension()) && (self.xlen == Xlen::Rv64 || !(elem.rv64_only() || wide_shift))
+	}
+
+	/// Read CSR `csr` into `rd` and, if `write` is set, update it with `val`. Set and clear with a zero
+	/// operand only read, so they work on read only CSRs. Returns `csr` if it was written.
+	fn exec_csr(
+		&mut self,
+		inst: Instruction,
+		rd: u32,
+		csr: u32,
+		op: def::CsrOp,
+		val: u64,
+		write: bool,
+	) -> Result<Option<u32>, Trap> {
+		self.require_privilege(csr::required_privilege(csr), inst)?;
+		if csr == 0x180 {
+			self.require_unrestricted(csr::MSTATUS_TVM, inst)?;
+		}
+		let Some(old) = self.read_csr(csr) else {
+			return Err(self.trap(TrapCause::IllegalInstruction, inst.0));
+		};
+		// writes to misa are ignored
+		if write && csr != 0x301 && self.write_csr(csr, op.apply(old, val)).is_none() {
+			return Err(self.trap(TrapCause::IllegalInstruction, inst.0));
 		}
-		
-		ret
+		self.set_reg(rd, old as i64);
+		Ok(write.then_some(csr))
 	}
-	
+
+	/// CSR `num` as the program sees it. `misa` depends on the machine, and on RV64 the counters are
+	/// read whole rather than in halves, the interrupt bit of the causes is the top one, and the status
+	/// registers show that user and supervisor mode are 64 bit too. `time` is the CLINT's `mtime` if
+	/// the machine has one.
+	fn read_csr(&self, num: u32) -> Option<u64> {
+		if num == 0x301 {
+			return Some(self.extensions.misa(self.xlen));
+		}
+		if let (Some(clint), 0xc01 | 0xc81) = (&self.clint, num) {
+			let time = clint.mtime();
+			return match (self.xlen, num) {
+				(Xlen::Rv32, 0xc01) => Some(time & 0xffff_ffff),
+				(Xlen::Rv32, _) => Some(time >> 32),
+				(_, 0xc01) => Some(time),
+				_ => None,
+			};
+		}
+		// the floating-point CSRs come with F
+		if (0x001..=0x003).contains(&num) && !self.extensions.f {
+			return None;
+		}
+		if self.xlen == Xlen::Rv32 {
+			return self.csrs.read(num).map(u64::from);
+		}
+		let uxl = 2 << 32;
+		let sxl = 2 << 34;
+		Some(match num {
+			0x100 => self.csrs.read(num)? as u64 | uxl,
+			0x300 => self.csrs.read(num)? as u64 | uxl | sxl,
+			0x142 | 0x342 => {
+				let cause = self.csrs.read(num)?;
+				(cause & !(1 << 31)) as u64 | ((cause >> 31) as u64) << 63
+			},
+			// Sv32 doesn't exist on RV64, and bare is the only mode left
+			0x180 => 0,
+			0xb00 | 0xc00 | 0xc01 => self.csrs.cycle,
+			0xb02 | 0xc02 => self.csrs.instret,
+			0xb80 | 0xb82 | 0xc80 | 0xc81 | 0xc82 => return None,
+			_ => self.csrs.read(num)? as u64,
+		})
+	}
+
+	/// Write CSR `num` the way the program sees it, see `read_csr`.
+	fn write_csr(&mut self, num: u32, val: u64) -> Option<()> {
+		if self.xlen == Xlen::Rv32 {
+			return self.csrs.write(num, val as u32);
+		}
+		match num {
+			0x142 | 0x342 => self.csrs.write(num, (val as u32 & !(1 << 31)) | ((val >> 63) as u32) << 31),
+			0x180 => Some(()),
+			0xb00 => {
+				self.csrs.cycle = val;
+				Some(())
+			},
+			0xb02 => {
+				self.csrs.instret = val;
+				Some(())
+			},
+			0xb80 | 0xb82 => None,
+			_ => self.csrs.write(num, val as u32),
+		}
+	}
+
+	/// Run a floating-point operation with rounding mode `rm`, which is the one in `frm` if it's dynamic,
+	/// and accrue the exception flags it raises. Reserved rounding modes make `inst` illegal. `None` is
+	/// for the operations that don't round.
+	fn fp<T>(&mut self, inst: Instruction, rm: Option<u32>, op: impl FnOnce(&mut fpu::Env) -> T) -> Result<T, Trap> {
+		let rm = match rm {
+			Some(def::RM_DYN) => self.csrs.fcsr >> 5 & 7,
+			rm => rm.unwrap_or(0),
+		};
+		let Some(rounding) = fpu::Rounding::from_bits(rm) else {
+			return Err(self.trap(TrapCause::IllegalInstruction, inst.0));
+		};
+		let mut env = fpu::Env { rounding, flags: 0 };
+		let result = op(&mut env);
+		self.csrs.fcsr |= env.flags;
+		Ok(result)
+	}
+
+	/// The `fsgnj` instructions: `rs1` with the sign that `sign` picks from its own and that of `rs2`.
+	fn sign_inject(&mut self, fmt: Fmt, rd: u32, rs1: u32, rs2: u32, sign: impl Fn(bool, bool) -> bool) {
+		let (a, b) = (self.freg(fmt, rs1), self.freg(fmt, rs2));
+		let sign = sign(fpu::sign_bit(fmt, a), fpu::sign_bit(fmt, b));
+		self.set_freg(fmt, rd, fpu::with_sign(fmt, a, sign))
+	}
+
+	/// Make `inst` illegal below privilege mode `mode`.
+	fn require_privilege(&self, mode: Privilege, inst: Instruction) -> Result<(), Trap> {
+		if self.privilege < mode {
+			return Err(self.trap(TrapCause::IllegalInstruction, inst.0));
+		}
+		Ok(())
+	}
+
+	/// Make `inst` illegal below machine mode if `bit` of `mstatus`, which is TVM, TW or TSR, is set.
+	fn require_unrestricted(&self, bit: u32, inst: Instruction) -> Result<(), Trap> {
+		if self.privilege < Privilege::Machine && self.csrs.mstatus & bit != 0 {
+			return Err(self.trap(TrapCause::IllegalInstruction, inst.0));
+		}
+		Ok(())
+	}
+
+	fn trap(&self, cause: TrapCause, tval: u32) -> Trap {
+		Trap {
+			cause,
+			pc: self.pc as u32,
+			tval,
+		}
+	}
+
+	/// Check that a jump or taken branch lands on an instruction boundary.
+	fn jump_target(&self, target: i32) -> Result<i32, Trap> {
+		if target % 4 == 0 {
+			Ok(target)
+		} else {
+			Err(self.trap(TrapCause::InstructionMisaligned, target as u32))
+		}
+	}
+
+	fn reg(&self, reg: u32) -> i64 {
+		self.regs[reg as usize]
+	}
+
+	/// The value of `reg` as an unsigned XLEN bit number.
+	fn ureg(&self, reg: u32) -> u64 {
+		self.unsigned(self.reg(reg))
+	}
+
+	/// `val` as an unsigned XLEN bit number.
+	fn unsigned(&self, val: i64) -> u64 {
+		val as u64 & u64::MAX >> (64 - self.xlen.bits())
+	}
+
+	/// The upper XLEN bits of a product.
+	fn high(&self, product: i128) -> i64 {
+		(product >> self.xlen.bits()) as i64
+	}
+
+	fn set_reg(&mut self, reg: u32, val: i64) {
+		self.regs[reg as usize] = self.xlen.wrap(val);
+	}
+
+	/// The value of floating-point register `reg` in format `fmt`. Single precision values that
+	/// aren't properly NaN-boxed read as the canonical NaN.
+	fn freg(&self, fmt: Fmt, reg: u32) -> u64 {
+		let val = self.fregs[reg as usize];
+		match fmt {
+			Fmt::S if val >> 32 != 0xffff_ffff => fpu::canonical_nan(Fmt::S),
+			Fmt::S => val & 0xffff_ffff,
+			Fmt::D => val,
+		}
+	}
+
+	fn set_freg(&mut self, fmt: Fmt, reg: u32, val: u64) {
+		self.fregs[reg as usize] = match fmt {
+			Fmt::S => 0xffff_ffff << 32 | val,
+			Fmt::D => val,
+		};
+	}
+
+	/// How a register holds `addr`.
+	fn addr_value(&self, addr: u32) -> i64 {
+		self.xlen.wrap(addr.into())
+	}
+
+	/// The address a register value points at, or `None` if it's beyond the 32 bit address space.
+	fn reg_addr(&self, val: i64) -> Option<u32> {
+		match self.xlen {
+			Xlen::Rv32 => Some(val as u32),
+			Xlen::Rv64 => val.try_into().ok(),
+		}
+	}
+
+	/// The address `offset` bytes from the one in `rs1`, for an access of kind `access`.
+	fn addr(&self, rs1: u32, offset: i32, access: Access) -> Result<u32, Trap> {
+		let addr = self.reg(rs1).wrapping_add(offset.into());
+		self.reg_addr(addr).ok_or_else(|| self.trap(access.access_fault(), addr as u32))
+	}
+
+	/// Read `width` bytes from the bus as a zero extended little endian value, the way a load
+	/// instruction at the pc would, translating `addr` if paging is on. Accesses have to be aligned
+	/// to their width.
+	pub fn load(&mut self, addr: u32, width: usize) -> Result<u32, Trap> {
+		if !(addr as usize).is_multiple_of(width) {
+			return Err(self.trap(TrapCause::LoadMisaligned, addr));
+		}
+		let phys = self.translate(addr, Access::Load)?;
+		self.bus.read(phys, width).map_err(|AccessFault| self.trap(TrapCause::LoadAccessFault, addr))
+	}
+
+	/// `load` for environment calls reading the program's memory. Devices fault instead of being read,
+	/// so a bad pointer can't take a UART's input or otherwise change a device.
+	pub(crate) fn load_memory(&mut self, addr: u32, width: usize) -> Result<u32, Trap> {
+		if !(addr as usize).is_multiple_of(width) {
+			return Err(self.trap(TrapCause::LoadMisaligned, addr));
+		}
+		let phys = self.translate(addr, Access::Load)?;
+		if !self.bus.is_memory(phys, width) {
+			return Err(self.trap(TrapCause::LoadAccessFault, addr));
+		}
+		self.bus.read(phys, width).map_err(|AccessFault| self.trap(TrapCause::LoadAccessFault, addr))
+	}
+
+	/// Write the low `width` bytes of `val` to the bus, the way a store instruction at the pc would.
+	pub fn store(&mut self, addr: u32, width: usize, val: u32) -> Result<(), Trap> {
+		if !(addr as usize).is_multiple_of(width) {
+			return Err(self.trap(TrapCause::StoreMisaligned, addr));
+		}
+		let phys = self.translate(addr, Access::Store)?;
+		self.bus.write(phys, width, val).map_err(|AccessFault| self.trap(TrapCause::StoreAccessFault, addr))
+	}
+
+	/// `load` for 8 bytes, which the bus reads as two words.
+	fn load_double(&mut self, addr: u32) -> Result<u64, Trap> {
+		if !addr.is_multiple_of(8) {
+			return Err(self.trap(TrapCause::LoadMisaligned, addr));
+		}
+		let low = self.load(addr, 4)?;
+		let high = self.load(addr + 4, 4)?;
+		Ok((high as u64) << 32 | low as u64)
+	}
+
+	/// `store` for 8 bytes, which the bus writes as two words. Both are translated before either is
+	/// written, so a page fault leaves memory as it was.
+	fn store_double(&mut self, addr: u32, val: u64) -> Result<(), Trap> {
+		if !addr.is_multiple_of(8) {
+			return Err(self.trap(TrapCause::StoreMisaligned, addr));
+		}
+		let (low, high) = (self.translate(addr, Access::Store)?, self.translate(addr + 4, Access::Store)?);
+		let fault = self.trap(TrapCause::StoreAccessFault, addr);
+		self.bus.write(low, 4, val as u32).map_err(|AccessFault| fault)?;
+		self.bus.write(high, 4, (val >> 32) as u32).map_err(|AccessFault| fault)
+	}
+
 	pub fn dump_registers(&self) {
 		println!("\nRegisters\n---------");
 		for i in 0..32 {
@@ -160,7 +985,8 @@ fn test_machine() {
 	let test = "
 	addi x1 x0 10
 	";
-	machine.run(&compile(test));
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
 	assert!(machine.regs[1] == 10);
 }
 
@@ -178,7 +1004,8 @@ fn test_machine_fib() {
 	addi t2 t2 -1
 	blt x0 t2 loop
 	";
-	machine.run(&compile(test));
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
 	assert!(machine.regs[5] == 8);
 }
 
@@ -197,8 +1024,8 @@ fn test_machine_fib2() {
 	bge t5 x0 start
 	addi t3 x0 -1
 	";
-	let code = compile(test);
-	machine.run(&code);
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
 	assert!(machine.regs[5] == 89);
 }
 
@@ -210,7 +1037,8 @@ fn test_pseudo_insts() {
 	li x2 2500
 	li x3 -10000
 	";
-	machine.run(&compile(test));
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
 	assert_eq!(machine.regs[1], 3);
 	assert_eq!(machine.regs[2], 2500);
 	assert_eq!(machine.regs[3], -10000);
@@ -275,5 +1103,982 @@ ex3_end:
     addi sp sp 4
     ret
 	";
-	machine.run(&compile(test));
-}
\ No newline at end of file
+	// stops at the first ecall, which prints 2^10
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	let trap = machine.run().unwrap_err();
+	assert_eq!(trap.cause, TrapCause::EnvironmentCall);
+	assert_eq!((machine.regs[10], machine.regs[11]), (1, 1024));
+}
+
+#[test]
+fn test_imm_ops_and_loads() {
+	let mut machine = Machine::new(1024);
+	let test = "
+	li t0 90
+	andi a0 t0 15
+	ori a1 t0 256
+	xori a2 t0 -1
+	slti a3 t0 100
+	sltiu a4 t0 -1
+	slli a5 t0 4
+	li t1 -2
+	sw t1 0(x0)
+	lb s2 0(x0)
+	lbu s3 0(x0)
+	lh s4 0(x0)
+	lhu s5 0(x0)
+	lw s6 0(x0)
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.regs[10], 90 & 15);
+	assert_eq!(machine.regs[11], 90 | 256);
+	assert_eq!(machine.regs[12], !90);
+	assert_eq!(machine.regs[13], 1);
+	assert_eq!(machine.regs[14], 1);
+	assert_eq!(machine.regs[15], 90 << 4);
+	assert_eq!(machine.regs[18], -2);
+	assert_eq!(machine.regs[19], 0xfe);
+	assert_eq!(machine.regs[20], -2);
+	assert_eq!(machine.regs[21], 0xfffe);
+	assert_eq!(machine.regs[22], -2);
+}
+
+#[test]
+fn test_data_section() {
+	let mut machine = Machine::new(1024);
+	let test = r#"
+.data
+numbers: .word 3, 4, 5
+greeting: .asciiz "hey"
+.text
+	la t0, numbers
+	lw a0, 0(t0)
+	lw a1, 8(t0)
+	la t1, greeting
+	lbu a2, 1(t1)
+	lbu a3, 3(t1)
+	"#;
+	let image = assemble(test).unwrap();
+	machine.load_image(&image).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.regs[10], 3);
+	assert_eq!(machine.regs[11], 5);
+	assert_eq!(machine.regs[12], b'e' as i64);
+	assert_eq!(machine.regs[13], 0);
+}
+
+#[test]
+fn test_more_pseudo_insts() {
+	let mut machine = Machine::new(1024);
+	let test = "
+	li t1, 0x1f080
+	sext.b a0, t1
+	zext.b a1, t1
+	sext.h a2, t1
+	zext.h a3, t1
+	seqz a4, zero
+	snez a5, t1
+	call func
+	bgtz a6, done
+	li a6, -1
+done:
+	ecall
+func:
+	li a6, 7
+	ret
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	let trap = machine.run().unwrap_err();
+	assert_eq!(trap.cause, TrapCause::EnvironmentCall);
+	assert_eq!(machine.regs[10], -128);
+	assert_eq!(machine.regs[11], 0x80);
+	assert_eq!(machine.regs[12], -0x0f80);
+	assert_eq!(machine.regs[13], 0xf080);
+	assert_eq!(machine.regs[14], 1);
+	assert_eq!(machine.regs[15], 1);
+	assert_eq!(machine.regs[16], 7);
+}
+
+#[test]
+fn test_m_extension() {
+	let mut machine = Machine::new(1024);
+	let test = "
+	li t0, -7
+	li t1, 2
+	li t2, 0x80000000
+	li t3, -1
+	mul a0, t0, t1
+	mulh a1, t2, t2
+	mulhsu a2, t3, t3
+	mulhu a3, t3, t3
+	div a4, t0, t1
+	divu a5, t0, t1
+	rem a6, t0, t1
+	remu a7, t0, t1
+	div s2, t0, zero
+	divu s3, t0, zero
+	rem s4, t0, zero
+	remu s5, t0, zero
+	div s6, t2, t3
+	rem s7, t2, t3
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.regs[10], -14);
+	assert_eq!(machine.regs[11], 0x40000000);
+	assert_eq!(machine.regs[12], -1);
+	assert_eq!(machine.regs[13], -2);
+	assert_eq!(machine.regs[14], -3);
+	assert_eq!(machine.regs[15], 0x7ffffffc);
+	assert_eq!(machine.regs[16], -1);
+	assert_eq!(machine.regs[17], 1);
+	assert_eq!(machine.regs[18], -1);
+	assert_eq!(machine.regs[19], -1);
+	assert_eq!(machine.regs[20], -7);
+	assert_eq!(machine.regs[21], -7);
+	assert_eq!(machine.regs[22], i32::MIN as i64);
+	assert_eq!(machine.regs[23], 0);
+}
+
+#[test]
+fn test_m_extension_disabled() {
+	let mut machine = Machine::new(1024);
+	machine.extensions.m = false;
+	machine.load_image(&assemble("mul a0, a0, a0").unwrap()).unwrap();
+	let trap = machine.run().unwrap_err();
+	assert_eq!(trap.cause, TrapCause::IllegalInstruction);
+	assert_eq!(trap.tval, 0x02a50533);
+	assert_eq!(trap.to_string(), "illegal instruction 0x02a50533 at pc 0x0");
+}
+
+/// Run `source` on a fresh machine and return `a0`.
+#[cfg(test)]
+fn run_a0(source: &str) -> i32 {
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble(source).unwrap()).unwrap();
+	machine.run().unwrap();
+	machine.regs[10] as i32
+}
+
+#[test]
+fn test_alu_edge_cases() {
+	let cases: &[(&str, i32)] = &[
+		("li t0, 0x7fffffff\nli t1, 1\nadd a0, t0, t1", i32::MIN),
+		("li t0, 0x80000000\nli t1, 1\nsub a0, t0, t1", i32::MAX),
+		("li t0, 0x80000000\nsub a0, zero, t0", i32::MIN),
+		("li t0, 0xf0f0f0f0\nli t1, 0xff00ff00\nand a0, t0, t1", 0xf000f000u32 as i32),
+		("li t0, 0xf0f0f0f0\nli t1, 0x0f00ff00\nor a0, t0, t1", 0xfff0fff0u32 as i32),
+		("li t0, 0xf0f0f0f0\nli t1, -1\nxor a0, t0, t1", 0x0f0f0f0f),
+		("li t0, 1\nli t1, 33\nsll a0, t0, t1", 2),
+		("li t0, 1\nli t1, 31\nsll a0, t0, t1", i32::MIN),
+		("li t0, 0x80000000\nli t1, 63\nsrl a0, t0, t1", 1),
+		("li t0, 0x80000000\nli t1, -1\nsra a0, t0, t1", -1),
+		("li t0, 0x80000000\nli t1, 32\nsra a0, t0, t1", i32::MIN),
+		("li t0, -1\nslt a0, t0, zero", 1),
+		("li t0, -1\nsltu a0, t0, zero", 0),
+		("li t0, -1\nsltu a0, zero, t0", 1),
+		("li t0, 0x7fffffff\naddi a0, t0, 1", i32::MIN),
+		("li t0, 0x80000000\naddi a0, t0, -1", i32::MAX),
+		("li t0, 0x12345678\nandi a0, t0, -16", 0x12345670),
+		("li t0, 0x12345678\nori a0, t0, -2048", 0xfffffe78u32 as i32),
+		("li t0, 0x12345678\nxori a0, t0, -1", !0x12345678),
+		("li t0, -5\nslti a0, t0, -4", 1),
+		("li t0, -5\nslti a0, t0, -5", 0),
+		// the immediate is sign extended and then compared as unsigned
+		("li t0, 5\nsltiu a0, t0, -1", 1),
+		("li t0, -1\nsltiu a0, t0, -1", 0),
+		("li t0, 3\nslli a0, t0, 31", i32::MIN),
+		("li t0, -1\nsrli a0, t0, 31", 1),
+		("li t0, 0x80000000\nsrai a0, t0, 31", -1),
+		("li t0, -1\nsrai a0, t0, 0", -1),
+		("lui a0, 0xfffff", -4096),
+		("lui a0, 0x80000\naddi a0, a0, -1", i32::MAX),
+		("nop\nauipc a0, 0x1", 4 + 4096),
+		("auipc a0, 0xfffff", -4096),
+	];
+	for &(source, expected) in cases {
+		assert_eq!(run_a0(source), expected, "{source}");
+	}
+}
+
+#[test]
+fn test_memory_edge_cases() {
+	let cases: &[(&str, i32)] = &[
+		("li t0, 0x8081\nsh t0, 64(zero)\nlb a0, 64(zero)", -127),
+		("li t0, 0x8081\nsh t0, 64(zero)\nlbu a0, 64(zero)", 0x81),
+		("li t0, 0x8081\nsh t0, 64(zero)\nlh a0, 64(zero)", -0x7f7f),
+		("li t0, 0x8081\nsh t0, 64(zero)\nlhu a0, 64(zero)", 0x8081),
+		("li t0, 0x89abcdef\nsw t0, 64(zero)\nlw a0, 64(zero)", 0x89abcdefu32 as i32),
+		// stores only write their width
+		("li t0, -1\nsw t0, 64(zero)\nsb zero, 65(zero)\nlw a0, 64(zero)", 0xffff00ffu32 as i32),
+		("li t0, -1\nsw t0, 64(zero)\nsh zero, 66(zero)\nlw a0, 64(zero)", 0xffff),
+		// negative offsets from a base register
+		("li t0, 100\nli t1, 7\nsw t1, -36(t0)\nlw a0, 64(zero)", 7),
+	];
+	for &(source, expected) in cases {
+		assert_eq!(run_a0(source), expected, "{source}");
+	}
+}
+
+#[test]
+fn test_control_flow_edge_cases() {
+	// each branch skips `li a0, 1` when taken
+	let branches: &[(&str, i32, i32, bool)] = &[
+		("beq", 5, 5, true),
+		("beq", 5, 6, false),
+		("bne", 5, 6, true),
+		("bne", -1, -1, false),
+		("blt", -1, 0, true),
+		("blt", 0, -1, false),
+		("bge", -1, -1, true),
+		("bge", -2, -1, false),
+		("bltu", 0, -1, true),
+		("bltu", -1, 0, false),
+		("bgeu", -1, 0, true),
+		("bgeu", 0, -1, false),
+	];
+	for &(name, a, b, taken) in branches {
+		let source = format!("li t0, {a}\nli t1, {b}\n{name} t0, t1, skip\nli a0, 1\nskip:");
+		assert_eq!(run_a0(&source), !taken as i32, "{source}");
+	}
+
+	let mut machine = Machine::new(1024);
+	let source = "
+	jal ra, target
+	li a1, 1
+target:
+	auipc t0, 0
+	addi t0, t0, 17
+	jalr a2, t0, 0
+	li a3, 1
+	li a4, 2
+	";
+	machine.load_image(&assemble(source).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.regs[1], 4);
+	assert_eq!(machine.regs[11], 0);
+	// the jump lands on 24, not 25
+	assert_eq!(machine.regs[12], 20);
+	assert_eq!(machine.regs[13], 0);
+	assert_eq!(machine.regs[14], 2);
+}
+
+#[test]
+fn test_traps() {
+	let trap = |source: &str| {
+		let mut machine = Machine::new(1024);
+		machine.load_image(&assemble(source).unwrap()).unwrap();
+		let trap = machine.run().unwrap_err();
+		// nothing the trapping instruction would have done is visible
+		assert_eq!(machine.pc as u32, trap.pc);
+		(trap, machine)
+	};
+
+	let (load, machine) = trap("li t0, 7\nlw t0, -16(zero)");
+	assert_eq!((load.cause, load.pc, load.tval), (TrapCause::LoadAccessFault, 4, 0xfffffff0));
+	assert_eq!(load.to_string(), "load access fault at pc 0x4 reading 0xfffffff0");
+	assert_eq!(machine.regs[5], 7);
+
+	let (load, _) = trap("lh t0, 1(zero)");
+	assert_eq!((load.cause, load.tval), (TrapCause::LoadMisaligned, 1));
+
+	let (store, machine) = trap("li t0, -1\nsw t0, 1022(zero)");
+	assert_eq!((store.cause, store.tval), (TrapCause::StoreMisaligned, 1022));
+	assert_eq!(machine.peek_bytes(1022, 2), [0, 0]);
+
+	let (store, _) = trap("sb t0, 1024(zero)");
+	assert_eq!((store.cause, store.tval), (TrapCause::StoreAccessFault, 1024));
+
+	let (jump, machine) = trap("li t0, 18\njalr ra, t0, 0");
+	assert_eq!((jump.cause, jump.pc, jump.tval), (TrapCause::InstructionMisaligned, 4, 18));
+	assert_eq!(machine.regs[1], 0);
+
+	let (branch, _) = trap("beq zero, zero, 6");
+	assert_eq!((branch.cause, branch.tval), (TrapCause::InstructionMisaligned, 6));
+
+	let (brk, _) = trap("nop\nebreak");
+	assert_eq!((brk.cause, brk.pc, brk.tval), (TrapCause::Breakpoint, 4, 4));
+	assert_eq!(brk.cause.code(), 3);
+
+	let mut machine = Machine::new(1024);
+	let illegal = machine.exec(Instruction(0xffffffff)).unwrap_err();
+	assert_eq!((illegal.cause, illegal.tval), (TrapCause::IllegalInstruction, 0xffffffff));
+	assert_eq!(machine.step(), Ok(StepOutcome::Finished));
+}
+
+#[test]
+fn test_load_image() {
+	let source = "
+	la t0, table
+	lw t1, 4(t0)
+	jalr ra, t1, 0
+	# patch the `li a0, 1` below into `li a0, 2` and run it
+	la t2, patched
+	lw t3, 0(t2)
+	lui t4, 0x100
+	add t3, t3, t4
+	sw t3, 0(t2)
+patched:
+	li a0, 1
+	j end
+first:
+	li a1, 10
+	ret
+second:
+	li a1, 20
+	ret
+end:
+.data
+table: .word first, second
+";
+	let layout = compile::Layout {
+		text_base: 0x100,
+		data_base: Some(0x200),
+	};
+	let image = assemble_at(source, &layout).unwrap();
+	let mut machine = Machine::new(1024);
+	machine.load_image(&image).unwrap();
+	assert_eq!(machine.pc, 0x100);
+	machine.run().unwrap();
+	assert_eq!(machine.regs[10], 2);
+	assert_eq!(machine.regs[11], 20);
+	assert_eq!(machine.pc as u32, image.symbols["end"]);
+	assert_eq!(machine.peek_bytes(0x204, 4), image.symbols["second"].to_le_bytes());
+
+	let mut machine = Machine::new(0x200);
+	assert_eq!(machine.load_image(&image), Err(LoadError { base: 0x200, len: 8 }));
+
+	// a segment running into a device is refused before any of it is written
+	let mut machine = Machine::new(1024);
+	let uart = Uart::new();
+	machine.bus.map(1024, UART_SIZE, uart.clone());
+	assert_eq!(machine.load_segment(1020, b"abcdefgh"), Err(LoadError { base: 1020, len: 8 }));
+	assert_eq!(machine.peek_bytes(1020, 4), [0; 4]);
+	assert!(uart.take_output().is_empty());
+
+	// jumping past the end of memory faults on the fetch
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble("li t0, 1024\njr t0").unwrap()).unwrap();
+	let trap = machine.run().unwrap_err();
+	assert_eq!((trap.cause, trap.pc, trap.tval), (TrapCause::InstructionAccessFault, 1024, 1024));
+}
+
+#[test]
+fn test_mmio() {
+	/// Counts up on every read and remembers the last word written.
+	#[derive(Default)]
+	struct Counter {
+		count: u32,
+		last: u32,
+	}
+
+	impl Bus for Counter {
+		fn read(&mut self, addr: u32, width: usize) -> Result<u32, AccessFault> {
+			if addr != 0 || width != 4 {
+				return Err(AccessFault);
+			}
+			self.count += 1;
+			Ok(self.count)
+		}
+
+		fn write(&mut self, addr: u32, width: usize, val: u32) -> Result<(), AccessFault> {
+			if addr != 4 || width != 4 {
+				return Err(AccessFault);
+			}
+			self.last = val;
+			Ok(())
+		}
+	}
+
+	let mut machine = Machine::new(1024);
+	machine.bus.map(0x1000_0000, 8, Counter::default());
+	let source = "
+	li t0, 0x10000000
+	lw a0, 0(t0)
+	lw a0, 0(t0)
+	sw a0, 4(t0)
+	lb a1, 0(t0)
+	";
+	machine.load_image(&assemble(source).unwrap()).unwrap();
+	let trap = machine.run().unwrap_err();
+	assert_eq!(machine.regs[10], 2);
+	assert_eq!((trap.cause, trap.tval), (TrapCause::LoadAccessFault, 0x1000_0000));
+	// devices don't have to support peeking
+	assert_eq!(machine.peek_bytes(0x1000_0000, 2), [0, 0]);
+
+	// a machine can also run straight on top of a single device
+	let mut machine = Machine::with_bus(Ram::new(64));
+	machine.load_image(&assemble("li a0, 5\nsw a0, 60(zero)").unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.bus.bytes[60], 5);
+	assert_eq!(machine.load_image(&assemble("li a0, 5").unwrap()), Ok(()));
+	assert_eq!(machine.load_segment(62, &[1, 2, 3]), Err(LoadError { base: 62, len: 3 }));
+}
+
+#[test]
+fn test_uart_echo() {
+	let mut machine = Machine::new(1024);
+	let uart = Uart::new();
+	machine.bus.map(UART_BASE, UART_SIZE, uart.clone());
+	uart.push_input(b"abc\n");
+	// read a line, then print it back in upper case
+	let source = "
+	li s0, 0x10000000
+	la s1, buf
+read:
+	lbu t0, 5(s0)
+	andi t0, t0, 1
+	beqz t0, read
+	lbu t0, 0(s0)
+	li t1, 10
+	beq t0, t1, print
+	addi t0, t0, -32
+	sb t0, 0(s1)
+	addi s1, s1, 1
+	j read
+print:
+	la s2, buf
+loop:
+	lbu t0, 5(s0)
+	andi t0, t0, 0x20
+	beqz t0, loop
+	lbu t0, 0(s2)
+	sb t0, 0(s0)
+	addi s2, s2, 1
+	bne s2, s1, loop
+.data
+buf: .space 16
+	";
+	machine.load_image(&assemble(source).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(uart.take_output(), b"ABC");
+}
+
+#[test]
+fn test_load_elf() {
+	let source = "
+	nop
+_start:
+	lw a0, 0(gp)
+	addi a0, a0, 5
+	sw a0, 4(gp)
+	li a7, 93
+	ecall
+	";
+	let layout = compile::Layout {
+		text_base: 0x100,
+		data_base: None,
+	};
+	let image = assemble_at(source, &layout).unwrap();
+	let text = image.text_bytes();
+	let bss = 0x100 + text.len() as u32;
+	let symbols = [("_start", image.symbols["_start"]), ("__global_pointer$", bss)];
+	let file = elf::build_elf(0x100, image.symbols["_start"], &text, 8, &symbols);
+	let elf = Elf::parse(&file).unwrap();
+
+	let mut machine = Machine::new(1000);
+	machine.load_segment(bss, &[0xff; 8]).unwrap();
+	machine.load_elf(&elf).unwrap();
+	assert_eq!(machine.pc, 0x104);
+	assert_eq!(machine.regs[2], 992);
+	assert_eq!(machine.regs[3] as u32, bss);
+	assert_eq!(machine.run_with(&mut Linux::new(0x200)), Ok(StepOutcome::Exited(5)));
+	assert_eq!(machine.peek_bytes(bss, 8), [0, 0, 0, 0, 5, 0, 0, 0]);
+
+	// linked where riscv-tests and most boards put RAM
+	let layout = compile::Layout {
+		text_base: 0x8000_0000,
+		data_base: None,
+	};
+	let image = assemble_at(source, &layout).unwrap();
+	let bss = 0x8000_0000 + text.len() as u32;
+	let symbols = [("__global_pointer$", bss)];
+	let file = elf::build_elf(0x8000_0000, image.symbols["_start"], &text, 8, &symbols);
+	let mut machine = Machine::with_ram(0x8000_0000, 1000, Xlen::Rv32);
+	machine.load_elf(&Elf::parse(&file).unwrap()).unwrap();
+	assert_eq!(machine.regs[2] as u32, 0x8000_03e0);
+	assert_eq!(machine.run_with(&mut Linux::new(0x8000_0200)), Ok(StepOutcome::Exited(5)));
+	assert_eq!(machine.peek_bytes(bss, 8), [0, 0, 0, 0, 5, 0, 0, 0]);
+
+	// a .bss far bigger than memory fails at the end of RAM instead of being allocated
+	let file = elf::build_elf(0x100, 0x100, &text, 0xf000_0000, &[]);
+	let mut machine = Machine::new(1000);
+	let err = machine.load_elf(&Elf::parse(&file).unwrap()).unwrap_err();
+	assert_eq!((err.base, err.len), (0x100, text.len() + 0xf000_0000));
+}
+
+#[test]
+fn test_csrs() {
+	let mut machine = Machine::new(1024);
+	let test = "
+	li t0, 0x1234
+	csrw mscratch, t0
+	csrrwi s0, mscratch, 7
+	csrr s1, mscratch
+	csrsi mscratch, 8
+	csrrci s2, mscratch, 3
+	csrr s3, mscratch
+	csrr s4, misa
+	csrr s5, mhartid
+	rdinstret s6
+	rdcycleh s7
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(&machine.regs[8..10], [0x1234, 7]);
+	assert_eq!(&machine.regs[18..24], [15, 12, 0x4014_1128, 0, 10, 0]);
+	assert_eq!(machine.csrs.instret, 12);
+
+	// unknown CSRs and writes to read only ones are illegal, but reading those is fine
+	for (test, word) in [("csrr a0, 0x7c0", 0x7c002573), ("csrw cycle, a0", 0xc0051073), ("csrrs a0, time, a1", 0xc015a573)] {
+		let mut machine = Machine::new(1024);
+		machine.load_image(&assemble(test).unwrap()).unwrap();
+		let trap = machine.run().unwrap_err();
+		assert_eq!((trap.cause, trap.tval), (TrapCause::IllegalInstruction, word));
+	}
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble("csrrs a0, cycle, zero\ncsrci instret, 0").unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.csrs.cycle, 2);
+
+	// a write to a counter replaces the increment of the instruction doing it
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble("csrwi minstret, 5\ncsrr s0, minstret\ncsrwi mcycle, 9\ncsrr s1, mcycle").unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(&machine.regs[8..10], [5, 9]);
+
+	machine.extensions.zicsr = false;
+	assert_eq!(machine.exec(Instruction(0x34059573)).unwrap_err().cause, TrapCause::IllegalInstruction);
+}
+
+#[test]
+fn test_trap_handler() {
+	let mut machine = Machine::new(1024);
+	let test = "
+	la t0, handler
+	csrw mtvec, t0
+	li s0, 1
+	ebreak
+	lw s1, 2(zero)
+	li s0, 2
+	j end
+	# skip the instruction that trapped and count the traps in s2
+	handler:
+	csrr t1, mepc
+	addi t1, t1, 4
+	csrw mepc, t1
+	csrr s3, mcause
+	csrr s4, mtval
+	addi s2, s2, 1
+	mret
+	end:
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(&machine.regs[8..10], [2, 0]);
+	assert_eq!(&machine.regs[18..21], [2, TrapCause::LoadMisaligned as i64, 2]);
+	// mret went back to machine mode and left user mode as the previous one
+	assert_eq!(machine.privilege, Privilege::Machine);
+	assert_eq!(machine.csrs.mstatus & csr::MSTATUS_MPP, 0);
+
+	// address zero is both the entry point and the handler
+	let test = "
+	bnez s0, handler
+	li s0, 1
+	csrw mtvec, zero
+	ebreak
+	handler:
+	csrr s1, mcause
+	";
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.regs[9], TrapCause::Breakpoint as i64);
+}
+
+#[test]
+fn test_timer_interrupt() {
+	let mut machine = Machine::new(1024);
+	let clint = machine.attach_clint();
+	let test = "
+	# vectored, so the timer interrupt goes to the eighth entry
+	la t0, vectors
+	ori t0, t0, 1
+	csrw mtvec, t0
+	li t0, 0x2004000
+	li t1, 20
+	sw t1, 0(t0)
+	sw zero, 4(t0)
+	li t0, 0x80
+	csrs mie, t0
+	csrsi mstatus, 8
+	wait:
+	wfi
+	beqz s0, wait
+	# a software interrupt goes off as soon as msip is set
+	li t0, 0x2000000
+	li t1, 1
+	sw t1, 0(t0)
+	li t0, 0x8
+	csrs mie, t0
+	nop
+	j end
+	timer:
+	csrr s1, mcause
+	li t0, 0x2004004
+	li t1, -1
+	sw t1, 0(t0)
+	addi s0, s0, 1
+	mret
+	software:
+	csrr s2, mcause
+	li t0, 0x2000000
+	sw zero, 0(t0)
+	mret
+	.align 4
+	vectors:
+	j end
+	nop
+	nop
+	j software
+	nop
+	nop
+	nop
+	j timer
+	end:
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.regs[8], 1);
+	assert_eq!(machine.regs[9] as u32, 1 << 31 | 7);
+	assert_eq!(machine.regs[18] as u32, 1 << 31 | 3);
+	assert!(clint.mtime() >= 20);
+	assert_eq!(machine.csrs.mstatus & csr::MSTATUS_MIE, csr::MSTATUS_MIE);
+
+	// time follows mtime after the program sets it, even when the cycle count disagrees
+	let mut machine = Machine::new(1024);
+	machine.attach_clint();
+	let test = "
+	li t0, 0x200bff8
+	li t1, 1000
+	sw t1, 0(t0)
+	li t1, 3
+	sw t1, 4(t0)
+	csrwi mcycle, 0
+	rdtime s0
+	rdtimeh s1
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert!((1000..1010).contains(&machine.regs[8]), "{}", machine.regs[8]);
+	assert_eq!(machine.regs[9], 3);
+}
+
+#[test]
+fn test_delegation() {
+	let mut machine = Machine::new(1024);
+	let test = "
+	la t0, s_handler
+	csrw stvec, t0
+	la t0, m_handler
+	csrw mtvec, t0
+	# ecalls from user mode go to supervisor mode, everything else to machine mode
+	li t0, 0x100
+	csrw medeleg, t0
+	la t0, supervisor
+	csrw mepc, t0
+	li t0, 0x800
+	csrs mstatus, t0
+	mret
+	supervisor:
+	la t0, user
+	csrw sepc, t0
+	sret
+	user:
+	ecall
+	csrr a0, sstatus
+	s_handler:
+	csrr s0, scause
+	csrr t0, sepc
+	addi t0, t0, 4
+	csrw sepc, t0
+	sret
+	m_handler:
+	csrr s1, mcause
+	csrr s2, mstatus
+	j end
+	end:
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.regs[8], 8);
+	assert_eq!(machine.regs[9], TrapCause::IllegalInstruction as i64);
+	// the illegal instruction came from user mode
+	assert_eq!(machine.regs[18] as u32 & csr::MSTATUS_MPP, 0);
+	assert_eq!(machine.privilege, Privilege::Machine);
+
+	// without a handler the trap goes to the host
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble("li t0, 0x1800\ncsrc mstatus, t0\nla t0, user\ncsrw mepc, t0\nmret\nuser: sret").unwrap()).unwrap();
+	let trap = machine.run().unwrap_err();
+	assert_eq!((trap.cause, trap.tval), (TrapCause::IllegalInstruction, 0x10200073));
+	assert_eq!(machine.privilege, Privilege::User);
+}
+
+#[test]
+fn test_ecall_routing() {
+	// a user mode ecall to exit, which the host would take
+	let user = "
+	li t0, 0x1800
+	csrc mstatus, t0
+	la t0, user
+	csrw mepc, t0
+	mret
+	user:
+	li a0, 10
+	ecall
+	";
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble(user).unwrap()).unwrap();
+	assert_eq!(machine.run_with(&mut Venus::new(Convention::Venus, 0)), Ok(StepOutcome::Exited(0)));
+
+	// but a kernel that installed a handler for it gets it instead, in machine or supervisor mode
+	for (setup, cause) in [("csrw mtvec, t0", "mcause"), ("csrw stvec, t0\nli t0, 0x100\ncsrw medeleg, t0", "scause")] {
+		let kernel = format!("la t0, handler\n{setup}\n{user}\nhandler:\ncsrr s0, {cause}");
+		let mut machine = Machine::new(1024);
+		machine.load_image(&assemble(&kernel).unwrap()).unwrap();
+		assert_eq!(machine.run_with(&mut Venus::new(Convention::Venus, 0)), Ok(StepOutcome::Finished));
+		assert_eq!(machine.regs[8], TrapCause::UserEnvironmentCall as i64);
+	}
+	let kernel = "la t0, handler\ncsrw mtvec, t0\nli a0, 10\necall\nhandler:\ncsrr s0, mcause";
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble(kernel).unwrap()).unwrap();
+	assert_eq!(machine.run_with(&mut Venus::new(Convention::Venus, 0)), Ok(StepOutcome::Finished));
+	assert_eq!(machine.regs[8], TrapCause::EnvironmentCall as i64);
+}
+
+#[test]
+fn test_paging() {
+	let mut machine = Machine::new(0x10000);
+	let test = "
+	la t0, handler
+	csrw mtvec, t0
+	# Sv32 with the root page table at 0x8000, then user mode at virtual 0x400000
+	li t0, 0x80000008
+	csrw satp, t0
+	li t0, 0x400000
+	csrw mepc, t0
+	li t0, 0x1800
+	csrc mstatus, t0
+	mret
+	# record the last two faults and skip the instructions that caused them
+	handler:
+	csrr t0, mcause
+	li t1, 8
+	beq t0, t1, end
+	mv s2, s0
+	mv s3, s1
+	mv s0, t0
+	csrr s1, mtval
+	csrr t0, mepc
+	addi t0, t0, 4
+	csrw mepc, t0
+	mret
+	.align 12
+	user:
+	li a3, 0x401000
+	lw a0, 0(a3)
+	sw a0, 0(a3)
+	li a4, 0x402000
+	lw a1, 0(a4)
+	lw a2, 0(a3)
+	ecall
+	end:
+	";
+	let image = assemble(test).unwrap();
+	let user = image.symbols["user"];
+	machine.load_image(&image).unwrap();
+	let (root, table, data) = (0x8000, 0x9000, 0xa000);
+	let pte = |addr: u32, flags: u32| (addr >> 12) << 10 | flags;
+	// V, R, X, U and V, R, U
+	machine.store(root + 4, 4, pte(table, 1)).unwrap();
+	machine.store(table, 4, pte(user, 0b11011)).unwrap();
+	machine.store(table + 4, 4, pte(data, 0b10011)).unwrap();
+	machine.store(data, 4, 42).unwrap();
+	machine.run().unwrap();
+
+	assert_eq!((machine.regs[10], machine.regs[12]), (42, 42));
+	assert_eq!((machine.regs[18], machine.regs[19]), (TrapCause::StorePageFault as i64, 0x401000));
+	assert_eq!((machine.regs[8], machine.regs[9]), (TrapCause::LoadPageFault as i64, 0x402000));
+	// the hardware set the accessed bits, and the second load from the data page hit the TLB
+	assert_eq!(machine.load(table, 4).unwrap() & 0xc0, 0x40);
+	assert_eq!(machine.load(table + 4, 4).unwrap() & 0xc0, 0x40);
+	assert!(machine.tlb.hits > 0);
+	assert_eq!(machine.tlb.misses, 4);
+
+	// supervisor mode can't run user code
+	machine.privilege = Privilege::Supervisor;
+	machine.pc = 0x400000;
+	machine.step().unwrap();
+	assert_eq!(machine.csrs.mcause, TrapCause::InstructionPageFault as u32);
+	assert_eq!(machine.csrs.mstatus & csr::MSTATUS_MPP, 1 << 11);
+}
+
+#[test]
+fn test_mprv() {
+	let mut machine = Machine::new(0x10000);
+	let test = "
+	li t0, 0x80000008
+	csrw satp, t0
+	# with MPRV set and MPP user, loads and stores go through the user's page table
+	li t0, 0x1800
+	csrc mstatus, t0
+	li t0, 0x20000
+	csrs mstatus, t0
+	li t1, 0x401000
+	lw s0, 0(t1)
+	sw s0, 4(t1)
+	csrc mstatus, t0
+	li t1, 0xa004
+	lw s1, 0(t1)
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	let (root, table, data) = (0x8000, 0x9000, 0xa000);
+	let pte = |addr: u32, flags: u32| (addr >> 12) << 10 | flags;
+	// V, R, W, U
+	machine.store(root + 4, 4, pte(table, 1)).unwrap();
+	machine.store(table + 4, 4, pte(data, 0b10111)).unwrap();
+	machine.store(data, 4, 42).unwrap();
+	machine.run().unwrap();
+	assert_eq!(&machine.regs[8..10], [42, 42]);
+
+	// returning to a lower mode clears MPRV
+	machine.load_image(&assemble("li t0, 0x20000\ncsrs mstatus, t0\nla t0, user\ncsrw mepc, t0\nmret\nuser:").unwrap()).unwrap();
+	machine.csrs.satp = 0;
+	machine.run().unwrap();
+	assert_eq!((machine.privilege, machine.csrs.mstatus & csr::MSTATUS_MPRV), (Privilege::User, 0));
+
+	// TVM, TW and TSR take satp, sfence.vma, wfi and sret away from supervisor mode
+	machine.privilege = Privilege::Supervisor;
+	for word in [0x18002573, 0x12000073, 0x10500073, 0x10200073] {
+		machine.csrs.mstatus = 0;
+		assert!(machine.exec(Instruction(word)).is_ok(), "{word:#x}");
+		machine.privilege = Privilege::Supervisor;
+		machine.csrs.mstatus = csr::MSTATUS_TVM | csr::MSTATUS_TW | csr::MSTATUS_TSR;
+		assert_eq!(machine.exec(Instruction(word)).unwrap_err().cause, TrapCause::IllegalInstruction);
+	}
+}
+
+#[test]
+fn test_rv64() {
+	let source = "
+	.data
+	val: .dword -2
+	.text
+	li s0, 0x123456789abcdef0
+	li t0, 0x7fffffff
+	addiw s1, t0, 1
+	add s2, t0, t0
+	la t1, val
+	ld s3, 0(t1)
+	lwu s4, 0(t1)
+	lw s5, 4(t1)
+	sd s0, 0(t1)
+	lw s6, 4(t1)
+	li t2, 1
+	slli s7, t2, 40
+	sraiw s8, s0, 4
+	mulh s9, s0, s0
+	subw s10, zero, t2
+	srl s11, s0, t2
+	";
+	let program = risclang::parse::parse_xlen(source, Xlen::Rv64).unwrap();
+	let image = compile::compile(program, &compile::Layout::default()).unwrap();
+	let mut machine = Machine::with_xlen(1024, Xlen::Rv64);
+	machine.load_image(&image).unwrap();
+	machine.run().unwrap();
+	assert_eq!(machine.regs[8], 0x1234_5678_9abc_def0);
+	assert_eq!(machine.regs[9], i32::MIN as i64);
+	assert_eq!(machine.regs[18], 0xffff_fffe);
+	assert_eq!(machine.regs[19], -2);
+	assert_eq!(machine.regs[20], 0xffff_fffe);
+	assert_eq!(machine.regs[21], -1);
+	assert_eq!(machine.regs[22], 0x1234_5678);
+	assert_eq!(machine.regs[23], 1 << 40);
+	assert_eq!(machine.regs[24], 0xf9ab_cdef_u32 as i32 as i64);
+	assert_eq!(machine.regs[25], ((0x1234_5678_9abc_def0_i128 * 0x1234_5678_9abc_def0_i128) >> 64) as i64);
+	assert_eq!(machine.regs[26], -1);
+	assert_eq!(machine.regs[27], 0x091a_2b3c_4d5e_6f78);
+
+	let mut machine = Machine::new(1024);
+	machine.load_image(&image).unwrap();
+	let trap = machine.run().unwrap_err();
+	assert_eq!(trap.cause, TrapCause::IllegalInstruction);
+	let shamt = risclang::parse::parse_xlen("slli a0, a0, 32", Xlen::Rv64).unwrap();
+	let mut machine = Machine::new(1024);
+	machine.load_image(&compile::compile(shamt, &compile::Layout::default()).unwrap()).unwrap();
+	assert_eq!(machine.run().unwrap_err().cause, TrapCause::IllegalInstruction);
+}
+
+#[test]
+fn test_float() {
+	let mut machine = Machine::new(1024);
+	let test = "
+	.data
+	vals: .float 1.5, 2.25
+	.double 0.1
+	.text
+	la t0, vals
+	flw fa0, 0(t0)
+	flw fa1, 4(t0)
+	fadd.s fa2, fa0, fa1
+	fmul.s fa3, fa0, fa1
+	fmadd.s fa4, fa0, fa1, fa2
+	fsw fa4, -4(sp)
+	fld fa5, 8(t0)
+	fcvt.s.d fa6, fa5
+	fadd.s fa7, fa5, fa5
+	fcvt.w.s a0, fa2
+	li t1, 1
+	fsrm t1
+	fcvt.w.s a1, fa2
+	fcvt.w.s a2, fa2, rup
+	fmv.x.w a3, fa0
+	feq.s a4, fa0, fa0
+	flt.s a5, fa1, fa0
+	fclass.s a6, fa0
+	li t2, -7
+	fcvt.d.w ft0, t2
+	fsqrt.d ft1, ft0
+	fneg.d ft2, ft0
+	frflags s0
+	";
+	machine.load_image(&assemble(test).unwrap()).unwrap();
+	machine.run().unwrap();
+	let boxed = |x: f32| 0xffff_ffff_0000_0000 | x.to_bits() as u64;
+	assert_eq!(machine.fregs[10..15], [1.5, 2.25, 3.75, 3.375, 7.125].map(boxed));
+	assert_eq!(machine.peek_bytes(1020, 4), 7.125f32.to_le_bytes());
+	assert_eq!(machine.fregs[15..17], [0.1f64.to_bits(), boxed(0.1)]);
+	// a double read as a single isn't NaN-boxed, so it's the canonical NaN
+	assert_eq!(machine.fregs[17], 0xffff_ffff_7fc0_0000);
+	assert_eq!(&machine.regs[10..17], [4, 3, 4, 0x3fc0_0000, 1, 0, 1 << 6]);
+	assert_eq!(machine.fregs[..3], [(-7f64).to_bits(), 0x7ff8_0000_0000_0000, 7f64.to_bits()]);
+	// the conversions were inexact and the square root invalid
+	assert_eq!(machine.regs[8], 0x11);
+	assert_eq!(machine.csrs.fcsr, 1 << 5 | 0x11);
+
+	// a reserved rounding mode in `frm` makes dynamic rounding illegal
+	let mut machine = Machine::new(1024);
+	machine.load_image(&assemble("fsrmi 5\nfadd.s fa0, fa0, fa0").unwrap()).unwrap();
+	let trap = machine.run().unwrap_err();
+	assert_eq!((trap.cause, trap.tval), (TrapCause::IllegalInstruction, 0x00a57553));
+
+	let mut machine = Machine::new(1024);
+	machine.extensions.d = false;
+	assert_eq!(machine.extensions.misa(Xlen::Rv32), 0x4014_1120);
+	assert_eq!(machine.exec(Instruction(0x00813507)).unwrap_err().cause, TrapCause::IllegalInstruction);
+	machine.extensions.f = false;
+	machine.load_image(&assemble("frcsr a0").unwrap()).unwrap();
+	assert_eq!(machine.run().unwrap_err().cause, TrapCause::IllegalInstruction);
+}
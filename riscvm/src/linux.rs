@@ -0,0 +1,297 @@
+use std::collections::VecDeque;
+
+use crate::syscall::read_string;
+use crate::vfs::{FileSystem, FsError, OpenOptions};
+use crate::{Bus, Machine, SyscallHandler, SyscallOutcome, Trap, Xlen};
+
+const SYS_OPENAT: i32 = 56;
+const SYS_CLOSE: i32 = 57;
+const SYS_READ: i32 = 63;
+const SYS_WRITE: i32 = 64;
+const SYS_FSTAT: i32 = 80;
+const SYS_EXIT: i32 = 93;
+const SYS_EXIT_GROUP: i32 = 94;
+const SYS_CLOCK_GETTIME: i32 = 113;
+const SYS_BRK: i32 = 214;
+/// The version of `clock_gettime` with a 64 bit `tv_sec` that 32 bit targets use since Linux 5.1.
+const SYS_CLOCK_GETTIME64: i32 = 403;
+
+const ENOENT: i32 = 2;
+const EBADF: i32 = 9;
+const EINVAL: i32 = 22;
+
+const O_ACCMODE: i32 = 0o3;
+const O_RDONLY: i32 = 0o0;
+const O_WRONLY: i32 = 0o1;
+const O_RDWR: i32 = 0o2;
+const O_CREAT: i32 = 0o100;
+const O_TRUNC: i32 = 0o1000;
+const O_APPEND: i32 = 0o2000;
+
+const S_IFCHR: u32 = 0o020000;
+const S_IFREG: u32 = 0o100000;
+
+/// The size of `struct stat` on 32 bit RISC-V.
+const STAT_SIZE: u32 = 104;
+/// The size of `struct stat` on 64 bit RISC-V. The fields that are filled in are at the same offsets.
+const STAT_SIZE_64: u32 = 128;
+
+/// Enough of the Linux system call interface for the C libraries of riscv32 toolchains, like newlib
+/// and picolibc: file access, `brk` and a clock. The number is in `a7`, arguments start at `a0` and
+/// errors are returned as negative `errno` values.
+///
+/// Standard output and error end up in `output` and `input` is standard input. Other files come from
+/// `fs`. The clock starts at `time_ns` and moves forward by `clock_step_ns` every time it's read, so
+/// runs are reproducible.
+#[derive(Debug, Clone)]
+pub struct Linux {
+	pub output: Vec<u8>,
+	pub input: VecDeque<u8>,
+	pub fs: FileSystem,
+	/// The current program break.
+	pub brk: u32,
+	/// Nanoseconds since the epoch.
+	pub time_ns: u64,
+	pub clock_step_ns: u64,
+	brk_start: u32,
+}
+
+impl Linux {
+	/// A handler whose heap starts at `brk`.
+	pub fn new(brk: u32) -> Self {
+		Self {
+			output: Vec::new(),
+			input: VecDeque::new(),
+			fs: FileSystem::new(),
+			brk,
+			time_ns: 0,
+			clock_step_ns: 1_000_000,
+			brk_start: brk,
+		}
+	}
+
+	/// A handler for `image`, with the heap right after its data.
+	pub fn for_image(image: &risclang::compile::Image) -> Self {
+		let data_end = image.data_base.wrapping_add(image.data.len() as u32);
+		Self::new(data_end.wrapping_add(7) & !7)
+	}
+
+	/// Everything written to standard output and error since the last call.
+	pub fn take_output(&mut self) -> Vec<u8> {
+		std::mem::take(&mut self.output)
+	}
+
+	fn openat(&mut self, name: &str, flags: i32) -> i32 {
+		let (read, write) = match flags & O_ACCMODE {
+			O_RDONLY => (true, false),
+			O_WRONLY => (false, true),
+			O_RDWR => (true, true),
+			_ => return -EINVAL,
+		};
+		let options = OpenOptions {
+			read,
+			write,
+			create: flags & O_CREAT != 0,
+			truncate: flags & O_TRUNC != 0,
+			append: flags & O_APPEND != 0,
+		};
+		self.fs.open(name, options).unwrap_or_else(errno)
+	}
+
+	/// Move the clock forward and return the time it shows.
+	fn clock(&mut self) -> (u64, u32) {
+		self.time_ns += self.clock_step_ns;
+		(self.time_ns / 1_000_000_000, (self.time_ns % 1_000_000_000) as u32)
+	}
+}
+
+fn errno(error: FsError) -> i32 {
+	match error {
+		FsError::NotFound => -ENOENT,
+		FsError::BadFd | FsError::NotPermitted => -EBADF,
+	}
+}
+
+impl<B: Bus> SyscallHandler<B> for Linux {
+	fn ecall(&mut self, machine: &mut Machine<B>) -> Result<SyscallOutcome, Trap> {
+		let [a0, a1, a2] = [machine.regs[10], machine.regs[11], machine.regs[12]].map(|reg| reg as i32);
+		let ret = match machine.regs[17] as i32 {
+			SYS_EXIT | SYS_EXIT_GROUP => return Ok(SyscallOutcome::Exit(a0)),
+			SYS_OPENAT => {
+				// every path is relative to the root of `fs`, so the directory doesn't matter
+				let name = read_string(machine, a1 as u32)?;
+				self.openat(&String::from_utf8_lossy(&name), a2)
+			},
+			SYS_CLOSE => match a0 {
+				0..=2 => 0,
+				fd => self.fs.close(fd).map_or_else(errno, |()| 0),
+			},
+			SYS_READ => {
+				let bytes = match a0 {
+					0 => {
+						let len = (a2.max(0) as usize).min(self.input.len());
+						Ok(self.input.drain(..len).collect())
+					},
+					fd => self.fs.read(fd, a2.max(0) as usize),
+				};
+				match bytes {
+					Ok(bytes) => {
+						for (i, &byte) in bytes.iter().enumerate() {
+							machine.store((a1 as u32).wrapping_add(i as u32), 1, byte as u32)?;
+						}
+						bytes.len() as i32
+					},
+					Err(error) => errno(error),
+				}
+			},
+			SYS_WRITE => {
+				let bytes = (0..a2.max(0) as u32)
+					.map(|i| machine.load_memory((a1 as u32).wrapping_add(i), 1).map(|byte| byte as u8))
+					.collect::<Result<Vec<_>, _>>()?;
+				let written = match a0 {
+					1 | 2 => {
+						self.output.extend(&bytes);
+						Ok(())
+					},
+					fd => self.fs.write(fd, &bytes),
+				};
+				written.map_or_else(errno, |()| bytes.len() as i32)
+			},
+			SYS_FSTAT => {
+				let stat = match a0 {
+					0..=2 => Ok((S_IFCHR | 0o620, 0)),
+					fd => self.fs.size(fd).map(|size| (S_IFREG | 0o644, size)),
+				};
+				match stat {
+					Ok((mode, size)) => {
+						let buf = a1 as u32;
+						let size_of_stat = if machine.xlen == Xlen::Rv64 { STAT_SIZE_64 } else { STAT_SIZE };
+						for offset in (0..size_of_stat).step_by(4) {
+							machine.store(buf.wrapping_add(offset), 4, 0)?;
+						}
+						// st_mode, st_size and st_blksize
+						machine.store(buf.wrapping_add(16), 4, mode)?;
+						machine.store(buf.wrapping_add(48), 4, size as u32)?;
+						machine.store(buf.wrapping_add(56), 4, 4096)?;
+						0
+					},
+					Err(error) => errno(error),
+				}
+			},
+			SYS_BRK => {
+				// asking for less than the initial break, like 0, just reads it
+				if a0 as u32 >= self.brk_start {
+					self.brk = a0 as u32;
+				}
+				self.brk as i32
+			},
+			SYS_CLOCK_GETTIME if machine.xlen == Xlen::Rv32 => {
+				let (sec, nsec) = self.clock();
+				machine.store(a1 as u32, 4, sec as u32)?;
+				machine.store((a1 as u32).wrapping_add(4), 4, nsec)?;
+				0
+			},
+			// the `timespec` of RV64 is the 64 bit time one of RV32
+			SYS_CLOCK_GETTIME | SYS_CLOCK_GETTIME64 => {
+				let (sec, nsec) = self.clock();
+				machine.store(a1 as u32, 4, sec as u32)?;
+				machine.store((a1 as u32).wrapping_add(4), 4, (sec >> 32) as u32)?;
+				machine.store((a1 as u32).wrapping_add(8), 4, nsec)?;
+				machine.store((a1 as u32).wrapping_add(12), 4, 0)?;
+				0
+			},
+			_ => return Ok(SyscallOutcome::Unknown),
+		};
+		machine.regs[10] = ret.into();
+		Ok(SyscallOutcome::Continue)
+	}
+}
+
+#[test]
+fn test_linux() {
+	let source = r#"
+	# write a file, read it back and print it
+	li a7, 56
+	li a0, -100
+	la a1, path
+	li a2, 0o1101
+	ecall
+	mv s0, a0
+	li a7, 64
+	la a1, msg
+	li a2, 6
+	ecall
+	li a7, 57
+	mv a0, s0
+	ecall
+	li a7, 56
+	li a0, -100
+	la a1, path
+	li a2, 0
+	ecall
+	mv s0, a0
+	li a7, 80
+	la a1, stat
+	ecall
+	la t0, stat
+	lw s1, 48(t0)
+	li a7, 63
+	mv a0, s0
+	la a1, buf
+	li a2, 64
+	ecall
+	mv a2, a0
+	li a7, 64
+	li a0, 1
+	la a1, buf
+	ecall
+	# writing to a read only file and reading a missing one fail
+	li a7, 64
+	mv a0, s0
+	ecall
+	mv s2, a0
+	li a7, 56
+	li a0, -100
+	la a1, msg
+	li a2, 0
+	ecall
+	mv s3, a0
+	li a7, 214
+	li a0, 0
+	ecall
+	mv s4, a0
+	addi a0, a0, 256
+	li a7, 214
+	ecall
+	sub s5, a0, s4
+	li a7, 113
+	li a0, 0
+	la a1, buf
+	ecall
+	li a7, 113
+	ecall
+	la t0, buf
+	lw s6, 4(t0)
+	li a7, 94
+	li a0, 7
+	ecall
+.data
+path: .string "/tmp/out.txt"
+msg: .string "hello\n"
+.align 2
+stat: .space 104
+buf: .space 64
+"#;
+	let image = crate::assemble(source).unwrap();
+	let mut machine = Machine::new(1024);
+	machine.load_image(&image).unwrap();
+	let mut linux = Linux::for_image(&image);
+	assert_eq!(machine.run_with(&mut linux), Ok(crate::StepOutcome::Exited(7)));
+	assert_eq!(linux.fs.files["tmp/out.txt"], b"hello\n");
+	assert_eq!(linux.take_output(), b"hello\n");
+	assert_eq!(machine.regs[9], 6);
+	assert_eq!((machine.regs[18], machine.regs[19]), ((-EBADF).into(), (-ENOENT).into()));
+	assert_eq!(machine.regs[20] as u32, linux.brk - 256);
+	assert_eq!(machine.regs[21], 256);
+	assert_eq!(machine.regs[22], 2_000_000);
+}
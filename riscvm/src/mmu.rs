@@ -0,0 +1,213 @@
+use crate::csr::{self, Privilege};
+use crate::{AccessFault, Bus, Machine, Trap, TrapCause};
+
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+const PTE_U: u32 = 1 << 4;
+const PTE_A: u32 = 1 << 6;
+const PTE_D: u32 = 1 << 7;
+
+/// How many translations the TLB holds.
+const TLB_SIZE: usize = 16;
+
+/// The kind of memory access an address is translated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Access {
+	Fetch,
+	Load,
+	Store,
+}
+
+impl Access {
+	fn page_fault(self) -> TrapCause {
+		match self {
+			Access::Fetch => TrapCause::InstructionPageFault,
+			Access::Load => TrapCause::LoadPageFault,
+			Access::Store => TrapCause::StorePageFault,
+		}
+	}
+
+	pub(crate) fn access_fault(self) -> TrapCause {
+		match self {
+			Access::Fetch => TrapCause::InstructionAccessFault,
+			Access::Load => TrapCause::LoadAccessFault,
+			Access::Store => TrapCause::StoreAccessFault,
+		}
+	}
+}
+
+/// The translation of a 4 KiB page, or of a 4 MiB megapage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TlbEntry {
+	/// The virtual page number, or megapage number.
+	vpn: u32,
+	mega: bool,
+	/// The leaf page table entry, with the accessed and dirty bits as they were written back.
+	pte: u32,
+}
+
+impl TlbEntry {
+	fn covers(&self, vaddr: u32) -> bool {
+		self.vpn == if self.mega { vaddr >> 22 } else { vaddr >> 12 }
+	}
+
+	/// The physical address of `vaddr`, which can be more than 32 bits.
+	fn physical(&self, vaddr: u32) -> u64 {
+		let ppn = (self.pte >> 10) as u64;
+		let offset_bits = if self.mega { 0x3f_ffff } else { 0xfff };
+		ppn << 12 | (vaddr & offset_bits) as u64
+	}
+}
+
+/// A small fully associative TLB that replaces its entries round robin. Like a real one, it isn't
+/// kept in sync with the page tables, so a program that changes a mapping it has used has to run
+/// `sfence.vma`. `hits` and `misses` count lookups, to show how well it works.
+#[derive(Debug, Clone, Default)]
+pub struct Tlb {
+	entries: Vec<TlbEntry>,
+	next: usize,
+	pub hits: u64,
+	pub misses: u64,
+}
+
+impl Tlb {
+	fn lookup(&self, vaddr: u32) -> Option<TlbEntry> {
+		self.entries.iter().find(|entry| entry.covers(vaddr)).copied()
+	}
+
+	fn insert(&mut self, entry: TlbEntry) {
+		self.entries.retain(|old| !old.covers(entry.vpn << if entry.mega { 22 } else { 12 }));
+		if self.entries.len() < TLB_SIZE {
+			self.entries.push(entry);
+		} else {
+			self.entries[self.next] = entry;
+			self.next = (self.next + 1) % TLB_SIZE;
+		}
+	}
+
+	/// Forget the translation of `vaddr`, or every translation if it's `None`.
+	pub fn flush(&mut self, vaddr: Option<u32>) {
+		match vaddr {
+			Some(vaddr) => self.entries.retain(|entry| !entry.covers(vaddr)),
+			None => self.entries.clear(),
+		}
+		self.next = 0;
+	}
+}
+
+impl<B: Bus> Machine<B> {
+	/// The physical address of `vaddr` for an access from the current privilege mode, or from MPP for
+	/// loads and stores while MPRV is set. Machine mode and a `satp` without Sv32 enabled use physical
+	/// addresses directly.
+	pub(crate) fn translate(&mut self, vaddr: u32, access: Access) -> Result<u32, Trap> {
+		if self.access_privilege(access) == Privilege::Machine || self.csrs.satp >> 31 == 0 {
+			return Ok(vaddr);
+		}
+		let cached = self.tlb.lookup(vaddr);
+		// a store to a page that isn't dirty yet walks the table again to mark it
+		let entry = match cached {
+			Some(entry) if access != Access::Store || entry.pte & PTE_D != 0 => {
+				self.tlb.hits += 1;
+				entry
+			},
+			_ => {
+				self.tlb.misses += 1;
+				let entry = self.walk(vaddr, access)?;
+				self.tlb.insert(entry);
+				entry
+			},
+		};
+		if !self.permits(entry.pte, access) {
+			return Err(self.trap(access.page_fault(), vaddr));
+		}
+		entry.physical(vaddr).try_into().map_err(|_| self.trap(access.access_fault(), vaddr))
+	}
+
+	/// Find the leaf entry for `vaddr` in the two level page table and set its accessed bit, and its
+	/// dirty bit for stores.
+	fn walk(&mut self, vaddr: u32, access: Access) -> Result<TlbEntry, Trap> {
+		let page_fault = self.trap(access.page_fault(), vaddr);
+		let access_fault = self.trap(access.access_fault(), vaddr);
+		let mut table = (self.csrs.satp & 0x3f_ffff) as u64 * 4096;
+		for mega in [true, false] {
+			let vpn = if mega { vaddr >> 22 } else { vaddr >> 12 & 0x3ff };
+			let pte_addr: u32 = (table + vpn as u64 * 4).try_into().map_err(|_| access_fault)?;
+			let pte = self.bus.read(pte_addr, 4).map_err(|AccessFault| access_fault)?;
+			if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+				return Err(page_fault);
+			}
+			if pte & (PTE_R | PTE_X) == 0 {
+				// a pointer to the next level, of which there's only one
+				if !mega {
+					return Err(page_fault);
+				}
+				table = (pte >> 10) as u64 * 4096;
+				continue;
+			}
+			// megapages have to be aligned to their size
+			if (mega && pte >> 10 & 0x3ff != 0) || !self.permits(pte, access) {
+				return Err(page_fault);
+			}
+			let flags = PTE_A | if access == Access::Store { PTE_D } else { 0 };
+			if pte & flags != flags {
+				self.bus.write(pte_addr, 4, pte | flags).map_err(|AccessFault| access_fault)?;
+			}
+			return Ok(TlbEntry {
+				vpn: if mega { vaddr >> 22 } else { vaddr >> 12 },
+				mega,
+				pte: pte | flags,
+			});
+		}
+		unreachable!()
+	}
+
+	/// The privilege mode `access` is translated and checked for.
+	fn access_privilege(&self, access: Access) -> Privilege {
+		if access != Access::Fetch && self.csrs.mstatus & csr::MSTATUS_MPRV != 0 {
+			Privilege::from_bits((self.csrs.mstatus & csr::MSTATUS_MPP) >> 11)
+		} else {
+			self.privilege
+		}
+	}
+
+	/// Whether a page with the leaf entry `pte` allows `access` from the mode it's made in.
+	fn permits(&self, pte: u32, access: Access) -> bool {
+		let user_page = pte & PTE_U != 0;
+		let mode_ok = match self.access_privilege(access) {
+			Privilege::User => user_page,
+			// supervisor mode never runs user code, and only touches user data if SUM allows it
+			Privilege::Supervisor => !user_page || (access != Access::Fetch && self.csrs.mstatus & csr::MSTATUS_SUM != 0),
+			Privilege::Machine => true,
+		};
+		let mxr = self.csrs.mstatus & csr::MSTATUS_MXR != 0;
+		mode_ok
+			&& match access {
+				Access::Fetch => pte & PTE_X != 0,
+				Access::Load => pte & PTE_R != 0 || (mxr && pte & PTE_X != 0),
+				Access::Store => pte & PTE_W != 0,
+			}
+	}
+}
+
+#[test]
+fn test_tlb() {
+	let mut tlb = Tlb::default();
+	let page = |vpn| TlbEntry { vpn, mega: false, pte: 0 };
+	for vpn in 0..TLB_SIZE as u32 + 1 {
+		tlb.insert(page(vpn));
+	}
+	// the oldest entry made way for the newest
+	assert_eq!(tlb.lookup(0), None);
+	assert_eq!(tlb.lookup(0x1234), Some(page(1)));
+	assert!(tlb.lookup(TLB_SIZE as u32 * 4096).is_some());
+
+	tlb.insert(TlbEntry { vpn: 1, mega: true, pte: 0 });
+	assert!(tlb.lookup(0x40_1000).is_some_and(|entry| entry.mega));
+	tlb.flush(Some(0x1000));
+	assert_eq!(tlb.lookup(0x1000), None);
+	assert!(tlb.lookup(0x2000).is_some());
+	tlb.flush(None);
+	assert_eq!(tlb.lookup(0x2000), None);
+}
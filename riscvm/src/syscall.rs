@@ -0,0 +1,387 @@
+use std::collections::VecDeque;
+
+use crate::vfs::{FileSystem, OpenOptions};
+use crate::{Bus, Machine, Trap, TrapCause};
+
+/// What the machine should do after an environment call.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallOutcome {
+	/// Carry on with the instruction after the `ecall`.
+	Continue,
+	/// Stop with an exit code.
+	Exit(i32),
+	/// The handler doesn't know the call, so it's reported to the caller as a trap.
+	Unknown,
+}
+
+/// Handles the environment calls of a program on the host. It gets the whole machine, so it can read
+/// arguments from the registers, write results back and access memory.
+pub trait SyscallHandler<B: Bus> {
+	fn ecall(&mut self, machine: &mut Machine<B>) -> Result<SyscallOutcome, Trap>;
+}
+
+/// Which registers the call number and its arguments are passed in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Convention {
+	/// The number in `a0` and the arguments from `a1` on, with Venus' numbering.
+	Venus,
+	/// The number in `a7` and the arguments from `a0` on, with RARS' numbering.
+	Rars,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Call {
+	PrintInt,
+	PrintString,
+	ReadInt,
+	Sbrk,
+	Exit,
+	PrintChar,
+	Open,
+	Read,
+	Write,
+	Close,
+	Exit2,
+}
+
+impl Convention {
+	fn call(self, num: i32) -> Option<Call> {
+		Some(match (self, num) {
+			(_, 1) => Call::PrintInt,
+			(_, 4) => Call::PrintString,
+			(_, 5) => Call::ReadInt,
+			(_, 9) => Call::Sbrk,
+			(_, 10) => Call::Exit,
+			(_, 11) => Call::PrintChar,
+			(Convention::Venus, 13) | (Convention::Rars, 1024) => Call::Open,
+			(Convention::Venus, 14) | (Convention::Rars, 63) => Call::Read,
+			(Convention::Venus, 15) | (Convention::Rars, 64) => Call::Write,
+			(Convention::Venus, 16) | (Convention::Rars, 57) => Call::Close,
+			(Convention::Venus, 17) | (Convention::Rars, 93) => Call::Exit2,
+			_ => return None,
+		})
+	}
+
+	fn num_reg(self) -> usize {
+		match self {
+			Convention::Venus => 10,
+			Convention::Rars => 17,
+		}
+	}
+
+	fn first_arg_reg(self) -> usize {
+		match self {
+			Convention::Venus => 11,
+			Convention::Rars => 10,
+		}
+	}
+}
+
+const STDIN: i32 = 0;
+const STDOUT: i32 = 1;
+const STDERR: i32 = 2;
+
+/// Flags for `open`, as RARS defines them.
+const OPEN_READ: i32 = 0;
+const OPEN_WRITE: i32 = 1;
+const OPEN_APPEND: i32 = 9;
+
+/// The environment calls of the Venus and RARS simulators: printing, reading integers, `sbrk`, exit
+/// and file access. Standard output and error both end up in `output` and `input` is standard input.
+#[derive(Debug, Clone)]
+pub struct Venus {
+	pub convention: Convention,
+	pub output: Vec<u8>,
+	pub input: VecDeque<u8>,
+	pub fs: FileSystem,
+	/// The end of the heap that `sbrk` grows.
+	pub heap_end: u32,
+}
+
+impl Venus {
+	/// A handler whose heap starts at `heap_start`.
+	pub fn new(convention: Convention, heap_start: u32) -> Self {
+		Self {
+			convention,
+			output: Vec::new(),
+			input: VecDeque::new(),
+			fs: FileSystem::new(),
+			heap_end: heap_start,
+		}
+	}
+
+	/// A handler for `image`, with the heap right after its data.
+	pub fn for_image(convention: Convention, image: &risclang::compile::Image) -> Self {
+		let data_end = image.data_base.wrapping_add(image.data.len() as u32);
+		Self::new(convention, data_end.wrapping_add(7) & !7)
+	}
+
+	/// Everything printed since the last call.
+	pub fn take_output(&mut self) -> Vec<u8> {
+		std::mem::take(&mut self.output)
+	}
+
+	/// Read a line of input and parse it as an integer, which is zero if it isn't one.
+	fn read_int(&mut self) -> i32 {
+		let mut line = Vec::new();
+		while let Some(byte) = self.input.pop_front() {
+			if byte == b'\n' {
+				break;
+			}
+			line.push(byte);
+		}
+		String::from_utf8_lossy(&line).trim().parse().unwrap_or(0)
+	}
+
+	fn open(&mut self, name: &str, flags: i32) -> Option<i32> {
+		let options = match flags {
+			OPEN_READ => OpenOptions { read: true, ..Default::default() },
+			OPEN_WRITE => OpenOptions { write: true, create: true, truncate: true, ..Default::default() },
+			OPEN_APPEND => OpenOptions { write: true, create: true, append: true, ..Default::default() },
+			_ => return None,
+		};
+		self.fs.open(name, options).ok()
+	}
+
+	/// The bytes `fd` reads next, up to `len` of them. Reading consumes them.
+	fn read(&mut self, fd: i32, len: usize) -> Option<Vec<u8>> {
+		if fd == STDIN {
+			let len = len.min(self.input.len());
+			return Some(self.input.drain(..len).collect());
+		}
+		self.fs.read(fd, len).ok()
+	}
+
+	fn write(&mut self, fd: i32, bytes: &[u8]) -> Option<()> {
+		if fd == STDOUT || fd == STDERR {
+			self.output.extend(bytes);
+			return Some(());
+		}
+		self.fs.write(fd, bytes).ok()
+	}
+}
+
+/// The longest string an environment call reads, so a missing NUL can't make it read all of memory.
+const MAX_STRING_LEN: usize = 64 * 1024;
+
+/// Read a NUL terminated string from memory. A string longer than `MAX_STRING_LEN` is a load access
+/// fault at the byte past the limit.
+pub(crate) fn read_string<B: Bus>(machine: &mut Machine<B>, addr: u32) -> Result<Vec<u8>, Trap> {
+	let mut bytes = Vec::new();
+	while bytes.len() < MAX_STRING_LEN {
+		match machine.load_memory(addr.wrapping_add(bytes.len() as u32), 1)? as u8 {
+			0 => return Ok(bytes),
+			byte => bytes.push(byte),
+		}
+	}
+	Err(machine.trap(TrapCause::LoadAccessFault, addr.wrapping_add(MAX_STRING_LEN as u32)))
+}
+
+impl<B: Bus> SyscallHandler<B> for Venus {
+	fn ecall(&mut self, machine: &mut Machine<B>) -> Result<SyscallOutcome, Trap> {
+		let Some(call) = self.convention.call(machine.regs[self.convention.num_reg()] as i32) else {
+			return Ok(SyscallOutcome::Unknown);
+		};
+		let first = self.convention.first_arg_reg();
+		let [a, b, c] = [machine.regs[first], machine.regs[first + 1], machine.regs[first + 2]].map(|reg| reg as i32);
+		let ret = match call {
+			Call::PrintInt => {
+				// the whole register, which is wider than `a` on RV64
+				self.output.extend(machine.regs[first].to_string().as_bytes());
+				return Ok(SyscallOutcome::Continue);
+			},
+			Call::PrintString => {
+				let string = read_string(machine, a as u32)?;
+				self.output.extend(string);
+				return Ok(SyscallOutcome::Continue);
+			},
+			Call::PrintChar => {
+				self.output.push(a as u8);
+				return Ok(SyscallOutcome::Continue);
+			},
+			Call::Exit => return Ok(SyscallOutcome::Exit(0)),
+			Call::Exit2 => return Ok(SyscallOutcome::Exit(a)),
+			Call::ReadInt => self.read_int(),
+			Call::Sbrk => {
+				let old = self.heap_end;
+				self.heap_end = old.wrapping_add(a as u32);
+				old as i32
+			},
+			Call::Open => {
+				let name = read_string(machine, a as u32)?;
+				self.open(&String::from_utf8_lossy(&name), b).unwrap_or(-1)
+			},
+			Call::Read => match self.read(a, c.max(0) as usize) {
+				Some(bytes) => {
+					for (i, &byte) in bytes.iter().enumerate() {
+						machine.store((b as u32).wrapping_add(i as u32), 1, byte as u32)?;
+					}
+					bytes.len() as i32
+				},
+				None => -1,
+			},
+			Call::Write => {
+				let bytes = (0..c.max(0) as u32)
+					.map(|i| machine.load_memory((b as u32).wrapping_add(i), 1).map(|byte| byte as u8))
+					.collect::<Result<Vec<_>, _>>()?;
+				match self.write(a, &bytes) {
+					Some(()) => bytes.len() as i32,
+					None => -1,
+				}
+			},
+			Call::Close => match self.fs.close(a) {
+				Ok(()) => 0,
+				Err(_) => -1,
+			},
+		};
+		machine.regs[10] = ret.into();
+		Ok(SyscallOutcome::Continue)
+	}
+}
+
+#[test]
+fn test_venus() {
+	let source = r#"
+	li a0, 4
+	la a1, hello
+	ecall
+	li a0, 1
+	li a1, -42
+	ecall
+	li a0, 11
+	li a1, '\n'
+	ecall
+	li a0, 5
+	ecall
+	addi s0, a0, 1
+	li a0, 9
+	li a1, 16
+	ecall
+	mv s1, a0
+	li a0, 9
+	li a1, 0
+	ecall
+	sub s2, a0, s1
+	li a0, 17
+	li a1, 3
+	ecall
+	li s3, 1
+.data
+hello: .string "hello "
+"#;
+	let image = crate::assemble(source).unwrap();
+	let mut machine = Machine::new(1024);
+	machine.load_image(&image).unwrap();
+	let mut venus = Venus::for_image(Convention::Venus, &image);
+	venus.input.extend(b" 41\n7\n");
+	assert_eq!(machine.run_with(&mut venus), Ok(crate::StepOutcome::Exited(3)));
+	assert_eq!(venus.take_output(), b"hello -42\n");
+	assert_eq!(machine.regs[8], 42);
+	assert_eq!(machine.regs[9] as u32, (image.data_base + 7 + 7) & !7);
+	assert_eq!(machine.regs[18], 16);
+	assert_eq!(machine.regs[19], 0);
+	assert_eq!(venus.input, b"7\n");
+}
+
+#[test]
+fn test_rars_files() {
+	let source = r#"
+	# copy in.txt to out.txt and append a line to log.txt
+	li a7, 1024
+	la a0, in_name
+	li a1, 0
+	ecall
+	mv s0, a0
+	li a7, 63
+	mv a0, s0
+	la a1, buf
+	li a2, 64
+	ecall
+	mv s1, a0
+	li a7, 57
+	mv a0, s0
+	ecall
+	li a7, 1024
+	la a0, out_name
+	li a1, 1
+	ecall
+	mv s2, a0
+	li a7, 64
+	mv a0, s2
+	la a1, buf
+	mv a2, s1
+	ecall
+	li a7, 1024
+	la a0, log_name
+	li a1, 9
+	ecall
+	li a7, 64
+	la a1, in_name
+	li a2, 3
+	ecall
+	li a7, 64
+	li a0, 1
+	la a1, out_name
+	li a2, 3
+	ecall
+	# reading a missing file fails
+	li a7, 1024
+	la a0, missing
+	li a1, 0
+	ecall
+	mv s3, a0
+	li a7, 57
+	li a0, 99
+	ecall
+	mv s4, a0
+	li a7, 10
+	ecall
+.data
+in_name: .string "in.txt"
+out_name: .string "out.txt"
+log_name: .string "log.txt"
+missing: .string "nope"
+buf: .space 64
+"#;
+	let image = crate::assemble(source).unwrap();
+	let mut machine = Machine::new(1024);
+	machine.load_image(&image).unwrap();
+	let mut rars = Venus::for_image(Convention::Rars, &image);
+	rars.fs.files.insert("in.txt".into(), b"some text".to_vec());
+	rars.fs.files.insert("log.txt".into(), b"old ".to_vec());
+	assert_eq!(machine.run_with(&mut rars), Ok(crate::StepOutcome::Exited(0)));
+	assert_eq!(machine.regs[9], 9);
+	assert_eq!(rars.fs.files["out.txt"], b"some text");
+	assert_eq!(rars.fs.files["log.txt"], b"old in.");
+	assert_eq!(rars.output, b"out");
+	assert_eq!((machine.regs[19], machine.regs[20]), (-1, -1));
+}
+
+#[test]
+fn test_syscall_traps() {
+	let mut machine = Machine::new(1024);
+	machine.load_image(&crate::assemble("li a0, 4\nli a1, -4\necall").unwrap()).unwrap();
+	let mut venus = Venus::new(Convention::Venus, 512);
+	let trap = machine.run_with(&mut venus).unwrap_err();
+	assert_eq!((trap.cause, trap.pc, trap.tval), (crate::TrapCause::LoadAccessFault, 8, 0xfffffffc));
+
+	// strings stop at 64 KiB and never read devices, whose registers can change when read
+	let mut machine = Machine::new(128 * 1024);
+	machine.load_image(&crate::assemble("li a0, 4\nli a1, 0x100\necall").unwrap()).unwrap();
+	machine.load_segment(0x100, &[b'a'; 70000]).unwrap();
+	let trap = machine.run_with(&mut venus).unwrap_err();
+	assert_eq!((trap.cause, trap.tval), (crate::TrapCause::LoadAccessFault, 0x10100));
+	let mut machine = Machine::new(1024);
+	let uart = crate::Uart::new();
+	machine.bus.map(1024, crate::UART_SIZE, uart.clone());
+	uart.push_input(b"hi");
+	machine.load_image(&crate::assemble("li a0, 4\nli a1, 1024\necall").unwrap()).unwrap();
+	let trap = machine.run_with(&mut venus).unwrap_err();
+	assert_eq!((trap.cause, trap.tval), (crate::TrapCause::LoadAccessFault, 1024));
+	assert_eq!(machine.peek_bytes(1024, 1), b"h");
+
+	let mut machine = Machine::new(1024);
+	machine.load_image(&crate::assemble("li a0, 1234\necall").unwrap()).unwrap();
+	let trap = machine.run_with(&mut venus).unwrap_err();
+	assert_eq!((trap.cause, trap.pc), (crate::TrapCause::EnvironmentCall, 4));
+}
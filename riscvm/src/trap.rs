@@ -0,0 +1,79 @@
+use std::fmt;
+
+/// Why an instruction couldn't complete. The discriminants are the RISC-V `mcause` exception codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrapCause {
+	InstructionMisaligned = 0,
+	InstructionAccessFault = 1,
+	IllegalInstruction = 2,
+	Breakpoint = 3,
+	LoadMisaligned = 4,
+	LoadAccessFault = 5,
+	StoreMisaligned = 6,
+	StoreAccessFault = 7,
+	UserEnvironmentCall = 8,
+	SupervisorEnvironmentCall = 9,
+	/// An `ecall` from machine mode, which is the mode programs start in.
+	EnvironmentCall = 11,
+	InstructionPageFault = 12,
+	LoadPageFault = 13,
+	StorePageFault = 15,
+}
+
+impl TrapCause {
+	/// The value `mcause` would hold for this trap.
+	pub fn code(self) -> u32 {
+		self as u32
+	}
+
+	/// Whether this is an `ecall` from any mode.
+	pub fn is_environment_call(self) -> bool {
+		matches!(self, TrapCause::UserEnvironmentCall | TrapCause::SupervisorEnvironmentCall | TrapCause::EnvironmentCall)
+	}
+}
+
+/// An exception raised by an instruction. `pc` is the address of that instruction, which hasn't
+/// changed any registers or memory. `tval` is extra information in the same form as the `mtval`
+/// register: the faulting address for memory accesses and jumps, the instruction word for illegal
+/// instructions, the pc for breakpoints and zero otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Trap {
+	pub cause: TrapCause,
+	pub pc: u32,
+	pub tval: u32,
+}
+
+impl fmt::Display for Trap {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let (pc, tval) = (self.pc, self.tval);
+		match self.cause {
+			TrapCause::InstructionMisaligned => write!(f, "misaligned jump at pc {pc:#x} to {tval:#x}"),
+			TrapCause::InstructionAccessFault => write!(f, "instruction access fault at pc {tval:#x}"),
+			TrapCause::IllegalInstruction => write!(f, "illegal instruction {tval:#010x} at pc {pc:#x}"),
+			TrapCause::Breakpoint => write!(f, "breakpoint at pc {pc:#x}"),
+			TrapCause::LoadMisaligned => write!(f, "misaligned load at pc {pc:#x} reading {tval:#x}"),
+			TrapCause::LoadAccessFault => write!(f, "load access fault at pc {pc:#x} reading {tval:#x}"),
+			TrapCause::StoreMisaligned => write!(f, "misaligned store at pc {pc:#x} writing {tval:#x}"),
+			TrapCause::StoreAccessFault => write!(f, "store access fault at pc {pc:#x} writing {tval:#x}"),
+			TrapCause::UserEnvironmentCall => write!(f, "environment call from user mode at pc {pc:#x}"),
+			TrapCause::SupervisorEnvironmentCall => write!(f, "environment call from supervisor mode at pc {pc:#x}"),
+			TrapCause::EnvironmentCall => write!(f, "environment call at pc {pc:#x}"),
+			TrapCause::InstructionPageFault => write!(f, "instruction page fault at pc {tval:#x}"),
+			TrapCause::LoadPageFault => write!(f, "load page fault at pc {pc:#x} reading {tval:#x}"),
+			TrapCause::StorePageFault => write!(f, "store page fault at pc {pc:#x} writing {tval:#x}"),
+		}
+	}
+}
+
+impl std::error::Error for Trap {}
+
+/// What happened when the machine was stepped without trapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+	/// An instruction ran and execution carries on at the new pc.
+	Continue,
+	/// The pc is past the end of the program, so there is nothing left to run.
+	Finished,
+	/// The program asked to exit with this code.
+	Exited(i32),
+}
@@ -0,0 +1,124 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use crate::bus::{AccessFault, Bus};
+
+/// Where the UART sits in the address space of machines that have one, the same place as on QEMU's
+/// `virt` board.
+pub const UART_BASE: u32 = 0x1000_0000;
+
+/// The number of bytes of address space the UART's registers take up.
+pub const UART_SIZE: u32 = 8;
+
+/// Receive buffer when read, transmit holding register when written.
+const DATA: u32 = 0;
+/// Line status register.
+const LSR: u32 = 5;
+/// Scratch register, which just holds whatever was written to it.
+const SCR: u32 = 7;
+
+const LSR_DATA_READY: u8 = 1 << 0;
+const LSR_THR_EMPTY: u8 = 1 << 5;
+const LSR_TRANSMITTER_EMPTY: u8 = 1 << 6;
+
+#[derive(Debug, Default)]
+struct State {
+	input: VecDeque<u8>,
+	output: Vec<u8>,
+	scratch: u8,
+}
+
+/// The byte registers of a 16550 UART, minus everything about baud rates, FIFOs and interrupts.
+/// Programs poll the line status register at offset 5 and read or write bytes at offset 0; the other
+/// registers read as zero and ignore writes.
+///
+/// A `Uart` is a handle, so the host keeps a clone to feed input and drain output after mapping
+/// another into a [`MemoryMap`](crate::MemoryMap).
+#[derive(Debug, Clone, Default)]
+pub struct Uart {
+	state: Rc<RefCell<State>>,
+}
+
+impl Uart {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Queue bytes for the program to read.
+	pub fn push_input(&self, bytes: &[u8]) {
+		self.state.borrow_mut().input.extend(bytes);
+	}
+
+	/// Everything the program wrote since the last call.
+	pub fn take_output(&self) -> Vec<u8> {
+		std::mem::take(&mut self.state.borrow_mut().output)
+	}
+
+	fn line_status(state: &State) -> u8 {
+		let ready = if state.input.is_empty() { 0 } else { LSR_DATA_READY };
+		ready | LSR_THR_EMPTY | LSR_TRANSMITTER_EMPTY
+	}
+}
+
+impl Bus for Uart {
+	fn read(&mut self, addr: u32, width: usize) -> Result<u32, AccessFault> {
+		if width != 1 {
+			return Err(AccessFault);
+		}
+		let mut state = self.state.borrow_mut();
+		Ok(match addr {
+			DATA => state.input.pop_front().unwrap_or(0),
+			LSR => Self::line_status(&state),
+			SCR => state.scratch,
+			_ => 0,
+		} as u32)
+	}
+
+	fn write(&mut self, addr: u32, width: usize, val: u32) -> Result<(), AccessFault> {
+		if width != 1 {
+			return Err(AccessFault);
+		}
+		let mut state = self.state.borrow_mut();
+		match addr {
+			DATA => state.output.push(val as u8),
+			SCR => state.scratch = val as u8,
+			_ => {},
+		}
+		Ok(())
+	}
+
+	fn peek(&self, addr: u32) -> Option<u8> {
+		let state = self.state.borrow();
+		Some(match addr {
+			DATA => state.input.front().copied().unwrap_or(0),
+			LSR => Self::line_status(&state),
+			SCR => state.scratch,
+			_ => 0,
+		})
+	}
+}
+
+#[test]
+fn test_uart() {
+	let host = Uart::new();
+	let mut uart = host.clone();
+	assert_eq!(uart.read(LSR, 1), Ok(0x60));
+	assert_eq!(uart.read(DATA, 1), Ok(0));
+
+	host.push_input(b"hi");
+	assert_eq!(uart.read(LSR, 1), Ok(0x61));
+	assert_eq!(uart.peek(DATA), Some(b'h'));
+	assert_eq!(uart.read(DATA, 1), Ok(b'h' as u32));
+	assert_eq!(uart.read(DATA, 1), Ok(b'i' as u32));
+	assert_eq!(uart.read(LSR, 1), Ok(0x60));
+
+	uart.write(DATA, 1, b'o' as u32).unwrap();
+	uart.write(DATA, 1, b'k' as u32).unwrap();
+	assert_eq!(host.take_output(), b"ok");
+	assert_eq!(host.take_output(), b"");
+
+	uart.write(SCR, 1, 0xab).unwrap();
+	assert_eq!(uart.read(SCR, 1), Ok(0xab));
+	assert_eq!(uart.read(DATA, 4), Err(AccessFault));
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+/// Why a file operation failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsError {
+	NotFound,
+	BadFd,
+	/// The file wasn't opened for this kind of access.
+	NotPermitted,
+}
+
+/// How to open a file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpenOptions {
+	pub read: bool,
+	pub write: bool,
+	/// Create the file if it doesn't exist.
+	pub create: bool,
+	/// Empty the file when it's opened.
+	pub truncate: bool,
+	/// Start at the end of the file instead of the beginning.
+	pub append: bool,
+}
+
+#[derive(Debug, Clone)]
+struct OpenFile {
+	name: String,
+	pos: usize,
+	options: OpenOptions,
+}
+
+/// The files a program can see, kept in memory. The host fills `files` before the program runs and
+/// can inspect it afterwards. Nothing on the host is touched unless `host_dir` is set, and even then
+/// files there are only read, the first time a program opens them; writes stay in memory.
+#[derive(Debug, Clone)]
+pub struct FileSystem {
+	pub files: HashMap<String, Vec<u8>>,
+	pub host_dir: Option<PathBuf>,
+	open: HashMap<i32, OpenFile>,
+	next_fd: i32,
+}
+
+impl Default for FileSystem {
+	fn default() -> Self {
+		Self {
+			files: HashMap::new(),
+			host_dir: None,
+			open: HashMap::new(),
+			// leave room for standard input, output and error
+			next_fd: 3,
+		}
+	}
+}
+
+impl FileSystem {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Open the file called `name` and return its descriptor.
+	pub fn open(&mut self, name: &str, options: OpenOptions) -> Result<i32, FsError> {
+		let name = name.trim_start_matches('/').to_owned();
+		if !self.files.contains_key(&name) {
+			match self.read_host_file(&name) {
+				Some(contents) => self.files.insert(name.clone(), contents),
+				None if options.create => self.files.insert(name.clone(), Vec::new()),
+				None => return Err(FsError::NotFound),
+			};
+		}
+		let contents = self.files.get_mut(&name).ok_or(FsError::NotFound)?;
+		if options.truncate {
+			contents.clear();
+		}
+		let pos = if options.append { contents.len() } else { 0 };
+		let fd = self.next_fd;
+		self.next_fd += 1;
+		self.open.insert(fd, OpenFile { name, pos, options });
+		Ok(fd)
+	}
+
+	/// The contents of `name` in `host_dir`, as long as it's a plain relative path that can't escape it.
+	fn read_host_file(&self, name: &str) -> Option<Vec<u8>> {
+		let dir = self.host_dir.as_ref()?;
+		let path = Path::new(name);
+		if !path.components().all(|component| matches!(component, Component::Normal(_))) {
+			return None;
+		}
+		std::fs::read(dir.join(path)).ok()
+	}
+
+	pub fn close(&mut self, fd: i32) -> Result<(), FsError> {
+		self.open.remove(&fd).map(|_| ()).ok_or(FsError::BadFd)
+	}
+
+	/// Read up to `len` bytes from where `fd` is and move past them.
+	pub fn read(&mut self, fd: i32, len: usize) -> Result<Vec<u8>, FsError> {
+		let file = self.open.get_mut(&fd).ok_or(FsError::BadFd)?;
+		if !file.options.read {
+			return Err(FsError::NotPermitted);
+		}
+		// the host may have removed the file while it was open
+		let contents = self.files.get(&file.name).ok_or(FsError::NotFound)?;
+		let start = file.pos.min(contents.len());
+		let bytes = contents[start..(start + len).min(contents.len())].to_vec();
+		file.pos = start + bytes.len();
+		Ok(bytes)
+	}
+
+	/// Write `bytes` where `fd` is, growing the file if needed, and move past them.
+	pub fn write(&mut self, fd: i32, bytes: &[u8]) -> Result<(), FsError> {
+		let file = self.open.get_mut(&fd).ok_or(FsError::BadFd)?;
+		if !file.options.write {
+			return Err(FsError::NotPermitted);
+		}
+		let contents = self.files.get_mut(&file.name).ok_or(FsError::NotFound)?;
+		if file.options.append {
+			file.pos = contents.len();
+		}
+		let end = file.pos + bytes.len();
+		if contents.len() < end {
+			contents.resize(end, 0);
+		}
+		contents[file.pos..end].copy_from_slice(bytes);
+		file.pos = end;
+		Ok(())
+	}
+
+	/// The size of the file `fd` refers to.
+	pub fn size(&self, fd: i32) -> Result<usize, FsError> {
+		let file = self.open.get(&fd).ok_or(FsError::BadFd)?;
+		self.files.get(&file.name).map(Vec::len).ok_or(FsError::NotFound)
+	}
+}
+
+#[test]
+fn test_file_system() {
+	let read = OpenOptions { read: true, ..Default::default() };
+	let append = OpenOptions { write: true, create: true, append: true, ..Default::default() };
+	let mut fs = FileSystem::new();
+	fs.files.insert("a.txt".into(), b"hello".to_vec());
+
+	assert_eq!(fs.open("missing", read), Err(FsError::NotFound));
+	let fd = fs.open("/a.txt", read).unwrap();
+	assert_eq!(fd, 3);
+	assert_eq!(fs.read(fd, 3).unwrap(), b"hel");
+	assert_eq!(fs.read(fd, 10).unwrap(), b"lo");
+	assert_eq!(fs.read(fd, 10).unwrap(), b"");
+	assert_eq!(fs.write(fd, b"x"), Err(FsError::NotPermitted));
+	assert_eq!(fs.size(fd), Ok(5));
+	assert_eq!(fs.close(fd), Ok(()));
+	assert_eq!(fs.close(fd), Err(FsError::BadFd));
+
+	let fd = fs.open("a.txt", append).unwrap();
+	fs.write(fd, b", world").unwrap();
+	assert_eq!(fs.files["a.txt"], b"hello, world");
+	assert_eq!(fs.read(fd, 1), Err(FsError::NotPermitted));
+
+	// a file the host removed while it was open fails instead of panicking
+	let fd = fs.open("a.txt", OpenOptions { read: true, write: true, ..Default::default() }).unwrap();
+	fs.files.remove("a.txt");
+	assert_eq!(fs.read(fd, 1), Err(FsError::NotFound));
+	assert_eq!(fs.write(fd, b"x"), Err(FsError::NotFound));
+	assert_eq!(fs.size(fd), Err(FsError::NotFound));
+
+	// without a host directory nothing outside the in-memory files can be opened
+	assert_eq!(fs.open("Cargo.toml", read), Err(FsError::NotFound));
+	fs.host_dir = Some(env!("CARGO_MANIFEST_DIR").into());
+	let fd = fs.open("Cargo.toml", read).unwrap();
+	assert!(fs.read(fd, 9).unwrap().starts_with(b"[package]"));
+	assert_eq!(fs.open("../Cargo.toml", read), Err(FsError::NotFound));
+}